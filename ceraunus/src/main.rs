@@ -1,51 +1,112 @@
 // std
 use std::future::Future;
-use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
 // external crates
-use anyhow::Result;
-use chrono::Utc;
+use anyhow::{Context, Result};
 use console_subscriber::ConsoleLayer;
-use tokio::sync::mpsc;
+use rust_decimal::{Decimal, dec};
+use tokio::{sync::mpsc, task::JoinHandle};
 use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
-use tracing::{error, info, warn};
+use tracing::{debug, error, field::display, info, warn};
 use tracing_subscriber::{
-    Layer, Registry, filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt,
+    Layer, Registry,
+    filter::{LevelFilter, Targets},
+    fmt::{self, time::ChronoUtc},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
 };
 use url::Url;
+use uuid::Uuid;
 
 // Internal crates
 use data::{
-    binance::market::Depth,
-    binance::subscription::{AccountStream, MarketStream, StreamCommand, StreamSpec, WsSession},
+    binance::market::PartialDepth,
+    binance::response::OrderSuccessResp,
+    binance::subscription::{
+        AccountStream, MarketStream, ParseStream, RAW_FRAME_TARGET, StreamCommand, StreamSpec,
+        WsSession,
+    },
     order::{Symbol, Symbol::SOLUSDT},
+    time::LocalTime,
 };
 use trading_core::{
-    OrderBook, Result as ClientResult,
-    engine::State,
-    exchange::Client,
-    strategy::{QuoteStrategy, Strategy},
+    OrderBook, Result as ClientResult, TradingCoreError,
+    client_id::{ClientIdGenerator, ClientIdScheme},
+    engine::{DepthOutcome, SymbolStates},
+    exchange::{
+        BinanceClient, Exchange, MarketFeed, cancel_on_disconnect, check_symbols, http_client,
+    },
+    gate::RequestGate,
+    recovery::RecoveryFile,
+    report::{FeedRestarts, SessionReport},
+    retry::RetryPolicy,
+    shared::{StateObserver, state_channel},
+    strategy::{QuoteStrategy, Quotes},
+    watchdog::LoopWatchdog,
 };
 
-const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
-const HTTP_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
 const STALE_ORDER_THRESHOLD: chrono::Duration = chrono::Duration::seconds(30);
+const FEED_RESTART: RetryPolicy =
+    RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(30)).with_jitter(0.2);
+const DEFAULT_MAX_INFLIGHT_REQUESTS: usize = 8;
+const RECONCILE_GRACE: chrono::Duration = chrono::Duration::seconds(5);
+const SNAPSHOT_RETRY: RetryPolicy =
+    RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(30)).with_jitter(0.2);
+const ORDER_RETRY: RetryPolicy =
+    RetryPolicy::new(Duration::from_millis(100), Duration::from_secs(1))
+        .with_jitter(0.5)
+        .with_max_attempts(3);
+/// Largest gap between the exchange and local position accepted at shutdown
+const FINAL_POSITION_TOLERANCE: Decimal = dec!(0.000001);
+/// Handling a single event should take far less, anything above blocks the loop
+const SLOW_ITERATION: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy)]
+enum Feed {
+    Market,
+    Account,
+}
 
 #[derive(Debug)]
 enum Event {
     // websocket
     Account(AccountStream),
     Market(MarketStream),
+    FeedClosed(Feed),
+    ReconcileDone(ClientResult<(Vec<OrderSuccessResp>, Option<Decimal>)>),
+    OrderFailed(Uuid, TradingCoreError),
     // orderbook
-    SnapshotDone(ClientResult<OrderBook>),
+    SnapshotDone(Symbol, ClientResult<OrderBook>),
     // open order
     SendOrderTick,
     CancelOrderTick,
 
     ReportStateTick,
     KeepaliveTick,
+    CountdownTick,
+
+    Shutdown,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Account(_) => "Account",
+            Event::Market(_) => "Market",
+            Event::FeedClosed(_) => "FeedClosed",
+            Event::ReconcileDone(_) => "ReconcileDone",
+            Event::OrderFailed(..) => "OrderFailed",
+            Event::SnapshotDone(..) => "SnapshotDone",
+            Event::SendOrderTick => "SendOrderTick",
+            Event::CancelOrderTick => "CancelOrderTick",
+            Event::ReportStateTick => "ReportStateTick",
+            Event::KeepaliveTick => "KeepaliveTick",
+            Event::CountdownTick => "CountdownTick",
+            Event::Shutdown => "Shutdown",
+        }
+    }
 }
 
 #[tokio::main]
@@ -79,55 +140,65 @@ async fn main() -> Result<()> {
 
     let file_layer = fmt::layer()
         .with_writer(nb_file_writer)
+        .with_timer(ChronoUtc::rfc_3339())
         .with_target(false)
         .with_file(true)
         .with_line_number(true)
         .with_thread_ids(false)
         .with_ansi(false)
-        .with_filter(file_filter);
+        .with_filter(
+            Targets::new()
+                .with_target(RAW_FRAME_TARGET, LevelFilter::OFF)
+                .with_default(file_filter),
+        );
 
     let stdout_layer = fmt::layer()
         .with_writer(nb_console_writer)
+        .with_timer(ChronoUtc::rfc_3339())
         .with_target(false)
         .with_file(true)
         .with_line_number(true)
         .with_thread_ids(false)
         .compact()
         // .pretty()
-        .with_filter(console_filter);
+        .with_filter(
+            Targets::new()
+                .with_target(RAW_FRAME_TARGET, LevelFilter::OFF)
+                .with_default(console_filter),
+        );
+
+    // Unparsed frames go to their own file, keeping the full text for schema analysis
+    let (raw_layer, _guard3) = if cfg.logging.raw_log {
+        std::fs::create_dir_all(&cfg.logging.raw.dir)?;
+        let raw_appender =
+            tracing_appender::rolling::daily(&cfg.logging.raw.dir, &cfg.logging.raw.name);
+        let (nb_raw_writer, guard) = tracing_appender::non_blocking(raw_appender);
+        let layer = fmt::layer()
+            .with_writer(nb_raw_writer)
+            .with_timer(ChronoUtc::rfc_3339())
+            .with_target(false)
+            .with_ansi(false)
+            .with_filter(Targets::new().with_target(RAW_FRAME_TARGET, LevelFilter::TRACE));
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
 
     // Tokio console layer (enable/configure via env vars; see tokio-console docs)
-    let tokio_console_layer = ConsoleLayer::builder().with_default_env().spawn();
+    let tokio_console_layer = cfg
+        .logging
+        .tokio_console
+        .then(|| ConsoleLayer::builder().with_default_env().spawn());
 
     Registry::default()
         .with(stdout_layer)
         .with(file_layer)
+        .with(raw_layer)
         .with(tokio_console_layer)
         .init();
 
     // build shared http client
-    let http = reqwest::Client::builder()
-        .tcp_nodelay(true)
-        .timeout(HTTP_REQUEST_TIMEOUT)
-        .pool_idle_timeout(IDLE_TIMEOUT)
-        .build()?;
-
-    let client = Arc::new(Client::from_config(&cfg, http.clone())?);
-
-    let listen_key = client.get_listen_key().await?;
-
-    let ws_url = match cfg.account.environment {
-        data::config::Environment::Production => &cfg.exchange.ws.endpoints.production,
-        data::config::Environment::Testnet => &cfg.exchange.ws.endpoints.testnet,
-    };
-
-    let rest_url = match cfg.account.environment {
-        data::config::Environment::Production => &cfg.exchange.rest.endpoints.production,
-        data::config::Environment::Testnet => &cfg.exchange.rest.endpoints.testnet,
-    };
-
-    let mkt_url = Url::parse(ws_url)?;
-    let acct_url = Url::parse(&format!("{}/{}", ws_url, listen_key))?;
+    let http = http_client(&cfg.exchange.rest)?;
 
     let ws_config = WebSocketConfig::default()
         .write_buffer_size(0)
@@ -135,75 +206,245 @@ async fn main() -> Result<()> {
         .max_message_size(Some(512 * 1024))
         .max_frame_size(Some(256 * 1024));
 
-    let (cmd_tx, cmd_rx) = mpsc::channel(32);
-    let (evt_tx, mut evt_rx) = mpsc::channel(1024);
-    let (acct_cmd_tx, acct_cmd_rx) = mpsc::channel(32);
-    let (acct_evt_tx, mut acct_evt_rx) = mpsc::channel(1024);
+    let client = Arc::new(BinanceClient::from_config(&cfg, http)?.with_ws_config(ws_config));
+    let gate = RequestGate::new(
+        cfg.exchange
+            .max_inflight_requests
+            .unwrap_or(DEFAULT_MAX_INFLIGHT_REQUESTS),
+    );
 
-    let ws = WsSession::market(mkt_url, ws_config, cmd_rx, evt_tx);
-    let acct_ws = WsSession::account(acct_url, ws_config, acct_cmd_rx, acct_evt_tx);
+    // fail before subscribing anything on a symbol that cannot be traded
+    let exchange_info = client.get_exchange_info().await?;
+    check_symbols(&exchange_info, &cfg.exchange.symbols)?;
 
-    ws.spawn_named("ws.market.session");
-    acct_ws.spawn_named("ws.account.session");
+    let trading = cfg.mode.trading;
 
-    cmd_tx
-        .send(StreamCommand::Subscribe(vec![
-            StreamSpec::Depth {
-                symbol: SOLUSDT,
-                levels: None,
-                interval_ms: None,
-            },
-            StreamSpec::BookTicker { symbol: SOLUSDT },
-        ]))
-        .await?;
+    // the client already resolved the endpoints of the configured environment
+    let ws_url = client.market_ws().clone();
+    let rest_url = client.endpoint().to_string();
+
+    let acct_url = if trading {
+        let listen_key = client.get_listen_key().await?;
+        Some(Url::parse(&format!("{}/{}", ws_url, listen_key))?)
+    } else {
+        warn!("Trading disabled, running the market feed only");
+        None
+    };
+
+    let ws_idle_timeout = cfg.exchange.ws.idle_timeout_ms.map(Duration::from_millis);
+    // a partial depth frame is a whole book on its own, no REST snapshot is ever taken then
+    let (partial_book, levels, interval_ms) = match cfg.exchange.ws.book {
+        data::config::BookSource::SnapshotDiff => (false, None, None),
+        data::config::BookSource::PartialStream {
+            levels,
+            interval_ms,
+        } => (true, Some(levels), interval_ms),
+    };
+    let mut market_specs: Vec<StreamSpec> = cfg
+        .exchange
+        .symbols
+        .iter()
+        .map(|&symbol| StreamSpec::Depth {
+            symbol,
+            levels,
+            interval_ms,
+        })
+        .collect();
+    // one all-symbols stream replaces the per-symbol ones, tickers are routed by symbol
+    if cfg.exchange.ws.all_book_ticker {
+        market_specs.push(StreamSpec::AllBookTicker);
+    } else {
+        market_specs.extend(
+            cfg.exchange
+                .symbols
+                .iter()
+                .map(|&symbol| StreamSpec::BookTicker { symbol }),
+        );
+    }
+    // quotes are checked against the PERCENT_PRICE band around the mark price
+    market_specs.extend(
+        cfg.exchange
+            .symbols
+            .iter()
+            .map(|&symbol| StreamSpec::MarkPrice { symbol }),
+    );
+    if let Some(trades) = cfg.exchange.ws.trades {
+        market_specs.extend(cfg.exchange.symbols.iter().map(|&symbol| match trades {
+            data::config::TradeStream::AggTrade => StreamSpec::AggTrade { symbol },
+            data::config::TradeStream::Trade => StreamSpec::Trade { symbol },
+        }));
+    }
+    let account_specs = vec![StreamSpec::OrderTradeUpdate, StreamSpec::TradeLite];
 
-    acct_cmd_tx
-        .send(StreamCommand::Subscribe(vec![
-            StreamSpec::OrderTradeUpdate,
-            // StreamSpec::TradeLite,
-        ]))
+    let (mut acct_cmd_tx, acct_cmd_rx) = mpsc::channel(32);
+    let (acct_evt_tx, mut acct_evt_rx) = mpsc::channel(1024);
+    let (reconcile_tx, mut reconcile_rx) = mpsc::channel(1);
+    let (order_fail_tx, mut order_fail_rx) = mpsc::channel(64);
+    let (snapshot_tx, mut snapshot_rx) = mpsc::channel(16);
+
+    let MarketFeed {
+        commands: mut cmd_tx,
+        events: mut evt_rx,
+    } = client
+        .subscribe(market_specs.clone(), Duration::ZERO)
         .await?;
 
+    // without a session the account channels stay closed, their arms are disabled below
+    if let Some(acct_url) = &acct_url {
+        WsSession::account(acct_url.clone(), ws_config, acct_cmd_rx, acct_evt_tx)
+            .with_idle_timeout(ws_idle_timeout)
+            .spawn_named("ws.account.session");
+        acct_cmd_tx
+            .send(StreamCommand::Subscribe(account_specs.clone()))
+            .await?;
+    }
+
     info!("----------INITILIAZATION FINISHED----------");
 
-    let mut state: State = State::new(SOLUSDT);
+    let mut states = SymbolStates::new(&cfg.exchange.symbols);
+    for state in states.iter_mut() {
+        state.risk = cfg.risk.clone();
+        state.strategy = cfg.strategy.clone();
+        state.client_ids =
+            ClientIdGenerator::new(ClientIdScheme::from_tag(cfg.strategy.client_id_tag));
+        state.set_percent_price(exchange_info.percent_price(state.symbol));
+    }
+    let strategy = cfg
+        .strategy
+        .inventory_skew
+        .map_or_else(QuoteStrategy::default, |skew| {
+            QuoteStrategy::with_params(skew.half_spread, skew.skew_per_unit, skew.size)
+        });
+    // account updates only carry the assets that changed, start from the whole wallet
+    if trading {
+        states.seed_balances(&client.get_balance().await?);
+    }
+    // TODO: route events by symbol, the loop below still drives a single symbol
+    states
+        .get(SOLUSDT)
+        .context("SOLUSDT is missing from exchange.symbols")?;
+
+    // without trading there are no orders or position worth keeping
+    let recovery_path = cfg.recovery.state_path.as_deref().filter(|_| trading);
+    if let Some(path) = recovery_path {
+        match RecoveryFile::load(path) {
+            Ok(Some(file)) => {
+                info!(%path, written_at = file.written_at, "Resuming from recovery file");
+                file.restore(&mut states)?;
+                // the file may be long out of date, the exchange has the last word
+                let client = Arc::clone(&client);
+                let reconcile_tx = reconcile_tx.clone();
+                spawn_named("account.reconcile", async move {
+                    let res = tokio::try_join!(
+                        client.get_open_orders(Some(SOLUSDT)),
+                        client.get_position(SOLUSDT),
+                    );
+                    let _ = reconcile_tx.send(res).await;
+                });
+            }
+            Ok(None) => info!(%path, "No recovery file, starting flat"),
+            Err(err) => warn!(%err, %path, "Recovery file unreadable, starting flat"),
+        }
+    }
 
-    let mut depth_buffer: Vec<Depth> = Vec::with_capacity(8);
-    let mut snapshot_fut = snapshot_task(
-        SOLUSDT,
-        http.clone(),
-        1000,
-        Duration::from_millis(1000),
-        rest_url.clone(),
-    );
+    // every symbol recovers its own book, a gap on one leaves the others quoting
+    if !partial_book {
+        for state in states.iter_mut() {
+            if state.request_snapshot() {
+                spawn_snapshot(
+                    Arc::clone(&client),
+                    state.symbol,
+                    Duration::from_millis(1000),
+                    snapshot_tx.clone(),
+                );
+            }
+        }
+    }
+
+    // consecutive failures, drive the retry delays
+    let feed_restart = match cfg.exchange.ws.max_restarts {
+        Some(max_restarts) => FEED_RESTART.with_max_attempts(max_restarts),
+        None => FEED_RESTART,
+    };
+    let mut market_feed = FeedRestarts::new("market", feed_restart);
+    let mut account_feed = FeedRestarts::new("account", feed_restart);
     let mut keepalive_interval = tokio::time::interval(Duration::from_secs(50 * 60));
+    // refresh the dead man's switch well within its window, a single late request must not trip it
+    let countdown_ms = cfg.risk.countdown_cancel_ms.filter(|_| trading);
+    let mut countdown_interval = tokio::time::interval(
+        Duration::from_millis(countdown_ms.unwrap_or(60_000) / 3).max(Duration::from_millis(1)),
+    );
     let mut send_order_interval = tokio::time::interval(Duration::from_secs(10));
     let mut cancel_order_interval = tokio::time::interval(Duration::from_secs(60));
     let mut report_state_interval = tokio::time::interval(Duration::from_secs(60));
+    let mut watchdog = LoopWatchdog::new(SLOW_ITERATION);
+    let (state_publisher, state_observer) = state_channel();
+    let started_at = states
+        .get(SOLUSDT)
+        .expect("SOLUSDT state is checked at startup")
+        .start_time();
+    spawn_named("report.summary", log_summaries(state_observer, started_at));
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
 
     // MAIN EVENT LOOP
     loop {
+        let state = states
+            .get_mut(SOLUSDT)
+            .expect("SOLUSDT state is checked at startup");
+        // an IP ban covers every REST request, only the websocket feeds keep running
+        let rest_ok = client.banned_for().is_none();
+        // quoting blind is worse than not quoting, a dead feed stops it for good
+        let feeds_up = !market_feed.is_down() && !account_feed.is_down();
         let event = tokio::select! {
             biased;
 
-            Some(event) = evt_rx.recv() => Event::Market(event),
+            _ = &mut shutdown => Event::Shutdown,
+
+            // a closed channel means the session is gone, `recv` would return `None` forever
+            // a feed given up stays closed, polling it would spin on `None`
+            maybe_event = evt_rx.recv(), if !market_feed.is_down() => match maybe_event {
+                Some(event) => {
+                    market_feed.on_event();
+                    Event::Market(event)
+                }
+                None => Event::FeedClosed(Feed::Market),
+            },
+
+            maybe_acct_event = acct_evt_rx.recv(), if trading && !account_feed.is_down() => match maybe_acct_event {
+                Some(acct_event) => {
+                    account_feed.on_event();
+                    Event::Account(acct_event)
+                }
+                None => Event::FeedClosed(Feed::Account),
+            },
+
+            Some(res) = reconcile_rx.recv() => Event::ReconcileDone(res),
 
-            Some(acct_event) = acct_evt_rx.recv() => Event::Account(acct_event),
+            Some((client_id, err)) = order_fail_rx.recv() => Event::OrderFailed(client_id, err),
 
             _ = report_state_interval.tick() => Event::ReportStateTick,
 
-            _ = send_order_interval.tick(), if state.has_order_book() => Event::SendOrderTick,
+            _ = send_order_interval.tick(), if trading && rest_ok && feeds_up && state.has_order_book() && !state.is_book_resuming() => Event::SendOrderTick,
+
+            _ = cancel_order_interval.tick(), if trading && rest_ok => Event::CancelOrderTick,
 
-            _ = cancel_order_interval.tick() => Event::CancelOrderTick,
+            Some((symbol, res)) = snapshot_rx.recv() => Event::SnapshotDone(symbol, res),
 
-            snapshot_res = &mut snapshot_fut, if !state.has_order_book() => Event::SnapshotDone(snapshot_res),
+            _ = keepalive_interval.tick(), if trading && rest_ok => Event::KeepaliveTick,
 
-            _ = keepalive_interval.tick() => Event::KeepaliveTick,
+            _ = countdown_interval.tick(), if rest_ok && countdown_ms.is_some() => Event::CountdownTick,
         };
 
+        let _iteration = watchdog.start(event.name());
         match event {
             Event::Account(acct_event) => match acct_event {
                 AccountStream::OrderTradeUpdate(update_event) => {
+                    if !update_event.last_filled_qty().is_zero() {
+                        state
+                            .trade_latency
+                            .on_order_update(update_event.trade_id(), LocalTime::now());
+                    }
                     if let Err(err) = state.on_update_received(&update_event) {
                         error!(
                             %err,
@@ -215,13 +456,30 @@ async fn main() -> Result<()> {
                             "Failed to process order update"
                         );
                     }
+                    // same conditions as the send tick, a skipped re-quote waits for the tick
+                    if rest_ok && feeds_up && state.has_order_book() && !state.is_book_resuming() {
+                        if let Some(quotes) = state.requote_after_fill(&strategy, LocalTime::now())
+                        {
+                            spawn_send_orders(
+                                Arc::clone(&client),
+                                gate.clone(),
+                                order_fail_tx.clone(),
+                                quotes,
+                            );
+                        }
+                    }
                 }
                 AccountStream::TradeLite(trade_lite) => {
+                    state
+                        .trade_latency
+                        .on_trade_lite(trade_lite.trade_id(), LocalTime::now());
                     trade_lite.log();
                 }
                 AccountStream::AccountUpdate(update_event) => {
+                    states.on_account_update(&update_event);
                     info!(
                         reason = %update_event.reason(),
+                        balance = ?states.get(SOLUSDT).and_then(|state| state.available_balance()),
                         "Account update received"
                     );
                 }
@@ -230,69 +488,204 @@ async fn main() -> Result<()> {
 
             Event::Market(event) => match event {
                 MarketStream::Depth(depth) => {
-                    if let Some(ob) = &mut state.order_book {
-                        if (depth.last_final_update_id()..=depth.final_update_id())
-                            .contains(&ob.last_update_id())
-                        {
-                            // TODO: recheck the gap-detection logic here
-                            ob.extend(depth);
-                            if ob.get_bbo() != state.bbo_level {
-                                warn!(
-                                    ob_bbo = ?ob.get_bbo(),
-                                    bbo = ?state.bbo_level,
-                                    "Orderbook and BBO level do not match"
-                                )
-                            }
-                        } else {
-                            warn!(
-                                last_final_update_id = %depth.last_final_update_id(),
-                                first_update_id = %depth.first_update_id(),
-                                final_update_id = %depth.final_update_id(),
-                                "Gap detected in depth updates"
-                            );
-                            state.remove_order_book();
-                            snapshot_fut = snapshot_task(
-                                SOLUSDT,
-                                http.clone(),
-                                1000,
-                                Duration::from_millis(1000),
-                                rest_url.clone(),
-                            );
-                        }
-                    } else {
-                        // Order book not constructed yet
-                        depth_buffer.push(depth);
-                        info!(buffer_size=%&depth_buffer.len(), "Depth pushed to buffer");
+                    let Some(state) = states.get_mut(depth.symbol()) else {
+                        debug!(symbol = %depth.symbol(), "Depth of an untraded symbol dropped");
+                        continue;
+                    };
+                    if partial_book {
+                        state.on_partial_depth(PartialDepth::from(depth));
+                    } else if state.on_depth(depth) == DepthOutcome::GapDetected
+                        && state.request_snapshot()
+                    {
+                        spawn_snapshot(
+                            Arc::clone(&client),
+                            state.symbol,
+                            Duration::from_millis(1000),
+                            snapshot_tx.clone(),
+                        );
                     }
                 }
                 MarketStream::BookTicker(book_ticker) => {
-                    state.on_book_ticker_received(book_ticker);
+                    let symbol = book_ticker.symbol();
+                    if !states.on_book_ticker_received(book_ticker) {
+                        debug!(%symbol, "Book ticker of an untraded symbol dropped");
+                    }
+                }
+                MarketStream::MarkPrice(mark_price) => {
+                    if let Some(state) = states.get_mut(mark_price.symbol()) {
+                        state.on_mark_price(&mark_price);
+                    }
+                }
+                MarketStream::AggTrade(trade) => {
+                    if let Some(state) = states.get_mut(trade.symbol()) {
+                        state.on_trade(
+                            trade.transaction_time(),
+                            trade.price(),
+                            trade.quantity(),
+                            trade.aggressor(),
+                        );
+                    }
+                }
+                MarketStream::Trade(trade) => {
+                    if let Some(state) = states.get_mut(trade.symbol()) {
+                        state.on_trade(
+                            trade.transaction_time(),
+                            trade.price(),
+                            trade.quantity(),
+                            trade.aggressor(),
+                        );
+                    }
+                }
+                MarketStream::Untracked | MarketStream::Raw(_) => {}
+            },
+
+            Event::FeedClosed(Feed::Market) => {
+                // only the first close of an outage, the restarts that follow change nothing
+                if market_feed.restarts() == 0 && trading && rest_ok {
+                    spawn_disconnect_cancel(
+                        Arc::clone(&client),
+                        &cfg.exchange.symbols,
+                        cfg.risk.cancel_on_disconnect,
+                    );
+                }
+                let Some(delay) = market_feed.on_closed(LocalTime::now()) else {
+                    // nothing to quote against anymore, pull the orders whatever the policy
+                    if trading && rest_ok {
+                        spawn_disconnect_cancel(Arc::clone(&client), &cfg.exchange.symbols, true);
+                    }
+                    for state in states.iter_mut() {
+                        state.remove_order_book();
+                    }
+                    continue;
+                };
+                error!(restart_in = ?delay, "Market feed closed, restarting session");
+                let feed = client.subscribe(market_specs.clone(), delay).await?;
+                // make sure the old session is gone before replacing its handle
+                let _ = cmd_tx.try_send(StreamCommand::Shutdown);
+                cmd_tx = feed.commands;
+                evt_rx = feed.events;
+
+                // depth updates may have been missed while the feed was down, a kept book is
+                // dropped by the first update that does not chain
+                for state in states.iter_mut() {
+                    if cfg.exchange.ws.warm_reconnect && state.resume_order_book() {
+                        continue;
+                    }
+                    state.remove_order_book();
+                    if !partial_book && state.request_snapshot() {
+                        spawn_snapshot(
+                            Arc::clone(&client),
+                            state.symbol,
+                            Duration::from_millis(1000),
+                            snapshot_tx.clone(),
+                        );
+                    }
+                }
+            }
+
+            Event::FeedClosed(Feed::Account) => {
+                // the account arm is only polled when trading, which always has an url
+                let Some(acct_url) = &acct_url else {
+                    continue;
+                };
+                if account_feed.restarts() == 0 && rest_ok {
+                    spawn_disconnect_cancel(
+                        Arc::clone(&client),
+                        &cfg.exchange.symbols,
+                        cfg.risk.cancel_on_disconnect,
+                    );
+                }
+                let Some(delay) = account_feed.on_closed(LocalTime::now()) else {
+                    // fills can no longer be seen, pull the orders whatever the policy
+                    if rest_ok {
+                        spawn_disconnect_cancel(Arc::clone(&client), &cfg.exchange.symbols, true);
+                    }
+                    continue;
+                };
+                error!(restart_in = ?delay, "Account feed closed, restarting session");
+                let (new_cmd_tx, new_cmd_rx) = mpsc::channel(32);
+                let (new_evt_tx, new_evt_rx) = mpsc::channel(1024);
+                WsSession::account(acct_url.clone(), ws_config, new_cmd_rx, new_evt_tx)
+                    .with_start_delay(delay)
+                    .with_idle_timeout(ws_idle_timeout)
+                    .spawn_named("ws.account.session");
+                new_cmd_tx
+                    .send(StreamCommand::Subscribe(account_specs.clone()))
+                    .await?;
+                let _ = acct_cmd_tx.try_send(StreamCommand::Shutdown);
+                acct_cmd_tx = new_cmd_tx;
+                acct_evt_rx = new_evt_rx;
+
+                // order updates sent during the gap are lost, ask the exchange instead
+                let client = Arc::clone(&client);
+                let reconcile_tx = reconcile_tx.clone();
+                spawn_named("account.reconcile", async move {
+                    tokio::time::sleep(delay).await;
+                    let res = tokio::try_join!(
+                        client.get_open_orders(Some(SOLUSDT)),
+                        client.get_position(SOLUSDT),
+                    );
+                    let _ = reconcile_tx.send(res).await;
+                });
+            }
+
+            Event::ReconcileDone(res) => match res {
+                Ok((open_orders, position)) => {
+                    let removed =
+                        state.reconcile_after_gap(&open_orders, position, RECONCILE_GRACE);
+                    info!(removed = removed.len(), "Account reconciled after gap");
                 }
-                MarketStream::AggTrade(_) | MarketStream::Trade(_) | MarketStream::Raw(_) => {}
+                Err(err) => error!(%err, "Account reconciliation failed"),
             },
 
-            Event::SnapshotDone(snapshot_res) => {
-                let mut ob = snapshot_res?;
+            Event::OrderFailed(client_id, err) => {
+                if err.is_reduce_only_rejected() {
+                    state.on_reduce_only_rejected(client_id);
+                } else {
+                    // TODO: complete the order
+                    warn!(%client_id, %err, "Open order failed");
+                }
+            }
 
-                for depth in depth_buffer.drain(..) {
-                    if depth.final_update_id() < ob.last_update_id() {
-                        continue; // too old
-                    } else {
-                        // TODO: we don't check U <= lastUpdateId AND u >= lastUpdateId here
-                        ob.extend(depth);
+            Event::SnapshotDone(symbol, snapshot_res) => {
+                let Some(state) = states.get_mut(symbol) else {
+                    continue;
+                };
+                match snapshot_res {
+                    Ok(ob) => {
+                        // a snapshot the buffered updates do not chain onto is retried like a failure
+                        if state.apply_buffered(ob).is_err() {
+                            let delay = SNAPSHOT_RETRY.delay(state.on_snapshot_failed());
+                            spawn_snapshot(Arc::clone(&client), symbol, delay, snapshot_tx.clone());
+                        }
+                    }
+                    Err(err) => {
+                        let attempt = state.on_snapshot_failed();
+                        let err = anyhow::Error::from(err).context(format!(
+                            "Order book snapshot of {} from {} failed, attempt {}",
+                            symbol,
+                            rest_url,
+                            attempt + 1
+                        ));
+                        let delay = SNAPSHOT_RETRY.delay(attempt);
+                        error!(retry_in = ?delay, "{err:#}");
+                        spawn_snapshot(Arc::clone(&client), symbol, delay, snapshot_tx.clone());
                     }
                 }
-                info!(last_update_id=%ob.last_update_id(), "Order book ready");
-                state.order_book = Some(ob);
             }
 
             Event::CancelOrderTick => {
                 let stale_ids = state.stale_order_ids(STALE_ORDER_THRESHOLD);
+                if stale_ids.is_empty() {
+                    continue;
+                }
 
-                for stale_id in stale_ids {
-                    let client = Arc::clone(&client);
-                    tokio::spawn(async move {
-                        match client.cancel_order(SOLUSDT, stale_id).await {
+                let client = Arc::clone(&client);
+                let gate = gate.clone();
+                spawn_named("order.cancel", async move {
+                    let results = gate.run(client.cancel_orders(SOLUSDT, &stale_ids)).await;
+                    for (stale_id, result) in stale_ids.iter().zip(results) {
+                        match result {
                             Ok(cancel) => {
                                 info!(
                                     symbol=%cancel.symbol(),
@@ -306,72 +699,254 @@ async fn main() -> Result<()> {
                                 error!(%err, %stale_id, "Cancel stale order failed");
                             }
                         }
-                    });
-                }
+                    }
+                });
             }
 
             Event::SendOrderTick => {
-                let quotes = QuoteStrategy::generate_quotes(SOLUSDT, &state);
-                state.register_orders(&quotes);
-                let client = Arc::clone(&client);
-                tokio::spawn(async move {
-                    let results = client.open_orders(&quotes).await;
-
-                    for result in results {
-                        match result {
-                            Ok(success) => info!(
-                                symbol=%success.symbol(),
-                                price=%success.price(),
-                                client_order_id=%success.client_order_id(),
-                                order_id=%success.order_id(),
-                                "Open order ACK"
-                            ),
-                            Err(err) => {
-                                // TODO: complete the order
-                                warn!(%err, "Open order failed");
-                            }
-                        }
-                    }
-                });
+                let now = LocalTime::now();
+                let mut quotes = state.make_quotes(&strategy, now, false);
+                quotes.extend(state.make_probe(now));
+                spawn_send_orders(
+                    Arc::clone(&client),
+                    gate.clone(),
+                    order_fail_tx.clone(),
+                    quotes,
+                );
             }
 
             Event::ReportStateTick => {
+                let evicted = state.trade_latency.evict(LocalTime::now());
+                let latency = state.trade_latency.stats();
+                info!(
+                    trades = latency.count(),
+                    mean = ?latency.mean(),
+                    min = ?latency.min(),
+                    max = ?latency.max(),
+                    unpaired = evicted,
+                    "TradeLite lead over order update"
+                );
+                let placement = state.placement_latency();
                 info!(
-                    elapsed = %(Utc::now() - state.start_time()),
-                    turnover = %state.turnover(),
-                    curr_pos = %state.get_position(),
-                    exec_pnl = %state.pnl.execution_pnl(),
-                    unrealized_pnl = %state.pnl.unrealized_pnl(),
-                    realized_pnl = %state.pnl.realized_pnl(),
-                    ob = ?state.order_book.as_ref().map(|ob| ob.show(5)),
-                    "Trading Summary"
+                    orders = placement.count(),
+                    mean = ?placement.mean(),
+                    min = ?placement.min(),
+                    max = ?placement.max(),
+                    "Order placement to first update"
                 );
+                let probes = state.probe_stats();
+                if probes.sent() > 0 {
+                    info!(
+                        sent = probes.sent(),
+                        filled = probes.filled(),
+                        missed = probes.missed(),
+                        mean = ?probes.latency().mean(),
+                        max = ?probes.latency().max(),
+                        "IOC probes"
+                    );
+                }
+                // the summary is formatted by the report task, the loop only takes the view
+                state_publisher.publish(&states);
+
+                if let Some(path) = &cfg.report.health_path {
+                    let report = SessionReport::new(
+                        states.iter(),
+                        trading,
+                        market_feed
+                            .health()
+                            .with_parse_failures(MarketStream::parse_failure_count()),
+                        trading.then(|| {
+                            account_feed
+                                .health()
+                                .with_parse_failures(AccountStream::parse_failure_count())
+                        }),
+                    );
+                    if let Err(err) = report.write_atomic(path) {
+                        warn!(%err, %path, "Health report write failed");
+                    }
+                }
+                if let Some(path) = recovery_path {
+                    if let Err(err) = RecoveryFile::new(&states).write_atomic(path) {
+                        warn!(%err, %path, "Recovery file write failed");
+                    }
+                }
             }
 
             Event::KeepaliveTick => {
                 let client = Arc::clone(&client);
-                tokio::spawn(async move {
+                spawn_named("listenkey.keepalive", async move {
                     match client.keepalive_listen_key().await {
                         Ok(key) => info!(listen_key=%key, "Listen key keepalive sent"),
                         Err(err) => error!(%err, "Listen key keepalive failed"),
                     }
                 });
             }
+
+            Event::CountdownTick => {
+                let Some(countdown_ms) = countdown_ms else {
+                    continue;
+                };
+                let client = Arc::clone(&client);
+                spawn_named("order.countdown", async move {
+                    if let Err(err) = client.set_countdown_cancel(SOLUSDT, countdown_ms).await {
+                        error!(%err, countdown_ms, "Countdown cancel refresh failed");
+                    }
+                });
+            }
+
+            Event::Shutdown => {
+                info!("Shutdown requested");
+                if trading {
+                    // cancel first, a fill racing the position query would fake a mismatch
+                    if let Err(err) = client.cancel_all_orders(SOLUSDT).await {
+                        error!(%err, "Cancel all orders at shutdown failed");
+                    }
+                    match client.get_position(SOLUSDT).await {
+                        Ok(position) => {
+                            state.check_final_position(position, FINAL_POSITION_TOLERANCE);
+                        }
+                        Err(err) => error!(%err, "Final position query failed"),
+                    }
+                }
+
+                let report = SessionReport::new(
+                    std::iter::once(&*state),
+                    trading,
+                    market_feed
+                        .health()
+                        .with_parse_failures(MarketStream::parse_failure_count()),
+                    trading.then(|| {
+                        account_feed
+                            .health()
+                            .with_parse_failures(AccountStream::parse_failure_count())
+                    }),
+                );
+                match serde_json::to_string(&report) {
+                    Ok(json) => info!(report = %json, "Final session report"),
+                    Err(err) => warn!(%err, "Final session report serialization failed"),
+                }
+                if let Some(path) = &cfg.report.health_path {
+                    if let Err(err) = report.write_atomic(path) {
+                        warn!(%err, %path, "Health report write failed");
+                    }
+                }
+                if let Some(path) = recovery_path {
+                    if let Err(err) = RecoveryFile::new(&states).write_atomic(path) {
+                        warn!(%err, %path, "Recovery file write failed");
+                    }
+                }
+                break;
+            }
         }
     }
+
+    Ok(())
+}
+
+/// Send the quotes off the event loop, failures come back on `order_fail_tx`
+fn spawn_send_orders(
+    client: Arc<BinanceClient>,
+    gate: RequestGate,
+    order_fail_tx: mpsc::Sender<(Uuid, TradingCoreError)>,
+    quotes: Quotes,
+) {
+    if quotes.is_empty() {
+        return;
+    }
+    spawn_named("order.send", async move {
+        let results = client.open_orders(&quotes, &ORDER_RETRY, &gate).await;
+
+        for (order, result) in quotes.iter().zip(results) {
+            match result {
+                Ok(success) => info!(
+                    symbol=%success.symbol(),
+                    price=%success.price(),
+                    client_order_id=%success.client_order_id(),
+                    order_id=%success.order_id(),
+                    "Open order ACK"
+                ),
+                Err(err) => {
+                    let _ = order_fail_tx.send((order.client_order_id(), err)).await;
+                }
+            }
+        }
+    });
 }
 
-fn snapshot_task(
+/// Fetch the order book snapshot of one symbol after `delay`, the result comes back tagged with
+/// its symbol
+fn spawn_snapshot<X: Exchange + 'static>(
+    exchange: Arc<X>,
     symbol: Symbol,
-    http: reqwest::Client,
-    depth: u16,
     delay: Duration,
-    rest_endpoint: String,
-) -> Pin<Box<dyn Future<Output = ClientResult<OrderBook>> + Send>> {
-    Box::pin(async move {
+    tx: mpsc::Sender<(Symbol, ClientResult<OrderBook>)>,
+) {
+    spawn_named("book.snapshot", async move {
         if !delay.is_zero() {
             tokio::time::sleep(delay).await;
         }
-        OrderBook::from_snapshot(symbol, depth, &rest_endpoint, http).await
-    })
+        let res = exchange.fetch_snapshot(symbol, 1000).await;
+        let _ = tx.send((symbol, res)).await;
+    });
+}
+
+/// Log a trading summary per symbol for every published view, off the event loop
+async fn log_summaries(mut observer: StateObserver, started_at: LocalTime) {
+    while let Some(view) = observer.next().await {
+        let elapsed = format_duration(LocalTime::now() - started_at);
+        for report in &view.symbols {
+            info!(
+                symbol = %report.symbol,
+                %elapsed,
+                turnover = %report.turnover,
+                curr_pos = %report.position,
+                exec_pnl = %report.execution_pnl,
+                fees = %report.fees,
+                rebates = %report.rebates,
+                unrealized_pnl = %report.unrealized_pnl,
+                realized_pnl = %report.realized_pnl,
+                book = ?report.book,
+                ob = report.top.as_ref().map(display),
+                buy_flow = %report.buy_flow,
+                sell_flow = %report.sell_flow,
+                net_flow = %(report.buy_flow - report.sell_flow),
+                "Trading Summary"
+            );
+        }
+    }
+}
+
+/// Cancel every resting order in the background if `cancel` is set, see [`cancel_on_disconnect`]
+fn spawn_disconnect_cancel<X: Exchange + 'static>(
+    exchange: Arc<X>,
+    symbols: &[Symbol],
+    cancel: bool,
+) {
+    if !cancel {
+        return;
+    }
+    let symbols = symbols.to_vec();
+    spawn_named("order.cancel_all", async move {
+        match cancel_on_disconnect(&*exchange, &symbols, cancel).await {
+            Ok(_) => warn!(?symbols, "Feed disconnected, all orders cancelled"),
+            Err(err) => error!(%err, "Cancel all orders after disconnect failed"),
+        }
+    });
+}
+
+fn spawn_named<F>(name: &'static str, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(fut)
+        .unwrap_or_else(|_| panic!("Failed to spawn task {}", name))
+}
+
+/// Format a duration as `2h13m05s`
+fn format_duration(elapsed: chrono::Duration) -> String {
+    let secs = elapsed.num_seconds().max(0);
+    format!("{}h{:02}m{:02}s", secs / 3600, secs % 3600 / 60, secs % 60)
 }
@@ -0,0 +1,130 @@
+#![allow(dead_code)]
+use chrono::{DateTime, Utc};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use data::binance::market::{Depth, Level};
+use serde::Deserialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// The `Depth` model before levels were kept inline.
+#[derive(Debug, Deserialize)]
+struct VecDepth {
+    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
+    event_time: DateTime<Utc>,
+    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    transaction_time: DateTime<Utc>,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "pu")]
+    last_final_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<Level>,
+    #[serde(rename = "a")]
+    asks: Vec<Level>,
+}
+
+// Levels touched per side by consecutive `@depth@100ms` frames, mostly small with the odd burst.
+const LEVELS_PER_FRAME: [usize; 16] = [1, 2, 1, 3, 0, 2, 1, 4, 2, 1, 6, 1, 3, 2, 1, 14];
+
+fn levels_json(n: usize, base: u32) -> String {
+    let levels: Vec<String> = (0..n)
+        .map(|i| {
+            format!(
+                r#"["{}.{:02}","{}.{:03}"]"#,
+                base + i as u32,
+                i,
+                i + 1,
+                i * 7
+            )
+        })
+        .collect();
+    format!("[{}]", levels.join(","))
+}
+
+fn diff_stream() -> Vec<String> {
+    LEVELS_PER_FRAME
+        .iter()
+        .enumerate()
+        .map(|(i, &n)| {
+            let u = 390_497_796 + i as u64 * 10;
+            format!(
+                r#"{{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"BTCUSDT","U":{},"u":{},"pu":{},"b":{},"a":{}}}"#,
+                u,
+                u + 9,
+                u - 1,
+                levels_json(n, 7400),
+                levels_json(LEVELS_PER_FRAME[(i + 5) % LEVELS_PER_FRAME.len()], 7405),
+            )
+        })
+        .collect()
+}
+
+fn allocations_per_frame<T: for<'de> Deserialize<'de>>(frames: &[String]) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for frame in frames {
+        black_box(serde_json::from_str::<T>(frame).expect("depth parse failed"));
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / frames.len() as f64
+}
+
+fn bench_depth_levels(c: &mut Criterion) {
+    let frames = diff_stream();
+
+    println!(
+        "allocations per frame: vec {:.2}, inline {:.2}",
+        allocations_per_frame::<VecDepth>(&frames),
+        allocations_per_frame::<Depth>(&frames),
+    );
+
+    let mut group = c.benchmark_group("depth_levels");
+    group.throughput(Throughput::Elements(frames.len() as u64));
+    group.bench_with_input(
+        BenchmarkId::new("vec", "diff_stream"),
+        &frames,
+        |b, frames| {
+            b.iter(|| {
+                for frame in frames {
+                    black_box(serde_json::from_str::<VecDepth>(frame).expect("depth parse failed"));
+                }
+            });
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("inline", "diff_stream"),
+        &frames,
+        |b, frames| {
+            b.iter(|| {
+                for frame in frames {
+                    black_box(serde_json::from_str::<Depth>(frame).expect("depth parse failed"));
+                }
+            });
+        },
+    );
+    group.finish();
+}
+
+criterion_group!(benches, bench_depth_levels);
+criterion_main!(benches);
@@ -1,5 +1,5 @@
 use crate::order::*;
-use chrono::{DateTime, Utc};
+use crate::time::ExchangeTime;
 use derive_getters::Getters;
 use derive_more::Display;
 use rust_decimal::Decimal;
@@ -22,20 +22,20 @@ pub enum ExecutionType {
 /// https://developers.binance.com/docs/derivatives/usds-margined-futures/user-data-streams/Event-Order-Update
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub struct OrderTradeUpdateEvent {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
-    event_time: DateTime<Utc>,
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
-    transaction_time: DateTime<Utc>,
+    #[serde(rename = "E")]
+    event_time: ExchangeTime,
+    #[serde(rename = "T")]
+    transaction_time: ExchangeTime,
     #[serde(rename = "o")]
     update: OrderTradeUpdate,
 }
 
 impl OrderTradeUpdateEvent {
-    pub fn event_time(&self) -> DateTime<Utc> {
+    pub fn event_time(&self) -> ExchangeTime {
         self.event_time
     }
 
-    pub fn transaction_time(&self) -> DateTime<Utc> {
+    pub fn transaction_time(&self) -> ExchangeTime {
         self.transaction_time
     }
 
@@ -107,7 +107,7 @@ impl OrderTradeUpdateEvent {
         self.update.commission
     }
 
-    pub fn trade_time(&self) -> DateTime<Utc> {
+    pub fn trade_time(&self) -> ExchangeTime {
         self.update.trade_time
     }
 
@@ -185,9 +185,9 @@ pub struct OrderTradeUpdate {
     // WARN: in USDT
     commission: Decimal,
 
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "T")]
     #[getter(copy)]
-    trade_time: DateTime<Utc>,
+    trade_time: ExchangeTime,
 
     #[serde(rename = "t")]
     trade_id: u64,
@@ -205,10 +205,10 @@ pub struct OrderTradeUpdate {
 #[derive(Debug, Clone, Copy, Deserialize)]
 #[allow(dead_code)]
 pub struct TradeLite {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
-    event_time: DateTime<Utc>,
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
-    transaction_time: DateTime<Utc>,
+    #[serde(rename = "E")]
+    event_time: ExchangeTime,
+    #[serde(rename = "T")]
+    transaction_time: ExchangeTime,
     #[serde(rename = "s")]
     symbol: Symbol,
     #[serde(rename = "q")]
@@ -233,6 +233,10 @@ pub struct TradeLite {
 }
 
 impl TradeLite {
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
     pub fn log(&self) {
         tracing::info!(
             client_order_id=%self.client_order_id,
@@ -275,20 +279,20 @@ pub enum AccountEventType {
 /// https://developers.binance.com/docs/derivatives/usds-margined-futures/user-data-streams/Event-Balance-and-Position-Update
 #[derive(Debug, Clone, Deserialize)]
 pub struct AccountUpdateEvent {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
-    event_time: DateTime<Utc>,
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
-    transaction_time: DateTime<Utc>,
+    #[serde(rename = "E")]
+    event_time: ExchangeTime,
+    #[serde(rename = "T")]
+    transaction_time: ExchangeTime,
     #[serde(rename = "a")]
     update: AccountUpdate,
 }
 
 impl AccountUpdateEvent {
-    pub fn event_time(&self) -> DateTime<Utc> {
+    pub fn event_time(&self) -> ExchangeTime {
         self.event_time
     }
 
-    pub fn transaction_time(&self) -> DateTime<Utc> {
+    pub fn transaction_time(&self) -> ExchangeTime {
         self.transaction_time
     }
 
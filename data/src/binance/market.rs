@@ -1,18 +1,34 @@
-use crate::order::Symbol;
-use chrono::{DateTime, Utc};
+use crate::order::{Side, Symbol};
+use crate::time::ExchangeTime;
 use derive_getters::Getters;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 
 type OrderBookUpdateId = u64;
 
+/// Levels kept inline by [`Levels`], diff frames rarely touch more than a handful per side
+pub const INLINE_LEVELS: usize = 8;
+
+/// Level collection of a depth frame, only spills to the heap for large diffs and snapshots.
+/// See `benches/depth_levels.rs` for the allocation count against `Vec<Level>`.
+pub type Levels = SmallVec<[Level; INLINE_LEVELS]>;
+
+/// `[price, quantity]` on the wire, written back the same way so recorded frames re-parse
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
-#[serde(from = "(Decimal, Decimal)")]
+#[serde(from = "(Decimal, Decimal)", into = "(Decimal, Decimal)")]
 pub struct Level {
     pub price: Decimal,
     pub quantity: Decimal,
 }
 
+impl Level {
+    /// Value resting at this level in quote currency
+    pub fn notional(&self) -> Decimal {
+        self.price * self.quantity
+    }
+}
+
 impl From<(Decimal, Decimal)> for Level {
     fn from((price, quantity): (Decimal, Decimal)) -> Self {
         Self { price, quantity }
@@ -36,15 +52,15 @@ impl From<Level> for (Decimal, Decimal) {
 
 /// Payload model for depth update stream, either snapshot or incremental update
 /// https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Mark-Price-Stream
-#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters)]
 pub struct Depth {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "E")]
     #[getter(copy)]
-    event_time: DateTime<Utc>,
+    event_time: ExchangeTime,
 
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "T")]
     #[getter(copy)]
-    transaction_time: DateTime<Utc>,
+    transaction_time: ExchangeTime,
 
     #[serde(rename = "s")]
     #[getter(copy)]
@@ -63,22 +79,63 @@ pub struct Depth {
     last_final_update_id: OrderBookUpdateId,
 
     #[serde(rename = "b")]
-    bids: Vec<Level>,
+    bids: Levels,
     #[serde(rename = "a")]
-    asks: Vec<Level>,
+    asks: Levels,
+}
+
+/// Payload model for the partial book depth stream, `<symbol>@depth<levels>`: the best levels of
+/// each side in full, each frame replacing the last one. Binance sends it as a `depthUpdate`
+/// like the diff stream, so it is told apart by what was subscribed, see `From<Depth>`.
+/// https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Partial-Book-Depth-Streams
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+pub struct PartialDepth {
+    #[serde(rename = "E")]
+    #[getter(copy)]
+    event_time: ExchangeTime,
+
+    #[serde(rename = "T")]
+    #[getter(copy)]
+    transaction_time: ExchangeTime,
+
+    #[serde(rename = "s")]
+    #[getter(copy)]
+    symbol: Symbol,
+
+    #[serde(rename = "u")]
+    #[getter(copy)]
+    final_update_id: OrderBookUpdateId,
+
+    #[serde(rename = "b")]
+    bids: Levels,
+    #[serde(rename = "a")]
+    asks: Levels,
+}
+
+impl From<Depth> for PartialDepth {
+    fn from(depth: Depth) -> Self {
+        Self {
+            event_time: depth.event_time,
+            transaction_time: depth.transaction_time,
+            symbol: depth.symbol,
+            final_update_id: depth.final_update_id,
+            bids: depth.bids,
+            asks: depth.asks,
+        }
+    }
 }
 
 /// Payload model for aggTrade stream
 /// https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Aggregate-Trade-Streams
 #[derive(Debug, Clone, Serialize, Deserialize, Getters)]
 pub struct AggTrade {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "E")]
     #[getter(copy)]
-    event_time: DateTime<Utc>,
+    event_time: ExchangeTime,
 
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "T")]
     #[getter(copy)]
-    transaction_time: DateTime<Utc>,
+    transaction_time: ExchangeTime,
 
     #[serde(rename = "s")]
     #[getter(copy)]
@@ -103,17 +160,24 @@ pub struct AggTrade {
     is_maker: bool,
 }
 
+impl AggTrade {
+    /// Side that crossed the spread, the seller when the buyer was the maker
+    pub fn aggressor(&self) -> Side {
+        if self.is_maker { Side::Sell } else { Side::Buy }
+    }
+}
+
 /// Payload model for trade stream
 /// Unfortunately, the trade stream only appears in Binance spot api docs
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Getters)]
 pub struct Trade {
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "E")]
     #[getter(copy)]
-    event_time: DateTime<Utc>,
+    event_time: ExchangeTime,
 
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "T")]
     #[getter(copy)]
-    transaction_time: DateTime<Utc>,
+    transaction_time: ExchangeTime,
 
     #[serde(rename = "s")]
     #[getter(copy)]
@@ -134,19 +198,26 @@ pub struct Trade {
     is_maker: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Getters)]
+impl Trade {
+    /// Side that crossed the spread, the seller when the buyer was the maker
+    pub fn aggressor(&self) -> Side {
+        if self.is_maker { Side::Sell } else { Side::Buy }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Getters)]
 pub struct BookTicker {
     #[serde(rename = "u")]
     #[getter(copy)]
     order_book_update_id: OrderBookUpdateId,
 
-    #[serde(rename = "E", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "E")]
     #[getter(copy)]
-    event_time: DateTime<Utc>,
+    event_time: ExchangeTime,
 
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
+    #[serde(rename = "T")]
     #[getter(copy)]
-    transaction_time: DateTime<Utc>,
+    transaction_time: ExchangeTime,
 
     #[serde(rename = "s")]
     #[getter(copy)]
@@ -168,3 +239,32 @@ pub struct BookTicker {
     #[getter(copy)]
     ask_qty: Decimal,
 }
+
+/// Payload model for markPrice stream
+/// https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Mark-Price-Stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Getters)]
+pub struct MarkPrice {
+    #[serde(rename = "E")]
+    #[getter(copy)]
+    event_time: ExchangeTime,
+
+    #[serde(rename = "s")]
+    #[getter(copy)]
+    symbol: Symbol,
+
+    #[serde(rename = "p")]
+    #[getter(copy)]
+    mark_price: Decimal,
+
+    #[serde(rename = "i")]
+    #[getter(copy)]
+    index_price: Decimal,
+
+    #[serde(rename = "r")]
+    #[getter(copy)]
+    funding_rate: Decimal,
+
+    #[serde(rename = "T")]
+    #[getter(copy)]
+    next_funding_time: ExchangeTime,
+}
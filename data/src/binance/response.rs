@@ -1,10 +1,11 @@
-use chrono::{DateTime, Utc};
 use derive_getters::Getters;
+use derive_more::Display;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::order::*;
+use crate::time::ExchangeTime;
 
 #[derive(Debug, Serialize, Deserialize, Getters)]
 #[serde(rename_all = "camelCase")]
@@ -23,13 +24,129 @@ pub struct OrderSuccessResp {
     orig_qty: Decimal, // initial quoted quantity
     #[getter(copy)]
     executed_qty: Decimal, // filled quantity
+    #[serde(default)] // absent from order queries
     #[getter(copy)]
     cum_qty: Decimal, // filled quantity
     #[getter(copy)]
     cum_quote: Decimal, // filled amount in usdt
     #[getter(copy)]
     side: Side,
-    #[serde(with = "chrono::serde::ts_milliseconds")]
     #[getter(copy)]
-    update_time: DateTime<Utc>,
+    update_time: ExchangeTime,
+}
+
+/// Position information from `GET /fapi/v3/positionRisk`
+#[derive(Debug, Serialize, Deserialize, Getters)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionRiskResp {
+    #[getter(copy)]
+    symbol: Symbol,
+    #[getter(copy)]
+    position_amt: Decimal,
+    #[getter(copy)]
+    entry_price: Decimal,
+    #[serde(rename = "unRealizedProfit")]
+    #[getter(copy)]
+    unrealized_profit: Decimal,
+    #[getter(copy)]
+    update_time: ExchangeTime,
+}
+
+/// Balance of one asset from `GET /fapi/v2/balance`
+#[derive(Debug, Serialize, Deserialize, Getters)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceResp {
+    #[getter(copy)]
+    asset: Asset,
+    #[getter(copy)]
+    balance: Decimal,
+    #[getter(copy)]
+    cross_wallet_balance: Decimal,
+    #[getter(copy)]
+    available_balance: Decimal,
+    #[getter(copy)]
+    update_time: ExchangeTime,
+}
+
+/// Error payload, either as a whole response body or as an element of a batch response
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Display)]
+#[display("{code}: {msg}")]
+pub struct ErrorResp {
+    #[getter(copy)]
+    code: i64,
+    msg: String,
+}
+
+/// Element of a `batchOrders` response, a single HTTP 200 may carry mixed results
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum BatchOrderResp {
+    Success(OrderSuccessResp),
+    Error(ErrorResp),
+}
+
+/// Trading rules from `GET /fapi/v1/exchangeInfo`, only the parts the bot enforces
+#[derive(Debug, Deserialize)]
+pub struct ExchangeInfoResp {
+    symbols: Vec<SymbolInfo>,
+}
+
+impl ExchangeInfoResp {
+    /// `PERCENT_PRICE` band of `symbol`, `None` if the symbol or its filter is missing
+    pub fn percent_price(&self, symbol: Symbol) -> Option<PercentPriceFilter> {
+        let name = symbol.to_string();
+        self.symbols
+            .iter()
+            .find(|info| info.symbol == name)?
+            .filters
+            .iter()
+            .find_map(|filter| match filter {
+                SymbolFilter::PercentPrice(band) => Some(*band),
+                SymbolFilter::Other => None,
+            })
+    }
+
+    /// Trading status of `symbol`, e.g. `TRADING` or `SETTLING`, `None` if it is not listed
+    pub fn symbol_status(&self, symbol: Symbol) -> Option<&str> {
+        let name = symbol.to_string();
+        self.symbols
+            .iter()
+            .find(|info| info.symbol == name)
+            .map(|info| info.status.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolInfo {
+    // every listed symbol is returned, most of them missing from `Symbol`
+    symbol: String,
+    status: String,
+    filters: Vec<SymbolFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
+enum SymbolFilter {
+    PercentPrice(PercentPriceFilter),
+    #[serde(other)]
+    Other,
+}
+
+/// Bids above `mark * multiplier_up` and asks below `mark * multiplier_down` are rejected
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Getters)]
+#[serde(rename_all = "camelCase")]
+pub struct PercentPriceFilter {
+    #[getter(copy)]
+    multiplier_up: Decimal,
+    #[getter(copy)]
+    multiplier_down: Decimal,
+}
+
+impl PercentPriceFilter {
+    pub fn new(multiplier_up: Decimal, multiplier_down: Decimal) -> Self {
+        Self {
+            multiplier_up,
+            multiplier_down,
+        }
+    }
 }
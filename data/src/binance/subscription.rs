@@ -1,8 +1,14 @@
 use derive_more::Display;
-use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, fmt, future::Future};
-use tokio::{select, sync::mpsc, task::JoinHandle};
+use futures_util::{Sink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize, de::IntoDeserializer};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::{select, sync::mpsc, task::JoinHandle, time::Instant};
 use tokio_tungstenite::{
     connect_async_with_config,
     tungstenite::{
@@ -10,13 +16,28 @@ use tokio_tungstenite::{
         protocol::{Message, WebSocketConfig},
     },
 };
-use tracing::warn;
+use tracing::{info, warn};
 use url::Url;
 
 use crate::binance::account::{AccountUpdateEvent, OrderTradeUpdateEvent, TradeLite};
 use crate::binance::market::*;
 use crate::order::Symbol;
 
+/// Tracing target of unparsed frames, route it to a dedicated writer to keep the main log clean
+pub const RAW_FRAME_TARGET: &str = "raw_frame";
+
+/// Binance caps the number of streams on a single connection
+const MAX_STREAMS_PER_CONNECTION: usize = 200;
+
+/// Large subscriptions are split into several messages of at most this many streams
+const MAX_STREAMS_PER_MESSAGE: usize = 50;
+
+/// Binance allows 10 incoming messages per second on a connection
+const SUBSCRIPTION_MESSAGE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Minimum interval between two unparsed-frame counts in the main log
+const RAW_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Serialize, Clone, Display)]
 #[serde(rename_all = "UPPERCASE")]
 #[display(rename_all = "UPPERCASE")]
@@ -58,12 +79,18 @@ pub enum StreamSpec {
     BookTicker {
         symbol: Symbol,
     },
+    /// Book tickers of every symbol on the exchange in one stream
+    AllBookTicker,
     AggTrade {
         symbol: Symbol,
     },
     Trade {
         symbol: Symbol,
     },
+    /// Mark price and funding rate, pushed every second
+    MarkPrice {
+        symbol: Symbol,
+    },
 
     // account streams
     OrderTradeUpdate,
@@ -86,8 +113,10 @@ impl StreamSpec {
                 (None, None) => format!("{}@depth", symbol.as_str_lowercase()),
             },
             S::BookTicker { symbol } => format!("{}@bookTicker", symbol.as_str_lowercase()),
+            S::AllBookTicker => "!bookTicker".to_string(),
             S::AggTrade { symbol } => format!("{}@aggTrade", symbol.as_str_lowercase()),
             S::Trade { symbol } => format!("{}@trade", symbol.as_str_lowercase()),
+            S::MarkPrice { symbol } => format!("{}@markPrice@1s", symbol.as_str_lowercase()),
             S::TradeLite => "TRADE_LITE".to_string(),
             S::OrderTradeUpdate => "ORDER_TRADE_UPDATE".to_string(),
             S::AccountUpdate => "ACCOUNT_UPDATE".to_string(),
@@ -95,6 +124,30 @@ impl StreamSpec {
     }
 }
 
+/// Binance's answer to a subscription command, `{"result":null,"id":1}` when it went through
+#[derive(Debug, Deserialize, PartialEq)]
+struct ControlResponse {
+    id: u64,
+    #[serde(default)]
+    error: Option<ControlError>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct ControlError {
+    code: i64,
+    msg: String,
+}
+
+impl ControlResponse {
+    /// `None` for anything but a control response, stream events all carry their type in `e`
+    fn parse(text: &str) -> Option<Self> {
+        if text.contains(r#""e":"#) {
+            return None;
+        }
+        serde_json::from_str(text).ok()
+    }
+}
+
 #[derive(Debug)]
 pub enum StreamCommand {
     Subscribe(Vec<StreamSpec>),
@@ -103,7 +156,92 @@ pub enum StreamCommand {
 }
 
 pub trait ParseStream: Sized {
+    /// Name of the stream in logs and reports
+    const KIND: &'static str;
+
     fn parse(text: &str) -> Self;
+
+    /// Whether the frame failed to parse into a known payload
+    fn is_raw(&self) -> bool;
+
+    /// Frames of this stream that failed to parse since startup, across every session
+    fn parse_failures() -> &'static AtomicU64;
+
+    fn parse_failure_count() -> u64 {
+        Self::parse_failures().load(Ordering::Relaxed)
+    }
+}
+
+/// Rate limit of the unparsed-frame warning: the first failure is logged right away, later
+/// ones are counted and logged as one line per interval
+#[derive(Debug)]
+struct RawFrameLimiter {
+    interval: Duration,
+    last_report: Option<Instant>,
+    // failures since the last report
+    pending: u64,
+}
+
+impl RawFrameLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_report: None,
+            pending: 0,
+        }
+    }
+
+    /// Count a failure at `now`, returns the failures to report if a log line is due
+    fn record(&mut self, now: Instant) -> Option<u64> {
+        self.pending += 1;
+        let due = self
+            .last_report
+            .is_none_or(|last| now.duration_since(last) >= self.interval);
+        if !due {
+            return None;
+        }
+        self.last_report = Some(now);
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+/// Delays between the attempts of a session reconnecting by itself, doubling from `initial` up
+/// to `max`. A connection that stays up for `stable_after` starts over from `initial`.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub stable_after: Duration,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            stable_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Delay before the reconnect following `failures` consecutive failed connections
+    fn delay(&self, failures: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.max)
+    }
+}
+
+/// How a connection of a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionEnd {
+    /// No frame arrived within the idle timeout
+    Idle,
+    /// Connecting failed, or the connection errored or closed after `up_for`
+    Dropped { up_for: Duration },
+    /// Shut down by command, or the command channel closed
+    Shutdown,
 }
 
 #[derive(Debug)]
@@ -112,23 +250,55 @@ pub enum MarketStream {
     BookTicker(BookTicker),
     AggTrade(AggTrade),
     Trade(Trade),
+    MarkPrice(MarkPrice),
+    /// A well-formed payload of a symbol this build does not know, e.g. from `!bookTicker`
+    Untracked,
     Raw(Utf8Bytes),
 }
 
 impl ParseStream for MarketStream {
+    const KIND: &'static str = "market";
+
     fn parse(text: &str) -> Self {
         match serde_json::from_str::<MarketPayload>(text) {
             Ok(MarketPayload::Depth(depth)) => MarketStream::Depth(depth),
             Ok(MarketPayload::BookTicker(book_ticker)) => MarketStream::BookTicker(book_ticker),
             Ok(MarketPayload::AggTrade(agg_trade)) => MarketStream::AggTrade(agg_trade),
             Ok(MarketPayload::Trade(trade)) => MarketStream::Trade(trade),
+            Ok(MarketPayload::MarkPrice(mark_price)) => MarketStream::MarkPrice(mark_price),
+            Err(_) if is_untracked_symbol(text) => MarketStream::Untracked,
             Err(_) => {
-                let stream = MarketStream::Raw(Utf8Bytes::from(text));
-                warn!(?stream, "Raw market stream (unparsed)");
-                stream
+                info!(target: RAW_FRAME_TARGET, stream = "market", frame = %text);
+                MarketStream::Raw(Utf8Bytes::from(text))
             }
         }
     }
+
+    fn is_raw(&self) -> bool {
+        matches!(self, MarketStream::Raw(_))
+    }
+
+    fn parse_failures() -> &'static AtomicU64 {
+        static FAILURES: AtomicU64 = AtomicU64::new(0);
+        &FAILURES
+    }
+}
+
+impl MarketStream {
+    /// The frame as recorded for replay, [`ParseStream::parse`] reads it back to the same
+    /// event. Raw frames are kept verbatim, untracked ones have nothing left to record.
+    pub fn record(&self) -> Option<String> {
+        let payload = match self {
+            MarketStream::Depth(depth) => RecordedPayload::Depth(depth),
+            MarketStream::BookTicker(book_ticker) => RecordedPayload::BookTicker(book_ticker),
+            MarketStream::AggTrade(agg_trade) => RecordedPayload::AggTrade(agg_trade),
+            MarketStream::Trade(trade) => RecordedPayload::Trade(trade),
+            MarketStream::MarkPrice(mark_price) => RecordedPayload::MarkPrice(mark_price),
+            MarketStream::Untracked => return None,
+            MarketStream::Raw(text) => return Some(text.to_string()),
+        };
+        Some(serde_json::to_string(&payload).expect("market payloads serialize to JSON"))
+    }
 }
 
 #[derive(Debug)]
@@ -140,6 +310,8 @@ pub enum AccountStream {
 }
 
 impl ParseStream for AccountStream {
+    const KIND: &'static str = "account";
+
     fn parse(text: &str) -> Self {
         match serde_json::from_str::<AccountPayload>(text) {
             Ok(AccountPayload::OrderTradeUpdate(update)) => AccountStream::OrderTradeUpdate(update),
@@ -148,12 +320,52 @@ impl ParseStream for AccountStream {
                 AccountStream::AccountUpdate(account_update)
             }
             Err(_) => {
-                let stream = AccountStream::Raw(Utf8Bytes::from(text));
-                warn!(?stream, "Raw account stream (unparsed)");
-                stream
+                info!(target: RAW_FRAME_TARGET, stream = "account", frame = %text);
+                AccountStream::Raw(Utf8Bytes::from(text))
             }
         }
     }
+
+    fn is_raw(&self) -> bool {
+        matches!(self, AccountStream::Raw(_))
+    }
+
+    fn parse_failures() -> &'static AtomicU64 {
+        static FAILURES: AtomicU64 = AtomicU64::new(0);
+        &FAILURES
+    }
+}
+
+/// Symbol of a frame that failed to parse
+#[derive(Debug, Deserialize)]
+struct SymbolProbe<'a> {
+    #[serde(rename = "s")]
+    symbol: &'a str,
+}
+
+/// Whether the frame failed only because its symbol is missing from [`Symbol`]
+fn is_untracked_symbol(text: &str) -> bool {
+    serde_json::from_str::<SymbolProbe>(text).is_ok_and(|probe| {
+        Symbol::deserialize(probe.symbol.into_deserializer())
+            .map_err(|_: serde::de::value::Error| ())
+            .is_err()
+    })
+}
+
+/// [`MarketPayload`] by reference, tagged the same way for the recorder
+#[derive(Debug, Serialize)]
+#[serde(tag = "e")]
+enum RecordedPayload<'a> {
+    #[serde(rename = "depthUpdate")]
+    Depth(&'a Depth),
+    #[serde(rename = "bookTicker")]
+    BookTicker(&'a BookTicker),
+    #[serde(rename = "trade")]
+    Trade(&'a Trade),
+    #[serde(rename = "aggTrade")]
+    AggTrade(&'a AggTrade),
+    #[serde(rename = "markPriceUpdate")]
+    MarkPrice(&'a MarkPrice),
 }
 
 #[derive(Debug, Deserialize)]
@@ -167,6 +379,8 @@ enum MarketPayload {
     Trade(Trade),
     #[serde(rename = "aggTrade")]
     AggTrade(AggTrade),
+    #[serde(rename = "markPriceUpdate")]
+    MarkPrice(MarkPrice),
 }
 
 #[derive(Debug, Deserialize)]
@@ -183,6 +397,15 @@ pub struct WsSession<E> {
     config: WebSocketConfig,
     active: HashSet<StreamSpec>,
     next_id: u64,
+    // commands sent on the current connection and not answered yet, by id
+    pending: HashMap<u64, (WsSubscriptionMethod, Vec<String>)>,
+    start_delay: Duration,
+    idle_timeout: Option<Duration>,
+    reconnect: Option<ReconnectBackoff>,
+    // connections dropped since the last one that stayed up
+    failures: u32,
+    // kept across reconnects, a drifted schema fails on every connection alike
+    raw_frames: RawFrameLimiter,
     cmd_rx: mpsc::Receiver<StreamCommand>,
     evt_tx: mpsc::Sender<E>,
 }
@@ -199,10 +422,113 @@ impl<E> WsSession<E> {
             config,
             active: HashSet::new(),
             next_id: 1,
+            pending: HashMap::new(),
+            start_delay: Duration::ZERO,
+            idle_timeout: None,
+            reconnect: None,
+            failures: 0,
+            raw_frames: RawFrameLimiter::new(RAW_REPORT_INTERVAL),
             cmd_rx,
             evt_tx,
         }
     }
+
+    /// Wait before connecting, e.g. when restarting a session that just died
+    pub fn with_start_delay(mut self, delay: Duration) -> Self {
+        self.start_delay = delay;
+        self
+    }
+
+    /// Reconnect and resubscribe when no frame of any kind arrives within `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Reconnect and resubscribe after a dropped connection instead of ending the session,
+    /// waiting longer after every failure
+    pub fn with_reconnect(mut self, backoff: Option<ReconnectBackoff>) -> Self {
+        self.reconnect = backoff;
+        self
+    }
+
+    /// Subscribe messages for every active stream, sent again after a reconnect
+    fn resubscribe_commands(&mut self) -> Vec<WsSubscriptionCommand> {
+        let specs: Vec<StreamSpec> = self.active.iter().cloned().collect();
+        self.chunk_commands(WsSubscriptionMethod::Subscribe, &specs)
+    }
+
+    /// Register new streams, refusing those beyond the connection cap,
+    /// and build the subscribe messages
+    fn subscribe_commands(&mut self, specs: Vec<StreamSpec>) -> Vec<WsSubscriptionCommand> {
+        let mut accepted = Vec::with_capacity(specs.len());
+        for spec in specs {
+            if self.active.contains(&spec) {
+                continue;
+            }
+            if self.active.len() >= MAX_STREAMS_PER_CONNECTION {
+                warn!(
+                    stream = %spec.as_param(),
+                    max_streams = MAX_STREAMS_PER_CONNECTION,
+                    "Subscription refused, too many streams on connection"
+                );
+                continue;
+            }
+            self.active.insert(spec.clone());
+            accepted.push(spec);
+        }
+        self.chunk_commands(WsSubscriptionMethod::Subscribe, &accepted)
+    }
+
+    fn unsubscribe_commands(&mut self, specs: Vec<StreamSpec>) -> Vec<WsSubscriptionCommand> {
+        for spec in &specs {
+            self.active.remove(spec);
+        }
+        self.chunk_commands(WsSubscriptionMethod::Unsubscribe, &specs)
+    }
+
+    fn chunk_commands(
+        &mut self,
+        method: WsSubscriptionMethod,
+        specs: &[StreamSpec],
+    ) -> Vec<WsSubscriptionCommand> {
+        specs
+            .chunks(MAX_STREAMS_PER_MESSAGE)
+            .map(|chunk| {
+                let params: Vec<String> = chunk.iter().map(StreamSpec::as_param).collect();
+                self.pending
+                    .insert(self.next_id, (method.clone(), params.clone()));
+                let cmd = WsSubscriptionCommand::new(method.clone(), params, self.next_id);
+                self.next_id += 1;
+                cmd
+            })
+            .collect()
+    }
+
+    /// Match a control response to the command it answers. A refused subscription is no
+    /// longer active, so that subscribing again sends it.
+    fn on_control_response(&mut self, response: ControlResponse) {
+        let Some((method, params)) = self.pending.remove(&response.id) else {
+            warn!(endpoint = %self.endpoint, id = response.id, "Control response to an unknown command");
+            return;
+        };
+        match response.error {
+            None => info!(
+                endpoint = %self.endpoint, %method, streams = ?params, id = response.id,
+                "Subscription command confirmed"
+            ),
+            Some(error) => {
+                warn!(
+                    endpoint = %self.endpoint, %method, streams = ?params, id = response.id,
+                    code = error.code, msg = %error.msg, "Subscription command refused"
+                );
+                if matches!(method, WsSubscriptionMethod::Subscribe) {
+                    self.active
+                        .retain(|spec| !params.contains(&spec.as_param()));
+                }
+            }
+        }
+    }
 }
 
 impl WsSession<MarketStream> {
@@ -234,69 +560,151 @@ where
     fn task(self) -> impl Future<Output = ()> + Send + 'static {
         async move {
             let mut session = self;
-            let Ok((ws_stream, _)) =
-                connect_async_with_config(session.endpoint.as_str(), Some(session.config), true)
-                    .await
-            else {
-                return;
+            if !session.start_delay.is_zero() {
+                tokio::time::sleep(session.start_delay).await;
+            }
+            loop {
+                match session.run_connection().await {
+                    ConnectionEnd::Idle => {
+                        warn!(endpoint = %session.endpoint, "Reconnecting idle session");
+                    }
+                    // without a backoff a dropped connection ends the session
+                    ConnectionEnd::Dropped { up_for } => {
+                        let Some(backoff) = session.reconnect else {
+                            return;
+                        };
+                        if up_for >= backoff.stable_after {
+                            session.failures = 0;
+                        }
+                        session.failures += 1;
+                        let delay = backoff.delay(session.failures);
+                        warn!(
+                            endpoint = %session.endpoint, ?up_for, failures = session.failures,
+                            reconnect_in = ?delay, "Session disconnected"
+                        );
+                        if !session.wait_reconnect(delay).await {
+                            return;
+                        }
+                    }
+                    ConnectionEnd::Shutdown => return,
+                }
+            }
+        }
+    }
+
+    /// Sleep for `delay` before a reconnect, still taking commands. Streams subscribed in the
+    /// meantime go out with the resubscription. Returns false if shut down.
+    async fn wait_reconnect(&mut self, delay: Duration) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            select! {
+                () = &mut sleep => return true,
+                maybe_cmd = self.cmd_rx.recv() => match maybe_cmd {
+                    Some(StreamCommand::Subscribe(specs)) => {
+                        self.subscribe_commands(specs);
+                    }
+                    Some(StreamCommand::Unsubscribe(specs)) => {
+                        self.unsubscribe_commands(specs);
+                    }
+                    Some(StreamCommand::Shutdown) | None => return false,
+                },
+            }
+        }
+    }
+
+    /// Drive one connection until it fails, goes idle or is shut down
+    async fn run_connection(&mut self) -> ConnectionEnd {
+        let Ok((ws_stream, _)) =
+            connect_async_with_config(self.endpoint.as_str(), Some(self.config), true).await
+        else {
+            return ConnectionEnd::Dropped {
+                up_for: Duration::ZERO,
             };
+        };
+        let connected_at = Instant::now();
+        if self.failures > 0 {
+            info!(
+                endpoint = %self.endpoint, failures = self.failures,
+                streams = self.active.len(), "Session reconnected, resubscribing"
+            );
+        }
 
-            let (mut ws_sink, mut ws_stream) = ws_stream.split();
+        let (mut ws_sink, mut ws_stream) = ws_stream.split();
 
-            loop {
-                select! {
-                    // if a message is received
-                    maybe_msg = ws_stream.next() => {
-                        match maybe_msg {
-                            Some(Ok(Message::Text(txt))) => {
-                                // debug!(msg_type = "text", "text message received");
-                                let event = E::parse(&txt);
-                                let _ = session.evt_tx.send(event).await;
-                            }
-                            Some(Ok(Message::Ping(_))) => {}
-                            Some(Ok(raw)) => {
-                                let msg_type = match &raw {
-                                    Message::Text(_) => "text",
-                                    Message::Binary(_) => "binary",
-                                    Message::Ping(_) => "ping",
-                                    Message::Pong(_) => "pong",
-                                    Message::Close(_) => "close",
-                                    Message::Frame(_) => "frame",
-                                };
-                                warn!(
-                                    %msg_type, ?raw,
-                                    "unexpected message received"
-                                );
-                            }
-                            Some(Err(_e)) => break,
-                            None => break,
-                        }
+        // answers to commands of the previous connection are not coming anymore
+        self.pending.clear();
+        let resubscribe = self.resubscribe_commands();
+        send_commands(&mut ws_sink, resubscribe).await;
+
+        let idle = tokio::time::sleep(self.idle_timeout.unwrap_or_default());
+        tokio::pin!(idle);
+
+        loop {
+            select! {
+                // if a message is received
+                maybe_msg = ws_stream.next() => {
+                    if let (Some(Ok(_)), Some(timeout)) = (&maybe_msg, self.idle_timeout) {
+                        idle.as_mut().reset(Instant::now() + timeout);
                     }
-                    // if a command sent
-                    maybe_cmd = session.cmd_rx.recv() => {
-                        use WsSubscriptionMethod as M;
-                        match maybe_cmd {
-                            Some(StreamCommand::Subscribe(specs)) => {
-                                let params: Vec<String> = specs.iter().map(StreamSpec::as_param).collect();
-                                session.active.extend(specs);
-                                let cmd = WsSubscriptionCommand::new(M::Subscribe, params, session.next_id);
-                                session.next_id += 1;
-                                let _ = ws_sink.send(Message::Text(cmd.to_string().into())).await;
+                    match maybe_msg {
+                        Some(Ok(Message::Text(txt))) => {
+                            // debug!(msg_type = "text", "text message received");
+                            if let Some(response) = ControlResponse::parse(&txt) {
+                                self.on_control_response(response);
+                                continue;
                             }
-                            Some(StreamCommand::Unsubscribe(specs)) => {
-                                for spec in &specs {
-                                    session.active.remove(spec);
+                            let event = E::parse(&txt);
+                            if event.is_raw() {
+                                let total = E::parse_failures().fetch_add(1, Ordering::Relaxed) + 1;
+                                if let Some(raw_count) = self.raw_frames.record(Instant::now()) {
+                                    warn!(
+                                        stream = E::KIND, %raw_count, %total,
+                                        endpoint = %self.endpoint, "Unparsed frames received"
+                                    );
                                 }
-                                let params: Vec<String> = specs.iter().map(StreamSpec::as_param).collect();
-                                let cmd = WsSubscriptionCommand::new(M::Unsubscribe, params, session.next_id);
-                                session.next_id += 1;
-                                let _ = ws_sink.send(Message::Text(cmd.to_string().into())).await;
                             }
-                            Some(StreamCommand::Shutdown) => break,
-                            None => break,
+                            let _ = self.evt_tx.send(event).await;
+                        }
+                        Some(Ok(Message::Ping(_))) => {}
+                        Some(Ok(raw)) => {
+                            let msg_type = match &raw {
+                                Message::Text(_) => "text",
+                                Message::Binary(_) => "binary",
+                                Message::Ping(_) => "ping",
+                                Message::Pong(_) => "pong",
+                                Message::Close(_) => "close",
+                                Message::Frame(_) => "frame",
+                            };
+                            warn!(
+                                %msg_type, ?raw,
+                                "unexpected message received"
+                            );
+                        }
+                        Some(Err(_)) | None => {
+                            return ConnectionEnd::Dropped {
+                                up_for: connected_at.elapsed(),
+                            };
                         }
                     }
                 }
+                // if a command sent
+                maybe_cmd = self.cmd_rx.recv() => {
+                    let cmds = match maybe_cmd {
+                        Some(StreamCommand::Subscribe(specs)) => self.subscribe_commands(specs),
+                        Some(StreamCommand::Unsubscribe(specs)) => self.unsubscribe_commands(specs),
+                        Some(StreamCommand::Shutdown) | None => return ConnectionEnd::Shutdown,
+                    };
+                    send_commands(&mut ws_sink, cmds).await;
+                }
+                // if the socket went quiet without closing
+                () = &mut idle, if self.idle_timeout.is_some() => {
+                    warn!(
+                        endpoint = %self.endpoint, idle_timeout = ?self.idle_timeout,
+                        "No frame received within the idle timeout"
+                    );
+                    return ConnectionEnd::Idle;
+                }
             }
         }
     }
@@ -312,3 +720,288 @@ where
             .unwrap_or_else(|_| panic!("Failed to spawn task {}", name))
     }
 }
+
+/// Send control messages one by one, staying under the incoming message rate limit
+async fn send_commands<S>(ws_sink: &mut S, cmds: Vec<WsSubscriptionCommand>)
+where
+    S: Sink<Message> + Unpin,
+{
+    for (i, cmd) in cmds.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(SUBSCRIPTION_MESSAGE_INTERVAL).await;
+        }
+        let _ = ws_sink.send(Message::Text(cmd.to_string().into())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_session() -> WsSession<MarketStream> {
+        let (_cmd_tx, cmd_rx) = mpsc::channel(1);
+        let (evt_tx, _evt_rx) = mpsc::channel(1);
+        let url = Url::parse("wss://fstream.binance.com/ws").unwrap();
+        WsSession::market(url, WebSocketConfig::default(), cmd_rx, evt_tx)
+    }
+
+    fn make_specs(n: u16) -> Vec<StreamSpec> {
+        (0..n)
+            .map(|i| StreamSpec::Depth {
+                symbol: Symbol::BTCUSDT,
+                levels: Some(i),
+                interval_ms: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_all_book_ticker_stream() {
+        assert_eq!(StreamSpec::AllBookTicker.as_param(), "!bookTicker");
+
+        let frame = |symbol: &str| {
+            format!(
+                r#"{{
+                    "e": "bookTicker", "u": 400900217, "E": 1568014460893, "T": 1568014460891,
+                    "s": "{symbol}", "b": "25.35", "B": "31.21", "a": "25.36", "A": "40.66"
+                }}"#
+            )
+        };
+        assert!(matches!(
+            MarketStream::parse(&frame("BTCUSDT")),
+            MarketStream::BookTicker(book_ticker) if book_ticker.symbol() == Symbol::BTCUSDT
+        ));
+        // symbols outside `Symbol` are expected on `!bookTicker`, they are not raw frames
+        let untracked = MarketStream::parse(&frame("DOGEUSDT"));
+        assert!(matches!(untracked, MarketStream::Untracked));
+        assert!(!untracked.is_raw());
+        assert!(MarketStream::parse(r#"{"result": null, "id": 1}"#).is_raw());
+    }
+
+    #[test]
+    fn test_large_subscription_is_chunked() {
+        let mut session = make_session();
+        let cmds = session.subscribe_commands(make_specs(120));
+
+        let sizes: Vec<usize> = cmds.iter().map(|cmd| cmd.params.len()).collect();
+        assert_eq!(sizes, vec![50, 50, 20]);
+        let ids: Vec<u64> = cmds.iter().map(|cmd| cmd.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(session.active.len(), 120);
+    }
+
+    #[tokio::test]
+    async fn test_idle_session_reconnects() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+
+        // accept connections and read whatever arrives, never sending a frame
+        let (conn_tx, mut conn_rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let conn_tx = conn_tx.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                    let _ = conn_tx.send(()).await;
+                    while let Some(Ok(_)) = ws.next().await {}
+                });
+            }
+        });
+
+        let (cmd_tx, cmd_rx) = mpsc::channel(1);
+        let (evt_tx, _evt_rx) = mpsc::channel(1);
+        let handle = WsSession::market(url, WebSocketConfig::default(), cmd_rx, evt_tx)
+            .with_idle_timeout(Some(Duration::from_millis(100)))
+            .spawn();
+
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(2), conn_rx.recv())
+                .await
+                .expect("Session did not reconnect after the idle timeout");
+        }
+        assert!(!handle.is_finished());
+
+        cmd_tx.send(StreamCommand::Shutdown).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), handle)
+            .await
+            .expect("Session did not shut down")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_session_reconnects_and_resubscribes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+
+        // take the first message of every connection, then drop the connection
+        let (msg_tx, mut msg_rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let msg_tx = msg_tx.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                    if let Some(Ok(Message::Text(txt))) = ws.next().await {
+                        let _ = msg_tx.send(txt.to_string()).await;
+                    }
+                });
+            }
+        });
+
+        let (cmd_tx, cmd_rx) = mpsc::channel(1);
+        let (evt_tx, _evt_rx) = mpsc::channel(1);
+        let handle = WsSession::market(url, WebSocketConfig::default(), cmd_rx, evt_tx)
+            .with_reconnect(Some(ReconnectBackoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+                stable_after: Duration::from_secs(60),
+            }))
+            .spawn();
+        cmd_tx
+            .send(StreamCommand::Subscribe(vec![StreamSpec::BookTicker {
+                symbol: Symbol::BTCUSDT,
+            }]))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let msg = tokio::time::timeout(Duration::from_secs(2), msg_rx.recv())
+                .await
+                .expect("Session did not reconnect after the connection dropped")
+                .unwrap();
+            assert!(msg.contains(r#""method":"SUBSCRIBE","params":["btcusdt@bookTicker"]"#));
+        }
+        assert!(!handle.is_finished());
+
+        cmd_tx.send(StreamCommand::Shutdown).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), handle)
+            .await
+            .expect("Session did not shut down")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reconnect_backoff_doubles_up_to_max() {
+        let backoff = ReconnectBackoff::default();
+        let delays: Vec<Duration> = (1..=9).map(|failures| backoff.delay(failures)).collect();
+
+        let millis = |ms: &[u64]| {
+            ms.iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            delays,
+            millis(&[500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000])
+        );
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_raw_frame_log_is_rate_limited() {
+        let mut limiter = RawFrameLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        let reports: Vec<(u64, u64)> = (0..10_000u64)
+            .filter_map(|i| {
+                // one failure every 10ms over 100s
+                let now = start + Duration::from_millis(i * 10);
+                limiter.record(now).map(|count| (i, count))
+            })
+            .collect();
+
+        // the first failure right away, then one line per minute for the rest
+        assert_eq!(reports, vec![(0, 1), (6000, 6000)]);
+        // nothing is lost between the lines, the rest is reported on the next one
+        let reported: u64 = reports.iter().map(|(_, count)| count).sum();
+        assert_eq!(reported + limiter.pending, 10_000);
+    }
+
+    #[test]
+    fn test_control_responses_answer_their_command() {
+        let ack = ControlResponse::parse(r#"{"result":null,"id":1}"#).unwrap();
+        assert_eq!(ack, ControlResponse { id: 1, error: None });
+        let refusal = ControlResponse::parse(
+            r#"{"error":{"code":2,"msg":"Invalid request: unknown stream"},"id":2}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            refusal.error,
+            Some(ControlError {
+                code: 2,
+                msg: "Invalid request: unknown stream".to_string(),
+            })
+        );
+        assert!(
+            ControlResponse::parse(
+                r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,
+                    "s":"BTCUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}"#
+            )
+            .is_none()
+        );
+
+        let mut session = make_session();
+        session.subscribe_commands(make_specs(1));
+        session.subscribe_commands(make_specs(2));
+        assert_eq!(session.pending.len(), 2);
+
+        session.on_control_response(ack);
+        assert_eq!(session.active.len(), 2);
+        // the second command only added `levels: Some(1)`, refusing it leaves the first
+        session.on_control_response(refusal);
+        assert!(session.pending.is_empty());
+        assert_eq!(session.active.len(), 1);
+        assert!(session.active.contains(&make_specs(1)[0]));
+    }
+
+    #[test]
+    fn test_subscription_beyond_cap_is_refused() {
+        let mut session = make_session();
+        let cmds = session.subscribe_commands(make_specs(250));
+
+        let total: usize = cmds.iter().map(|cmd| cmd.params.len()).sum();
+        assert_eq!(total, MAX_STREAMS_PER_CONNECTION);
+        assert_eq!(session.active.len(), MAX_STREAMS_PER_CONNECTION);
+
+        // already active streams are not sent again
+        assert!(session.subscribe_commands(make_specs(10)).is_empty());
+    }
+
+    #[test]
+    fn test_recorded_frames_replay_identically() {
+        let frames = [
+            r#"{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"BTCUSDT",
+                "U":390497796,"u":390497878,"pu":390497794,
+                "b":[["7403.89","0.002"],["7403.90","3.906"]],"a":[["7405.96","3.340"]]}"#,
+            // one side empty, zero quantities remove a level
+            r#"{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"BTCUSDT",
+                "U":390497879,"u":390497880,"pu":390497878,
+                "b":[],"a":[["7405.96","0"],["7406.00","0.000"]]}"#,
+            r#"{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"SOLUSDT",
+                "U":1,"u":1,"pu":0,"b":[],"a":[]}"#,
+            r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,
+                "s":"BTCUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66"}"#,
+            r#"{"e":"bookTicker","u":400900218,"E":1568014460893,"T":1568014460891,
+                "s":"SOLUSDT","b":"0","B":"0","a":"25.3652","A":"0.00"}"#,
+        ];
+
+        for frame in frames {
+            let recorded = MarketStream::parse(frame).record().unwrap();
+            match (MarketStream::parse(frame), MarketStream::parse(&recorded)) {
+                (MarketStream::Depth(live), MarketStream::Depth(replayed)) => {
+                    assert_eq!(live, replayed)
+                }
+                (MarketStream::BookTicker(live), MarketStream::BookTicker(replayed)) => {
+                    assert_eq!(live, replayed)
+                }
+                (live, replayed) => panic!("{live:?} replayed as {replayed:?}"),
+            }
+            // recording the replayed event writes the same frame again
+            assert_eq!(MarketStream::parse(&recorded).record().unwrap(), recorded);
+        }
+        assert!(MarketStream::Untracked.record().is_none());
+        assert_eq!(
+            MarketStream::Raw(Utf8Bytes::from("not json")).record(),
+            Some("not json".to_string())
+        );
+    }
+}
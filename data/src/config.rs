@@ -1,30 +1,95 @@
 use crate::Result;
 use crate::error::{ConfigError, DataError};
-use crate::order::Symbol;
+use crate::order::{SelfTradePreventionMode, Side, Symbol};
+use chrono::NaiveTime;
 use csv::Reader;
-use serde::{Deserialize, Deserializer};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+/// How the api secret signs requests, from the optional `key_type` csv column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyType {
+    /// The secret is the shared HMAC key, the default
+    #[default]
+    HmacSha256,
+    /// The secret is a PKCS#8 private key, as PEM or the bare base64 body
+    Ed25519,
+}
+
+#[derive(Debug, Clone)]
 pub struct AccountConfidential {
-    #[serde(rename = "account_name")]
     pub name: String,
     pub api_key: String,
     pub api_secret: String,
-    #[serde(rename = "testnet", deserialize_with = "bool_from_string")]
-    is_testnet: bool,
+    pub key_type: KeyType,
+    environment: Environment,
+}
+
+/// Raw csv row, validated only once the account is matched
+#[derive(Debug, Deserialize)]
+struct AccountRecord {
+    account_name: String,
+    api_key: String,
+    api_secret: String,
+    /// Superseded by `environment`, `true` stands for the testnet
+    #[serde(default)]
+    testnet: String,
+    #[serde(default)]
+    environment: String,
+    #[serde(default)]
+    key_type: String,
+}
+
+impl TryFrom<AccountRecord> for AccountConfidential {
+    type Error = ConfigError;
+
+    fn try_from(record: AccountRecord) -> std::result::Result<Self, Self::Error> {
+        let invalid = |reason| ConfigError::InvalidCredential {
+            name: record.account_name.clone(),
+            reason,
+        };
+        if record.api_key.trim().is_empty() {
+            return Err(invalid("empty api_key"));
+        }
+        if record.api_secret.trim().is_empty() {
+            return Err(invalid("empty api_secret"));
+        }
+        let environment = match (record.environment.trim(), record.testnet.trim()) {
+            ("", s) if s.eq_ignore_ascii_case("true") => Environment::Testnet,
+            ("", s) if s.eq_ignore_ascii_case("false") => Environment::Production,
+            ("", _) => return Err(invalid("testnet must be true or false")),
+            (s, _) if s.eq_ignore_ascii_case("production") => Environment::Production,
+            (s, _) if s.eq_ignore_ascii_case("testnet") => Environment::Testnet,
+            (s, _) if s.eq_ignore_ascii_case("demo") => Environment::Demo,
+            _ => return Err(invalid("environment must be production, testnet or demo")),
+        };
+        let key_type = match record.key_type.trim() {
+            "" => KeyType::HmacSha256,
+            s if s.eq_ignore_ascii_case("hmac") => KeyType::HmacSha256,
+            s if s.eq_ignore_ascii_case("ed25519") => KeyType::Ed25519,
+            _ => return Err(invalid("key_type must be hmac or ed25519")),
+        };
+        Ok(Self {
+            name: record.account_name,
+            api_key: record.api_key,
+            api_secret: record.api_secret,
+            key_type,
+            environment,
+        })
+    }
 }
 
 impl AccountConfidential {
     pub fn from_csv(name: &str, csv_path: impl AsRef<Path>) -> Result<Self> {
         let mut rdr = Reader::from_path(csv_path)?;
         for result in rdr.deserialize() {
-            let record: AccountConfidential = result?;
+            let record: AccountRecord = result?;
 
-            if record.name == name {
-                return Ok(record);
+            if record.account_name == name {
+                return Ok(AccountConfidential::try_from(record)?);
             }
         }
         Err(DataError::Config(ConfigError::AccountNotFound {
@@ -32,19 +97,12 @@ impl AccountConfidential {
         }))
     }
 
-    pub fn is_testnet(&self) -> bool {
-        self.is_testnet
+    /// Environment the credentials were issued for, they are refused everywhere else
+    pub fn environment(&self) -> Environment {
+        self.environment
     }
 }
 
-fn bool_from_string<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    Ok(s.eq_ignore_ascii_case("true"))
-}
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct LoggingFileConfig {
     pub dir: String,
@@ -60,19 +118,55 @@ pub struct LoggingConsoleConfig {
     pub pretty: bool,
 }
 
+/// Dedicated file for unparsed websocket frames
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoggingRawConfig {
+    pub dir: String,
+    pub name: String,
+}
+
+impl Default for LoggingRawConfig {
+    fn default() -> Self {
+        Self {
+            dir: "./logs".to_string(),
+            name: "raw.log".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LoggingConfig {
     pub file_log: bool,
     pub console_log: bool,
+    #[serde(default)]
+    pub raw_log: bool,
+    #[serde(default)]
+    pub tokio_console: bool,
     pub file: LoggingFileConfig,
     pub console: LoggingConsoleConfig,
+    #[serde(default)]
+    pub raw: LoggingRawConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Binance systems an account lives on, each with its own endpoints and credentials
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Environment {
     Production,
+    /// Futures testnet, `testnet.binancefuture.com`
     Testnet,
+    /// Demo trading on production market data, `demo-fapi.binance.com`
+    Demo,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Production => "production",
+            Environment::Testnet => "testnet",
+            Environment::Demo => "demo",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -87,16 +181,89 @@ pub struct AccountConfig {
 pub struct EndpointMap {
     pub production: String,
     pub testnet: String,
+    /// Only needed to run on the demo environment
+    #[serde(default)]
+    pub demo: Option<String>,
+}
+
+impl EndpointMap {
+    /// Endpoint of `environment`, `None` if it is not configured
+    pub fn get(&self, environment: Environment) -> Option<&str> {
+        match environment {
+            Environment::Production => Some(&self.production),
+            Environment::Testnet => Some(&self.testnet),
+            Environment::Demo => self.demo.as_deref(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct RestConfig {
     pub endpoints: EndpointMap,
+    /// Idle connections kept per host, unbounded if unset
+    #[serde(default)]
+    pub pool_max_idle: Option<usize>,
+    /// Speak HTTP/2 from the first byte, without negotiating it over HTTP/1.1
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// Close pooled connections idle for this long, 30s if unset
+    #[serde(default)]
+    pub pool_idle_timeout_ms: Option<u64>,
+    /// TCP keepalive probes on idle connections, off if unset
+    #[serde(default)]
+    pub tcp_keepalive_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct WsConfig {
     pub endpoints: EndpointMap,
+    /// Reconnect a session that received no frame at all for this long, off if unset
+    #[serde(default)]
+    pub idle_timeout_ms: Option<u64>,
+    /// Subscribe `!bookTicker` once instead of a book ticker stream per symbol
+    #[serde(default)]
+    pub all_book_ticker: bool,
+    /// Public trades of every symbol feeding the trade flow, not subscribed if unset
+    #[serde(default)]
+    pub trades: Option<TradeStream>,
+    /// How the local order book is built
+    #[serde(default)]
+    pub book: BookSource,
+    /// Keep the order book across a market feed reconnect if the first depth update still
+    /// chains, instead of always taking a new snapshot
+    #[serde(default)]
+    pub warm_reconnect: bool,
+    /// Give a feed up after this many restarts without a single event, quoting stops then.
+    /// Restarted forever if unset.
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+}
+
+/// Source of the local order book
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "source")]
+pub enum BookSource {
+    /// REST snapshot kept up to date by the diff stream, the full book at the cost of snapshot
+    /// request weight after every gap
+    #[default]
+    SnapshotDiff,
+    /// The partial depth stream alone, the best `levels` (5, 10 or 20) of each side and no
+    /// REST snapshot. Every frame replaces the book.
+    PartialStream {
+        levels: u16,
+        /// Update speed, 250ms if unset
+        #[serde(default)]
+        interval_ms: Option<u16>,
+    },
+}
+
+/// Public trade stream to subscribe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStream {
+    /// Trades at the same price and taker side merged, far fewer frames
+    AggTrade,
+    Trade,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -104,6 +271,327 @@ pub struct ExchangeConfig {
     pub symbols: Vec<Symbol>,
     pub rest: RestConfig,
     pub ws: WsConfig,
+    /// Order and cancel requests in flight at once, further ones wait for a slot
+    #[serde(default)]
+    pub max_inflight_requests: Option<usize>,
+    /// REST suspension after an IP ban (418) that carries no Retry-After
+    #[serde(default)]
+    pub ban_fallback_secs: Option<u64>,
+}
+
+/// Fat-finger guards in USDT, independent of exchange and position limits
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RiskConfig {
+    #[serde(default)]
+    pub max_order_notional: Option<Decimal>,
+    #[serde(default)]
+    pub max_open_notional: Option<Decimal>,
+    /// Maximum deviation of a quote from the rolling mid, in percent
+    #[serde(default)]
+    pub max_price_deviation_pct: Option<Decimal>,
+    /// Exchange cancels all orders unless refreshed within this window, off if unset
+    #[serde(default)]
+    pub countdown_cancel_ms: Option<u64>,
+    /// Skip quoting while the book ticker is older than this, off if unset
+    #[serde(default)]
+    pub max_bbo_age_ms: Option<u64>,
+    /// Resting orders tracked at once, new orders beyond this are refused
+    #[serde(default)]
+    pub max_active_orders: Option<usize>,
+    /// Cancel all resting orders as soon as a feed drops, instead of leaving them to GTD expiry
+    /// and the countdown cancel
+    #[serde(default)]
+    pub cancel_on_disconnect: bool,
+}
+
+/// Sides the strategy provides liquidity on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteSide {
+    #[default]
+    Both,
+    BidOnly,
+    AskOnly,
+}
+
+impl QuoteSide {
+    pub fn allows(&self, side: Side) -> bool {
+        match self {
+            QuoteSide::Both => true,
+            QuoteSide::BidOnly => matches!(side, Side::Buy),
+            QuoteSide::AskOnly => matches!(side, Side::Sell),
+        }
+    }
+}
+
+/// How quotes are placed when the market spread is wider than `max_quote_spread_bps`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WideSpreadQuoting {
+    /// Quote the capped spread around the center, inside the market
+    #[default]
+    Cap,
+    /// Do not quote until the spread narrows
+    Abstain,
+}
+
+/// Small IOC orders sent now and then at or inside the touch, to find hidden liquidity and time
+/// fills. They never rest, sides alternate from the bid.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ProbeConfig {
+    pub interval_ms: u64,
+    /// Quantity of each probe, the smallest the symbol accepts
+    pub qty: Decimal,
+    /// Ticks inside the far touch, sized by `tick_offset.tick_sizes`. At the touch if 0 or
+    /// without a tick size.
+    #[serde(default)]
+    pub ticks_inside: u32,
+}
+
+/// Quotes a fixed half spread around the center, shifted against the inventory away from
+/// `inventory_target`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct InventorySkewConfig {
+    pub half_spread: Decimal,
+    /// Price shift per unit of inventory, a long position lowers both quotes
+    pub skew_per_unit: Decimal,
+    /// Quantity quoted when neither balance nor notional sizing applies
+    pub size: Decimal,
+}
+
+/// Several price levels per side instead of a single bid and ask
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct QuoteLevelsConfig {
+    /// Orders per side, nothing is quoted at 0
+    pub levels: usize,
+    /// Price distance between neighbouring levels, a multiple of the tick size
+    pub level_step: Decimal,
+    /// Fraction of the size each level gives up against the one inside it, flat if 0
+    #[serde(default)]
+    pub size_decay: Decimal,
+}
+
+/// Sizes quotes off the account balance rather than a fixed quantity
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BalanceSizingConfig {
+    /// Fraction of the balance committed as margin by each quote
+    pub fraction: Decimal,
+    pub leverage: Decimal,
+    /// Decimal places of the symbol's quantity step
+    pub qty_scale: u32,
+}
+
+/// Sizes quotes by their notional in the quote asset, e.g. USDT, instead of a base quantity
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotionalSizingConfig {
+    pub notionals: HashMap<Symbol, Decimal>,
+    /// Quantity step of each symbol, quantities are rounded down to it
+    pub lot_sizes: HashMap<Symbol, Decimal>,
+}
+
+impl NotionalSizingConfig {
+    /// Notional and lot size of `symbol`, `None` unless both are configured
+    pub fn sizing(&self, symbol: Symbol) -> Option<(Decimal, Decimal)> {
+        Some((*self.notionals.get(&symbol)?, *self.lot_sizes.get(&symbol)?))
+    }
+}
+
+/// Places quotes a whole number of ticks from the touch instead of around the mid
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TickOffsetConfig {
+    /// Ticks behind the touch: 0 joins, negative steps ahead without crossing, positive backs off
+    #[serde(default)]
+    pub ticks: i64,
+    pub tick_sizes: HashMap<Symbol, Decimal>,
+    /// Picks the offset by spread width instead of the fixed `ticks` if set
+    #[serde(default)]
+    pub placement: Option<SpreadPlacementConfig>,
+}
+
+impl TickOffsetConfig {
+    pub fn tick_size(&self, symbol: Symbol) -> Option<Decimal> {
+        self.tick_sizes.get(&symbol).copied()
+    }
+
+    /// Ticks behind the touch for a spread of `spread` with ticks of `tick_size`
+    pub fn ticks_for(&self, spread: Decimal, tick_size: Decimal) -> i64 {
+        match &self.placement {
+            Some(placement) => {
+                let wide = spread >= tick_size * Decimal::from(placement.wide_spread_ticks);
+                if wide {
+                    placement.wide
+                } else {
+                    placement.tight
+                }
+                .ticks()
+            }
+            None => self.ticks,
+        }
+    }
+}
+
+/// Where a quote goes relative to its own side's touch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementPolicy {
+    /// Quote at the touch
+    Join,
+    /// Step ahead of the touch for queue priority, never crossing
+    ImproveByTicks(u32),
+    /// Back off behind the touch
+    StepBackTicks(u32),
+}
+
+impl PlacementPolicy {
+    /// Signed ticks behind the touch, negative ahead of it
+    pub fn ticks(self) -> i64 {
+        match self {
+            PlacementPolicy::Join => 0,
+            PlacementPolicy::ImproveByTicks(ticks) => -i64::from(ticks),
+            PlacementPolicy::StepBackTicks(ticks) => i64::from(ticks),
+        }
+    }
+}
+
+/// Join-vs-improve by spread width, e.g. improve a wide market, join or back off a tight one
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SpreadPlacementConfig {
+    /// Spreads of at least this many ticks are wide
+    pub wide_spread_ticks: u32,
+    pub wide: PlacementPolicy,
+    pub tight: PlacementPolicy,
+}
+
+/// Smallest top of book move that is worth new quotes
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebounceThreshold {
+    /// Whole ticks, sized by `tick_offset.tick_sizes`
+    Ticks(u32),
+    /// Basis points of the mid quoted against
+    Bps(Decimal),
+}
+
+/// Holds quotes while the BBO jitters, but never longer than `max_hold_ms`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct QuoteDebounceConfig {
+    pub threshold: DebounceThreshold,
+    pub max_hold_ms: u64,
+}
+
+/// Daily UTC window `[start, end)` with its own quote size, wrapping past midnight when `end` is
+/// before `start`, e.g. around funding at 00:00
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct QuoteWindowConfig {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Multiplier of the quote quantity inside the window, 0 pauses quoting
+    #[serde(default)]
+    pub size_factor: Decimal,
+}
+
+impl QuoteWindowConfig {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StrategyConfig {
+    /// Mint decodable client order ids tagged with this value, random uuids if unset
+    #[serde(default)]
+    pub client_id_tag: Option<u8>,
+    #[serde(default)]
+    pub quote_side: QuoteSide,
+    /// Self-trade prevention mode sent with every quote
+    #[serde(default)]
+    pub stp_mode: SelfTradePreventionMode,
+    /// Fixed quote quantity is used if unset, or until the first balance update
+    #[serde(default)]
+    pub balance_sizing: Option<BalanceSizingConfig>,
+    /// Quote a constant notional instead of the fixed quantity, for the symbols it covers.
+    /// Balance sizing takes precedence once the balance is known.
+    #[serde(default)]
+    pub notional_sizing: Option<NotionalSizingConfig>,
+    /// Pause re-quoting a side for this long after a fill on it, off if unset
+    #[serde(default)]
+    pub fill_cooldown_ms: Option<u64>,
+    /// Quote around the mid if unset, or for symbols without a tick size
+    #[serde(default)]
+    pub tick_offset: Option<TickOffsetConfig>,
+    /// Re-quote on every send tick if unset
+    #[serde(default)]
+    pub debounce: Option<QuoteDebounceConfig>,
+    /// Size shift per unit of inventory away from `inventory_target` towards the side that
+    /// brings it back, e.g. 0.1 quotes 20% more on the ask than on the bid when 1 above it.
+    /// Symmetric sizes if unset.
+    #[serde(default)]
+    pub size_skew: Option<Decimal>,
+    /// Position the skews steer towards, flat by default
+    #[serde(default)]
+    pub inventory_target: Decimal,
+    /// Price shift in bps of the mid per unit of inventory away from `inventory_target`, both
+    /// quotes move down when above it and up when below. Unskewed prices if unset.
+    #[serde(default)]
+    pub mean_reversion_bps: Option<Decimal>,
+    /// Windows that pause or down-size quoting, the smallest factor wins where they overlap
+    #[serde(default)]
+    pub schedule: Vec<QuoteWindowConfig>,
+    /// Quotes rest as GTD for this long, at least the exchange minimum of 10 minutes.
+    /// GTC if unset.
+    #[serde(default)]
+    pub quote_ttl_ms: Option<u64>,
+    /// Re-quote right after a fill instead of waiting for the next send tick. The debounce
+    /// is skipped, the fill cool-down still holds.
+    #[serde(default)]
+    pub requote_on_fill: bool,
+    /// Widest spread in bps of the mid that is quoted as the market shows it, any if unset
+    #[serde(default)]
+    pub max_quote_spread_bps: Option<Decimal>,
+    /// Placement past `max_quote_spread_bps`
+    #[serde(default)]
+    pub wide_spread_quoting: WideSpreadQuoting,
+    /// IOC probes alongside the quotes, off if unset
+    #[serde(default)]
+    pub probe: Option<ProbeConfig>,
+    /// Fixed-spread quoting skewed by inventory instead of the market's spread, off if unset
+    #[serde(default)]
+    pub inventory_skew: Option<InventorySkewConfig>,
+    /// Layered quotes, one level per side if unset
+    #[serde(default)]
+    pub quote_levels: Option<QuoteLevelsConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModeConfig {
+    /// With trading off only the market feed runs: no listen key, account stream or orders
+    pub trading: bool,
+}
+
+impl Default for ModeConfig {
+    fn default() -> Self {
+        Self { trading: true }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReportConfig {
+    /// Session report rewritten atomically as JSON on every report tick, off if unset
+    #[serde(default)]
+    pub health_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecoveryConfig {
+    /// Trading state written on every report tick and at shutdown, resumed from at startup.
+    /// Off if unset.
+    #[serde(default)]
+    pub state_path: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -111,6 +599,16 @@ pub struct DataCenterConfig {
     pub logging: LoggingConfig,
     pub account: AccountConfig,
     pub exchange: ExchangeConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub strategy: StrategyConfig,
+    #[serde(default)]
+    pub mode: ModeConfig,
+    #[serde(default)]
+    pub report: ReportConfig,
+    #[serde(default)]
+    pub recovery: RecoveryConfig,
 }
 
 impl DataCenterConfig {
@@ -125,6 +623,13 @@ impl DataCenterConfig {
 mod tests {
     use super::*;
     const PATH: &'static str = "../config/test_account_info.csv";
+
+    fn write_csv(file_name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(file_name);
+        fs::write(&path, content).expect("Failed to write csv");
+        path
+    }
+
     #[test]
     fn test_read_confidential_from_csv() {
         let test_res = AccountConfidential::from_csv("test", PATH);
@@ -135,4 +640,90 @@ mod tests {
         assert!(prod_res.is_ok());
         assert!(fail_res.is_err());
     }
+
+    #[test]
+    fn test_blank_secret_is_rejected() {
+        let path = write_csv(
+            "ceraunus_blank_secret.csv",
+            "account_name,api_key,api_secret,testnet\nblank,key, ,true\n",
+        );
+        let res = AccountConfidential::from_csv("blank", &path);
+
+        assert!(matches!(
+            res,
+            Err(DataError::Config(ConfigError::InvalidCredential {
+                reason: "empty api_secret",
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_garbage_testnet_is_rejected() {
+        let path = write_csv(
+            "ceraunus_garbage_testnet.csv",
+            "account_name,api_key,api_secret,testnet\ngarbage,key,secret,yes\nvalid,key,secret,TRUE\n",
+        );
+        let garbage_res = AccountConfidential::from_csv("garbage", &path);
+        let valid_res = AccountConfidential::from_csv("valid", &path);
+
+        assert!(matches!(
+            garbage_res,
+            Err(DataError::Config(ConfigError::InvalidCredential { .. }))
+        ));
+        assert!(valid_res.is_ok_and(|acct| acct.environment() == Environment::Testnet));
+    }
+
+    #[test]
+    fn test_environment_column() {
+        let path = write_csv(
+            "ceraunus_environment.csv",
+            "account_name,api_key,api_secret,testnet,environment
+             legacy,key,secret,false,
+             demo,key,secret,,Demo
+             testnet,key,secret,false,testnet
+             staging,key,secret,,staging
+",
+        );
+
+        let environment =
+            |name: &str| AccountConfidential::from_csv(name, &path).map(|acct| acct.environment());
+        assert!(matches!(environment("legacy"), Ok(Environment::Production)));
+        assert!(matches!(environment("demo"), Ok(Environment::Demo)));
+        // the environment column wins over the legacy flag
+        assert!(matches!(environment("testnet"), Ok(Environment::Testnet)));
+        assert!(matches!(
+            environment("staging"),
+            Err(DataError::Config(ConfigError::InvalidCredential { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_key_type_column() {
+        let path = write_csv(
+            "ceraunus_key_type.csv",
+            "account_name,api_key,api_secret,testnet,key_type\n\
+             hmac,key,secret,true,\n\
+             ed,key,secret,true,Ed25519\n\
+             rsa,key,secret,true,rsa\n",
+        );
+        let legacy = write_csv(
+            "ceraunus_no_key_type.csv",
+            "account_name,api_key,api_secret,testnet\nlegacy,key,secret,true\n",
+        );
+
+        let key_type = |path: &Path, name: &str| {
+            AccountConfidential::from_csv(name, path).map(|acct| acct.key_type)
+        };
+        assert!(matches!(key_type(&path, "hmac"), Ok(KeyType::HmacSha256)));
+        assert!(matches!(key_type(&path, "ed"), Ok(KeyType::Ed25519)));
+        assert!(matches!(
+            key_type(&path, "rsa"),
+            Err(DataError::Config(ConfigError::InvalidCredential { .. }))
+        ));
+        assert!(matches!(
+            key_type(&legacy, "legacy"),
+            Ok(KeyType::HmacSha256)
+        ));
+    }
 }
@@ -0,0 +1,76 @@
+//! Alternative `Decimal` formats for JSON written by the bot. The default serde format is a
+//! string, which keeps every digit but trips up tools that expect numbers.
+
+/// Serialize as a JSON number, through `f64` so digits past its precision are lost. Reading
+/// accepts numbers and strings alike, files in either format load back.
+///
+/// Use with `#[serde(with = "data::decimal::as_number")]`.
+pub mod as_number {
+    use rust_decimal::Decimal;
+    use rust_decimal::prelude::ToPrimitive;
+    use serde::{Deserialize, Deserializer, Serializer, ser::Error};
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        let number = value
+            .to_f64()
+            .ok_or_else(|| S::Error::custom(format!("{value} does not fit an f64")))?;
+        serializer.serialize_f64(number)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        // the serde impl of Decimal already takes both strings and numbers
+        Decimal::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::{Decimal, dec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct AsString {
+        price: Decimal,
+        qty: Decimal,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct AsNumber {
+        #[serde(with = "super::as_number")]
+        price: Decimal,
+        #[serde(with = "super::as_number")]
+        qty: Decimal,
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let fill = AsString {
+            price: dec!(142.37),
+            qty: dec!(0.001),
+        };
+        let json = serde_json::to_string(&fill).unwrap();
+
+        assert_eq!(json, r#"{"price":"142.37","qty":"0.001"}"#);
+        assert_eq!(serde_json::from_str::<AsString>(&json).unwrap(), fill);
+        assert_eq!(
+            serde_json::from_str::<AsNumber>(&json).unwrap().price,
+            fill.price
+        );
+    }
+
+    #[test]
+    fn test_number_round_trip() {
+        let fill = AsNumber {
+            price: dec!(142.37),
+            qty: dec!(0.001),
+        };
+        let json = serde_json::to_string(&fill).unwrap();
+
+        assert_eq!(json, r#"{"price":142.37,"qty":0.001}"#);
+        assert_eq!(serde_json::from_str::<AsNumber>(&json).unwrap(), fill);
+        assert_eq!(
+            serde_json::from_str::<AsString>(&json).unwrap().qty,
+            fill.qty
+        );
+    }
+}
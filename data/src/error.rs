@@ -35,6 +35,9 @@ pub enum ConfigError {
 
     #[error("account '{name}' not found")]
     AccountNotFound { name: String },
+
+    #[error("invalid credential for account '{name}': {reason}")]
+    InvalidCredential { name: String, reason: &'static str },
 }
 
 /// Websocket connection error
@@ -1,6 +1,8 @@
 pub mod binance;
 pub mod config;
+pub mod decimal;
 pub mod error;
 pub mod order;
+pub mod time;
 
 pub use error::{DataError, Error, Result};
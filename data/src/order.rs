@@ -30,6 +30,9 @@ pub enum OrderStatus {
 pub enum Asset {
     USDT,
     BUSD,
+    /// Any asset the engine does not track
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Display, Enum)]
@@ -50,9 +53,16 @@ impl Symbol {
             S::BNBUSDT => "bnbusdt",
         }
     }
+
+    /// Asset prices and notionals of the symbol are expressed in
+    pub fn quote_asset(&self) -> Asset {
+        match self {
+            Symbol::BTCUSDT | Symbol::ETHUSDT | Symbol::SOLUSDT | Symbol::BNBUSDT => Asset::USDT,
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize, Display)]
+#[derive(Debug, Copy, Clone, Deserialize, Serialize, Display, Enum)]
 #[serde(rename_all = "UPPERCASE")]
 #[display(rename_all = "UPPERCASE")]
 pub enum Side {
@@ -73,3 +83,17 @@ pub enum TimeInForce {
     #[serde(rename = "IOC")]
     ImmediateOrCancel,
 }
+
+/// Binance `selfTradePreventionMode`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize, Display)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[display(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SelfTradePreventionMode {
+    None,
+    ExpireTaker,
+    /// Quoting both sides, our own bid and ask may cross each other. Expiring the maker
+    /// cancels the older resting quote and keeps the fresh one, priced off the latest book.
+    #[default]
+    ExpireMaker,
+    ExpireBoth,
+}
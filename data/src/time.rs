@@ -0,0 +1,119 @@
+//! Timestamps tagged with the clock that produced them. The exchange and local clocks drift
+//! apart, subtracting one from the other measures latency plus that offset, so it has to be
+//! asked for explicitly.
+
+use chrono::{DateTime, Duration, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Sub};
+
+/// Time stamped by the exchange, e.g. the event and transaction times of a payload
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display,
+)]
+#[serde(transparent)]
+pub struct ExchangeTime(#[serde(with = "chrono::serde::ts_milliseconds")] DateTime<Utc>);
+
+/// Time read from the local clock, e.g. when a payload was received
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
+pub struct LocalTime(DateTime<Utc>);
+
+impl ExchangeTime {
+    pub fn from_millis(ms: i64) -> Option<Self> {
+        DateTime::from_timestamp_millis(ms).map(Self)
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    pub fn timestamp_millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        Self(Utc::now())
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    pub fn timestamp_millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+
+    /// Local receipt time minus exchange time, one-way latency skewed by the clock offset
+    pub fn skewed_since(&self, xchg: ExchangeTime) -> Duration {
+        self.0 - xchg.0
+    }
+}
+
+impl From<DateTime<Utc>> for ExchangeTime {
+    fn from(ts: DateTime<Utc>) -> Self {
+        Self(ts)
+    }
+}
+
+impl From<DateTime<Utc>> for LocalTime {
+    fn from(ts: DateTime<Utc>) -> Self {
+        Self(ts)
+    }
+}
+
+macro_rules! impl_clock_arithmetic {
+    ($clock:ty) => {
+        impl Sub for $clock {
+            type Output = Duration;
+
+            fn sub(self, rhs: Self) -> Duration {
+                self.0 - rhs.0
+            }
+        }
+
+        impl Add<Duration> for $clock {
+            type Output = Self;
+
+            fn add(self, rhs: Duration) -> Self {
+                Self(self.0 + rhs)
+            }
+        }
+
+        impl Sub<Duration> for $clock {
+            type Output = Self;
+
+            fn sub(self, rhs: Duration) -> Self {
+                Self(self.0 - rhs)
+            }
+        }
+    };
+}
+
+impl_clock_arithmetic!(ExchangeTime);
+impl_clock_arithmetic!(LocalTime);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exchange_time_serde() {
+        let ts: ExchangeTime = serde_json::from_str("1568879465651").unwrap();
+        assert_eq!(ts.timestamp_millis(), 1568879465651);
+        assert_eq!(serde_json::to_string(&ts).unwrap(), "1568879465651");
+    }
+
+    #[test]
+    fn test_same_clock_arithmetic() {
+        let xchg = ExchangeTime::from_millis(1_000).unwrap();
+        assert_eq!(
+            xchg + Duration::milliseconds(250) - xchg,
+            Duration::milliseconds(250)
+        );
+
+        let received = LocalTime::from(xchg.as_datetime() + Duration::milliseconds(40));
+        assert_eq!(received.skewed_since(xchg), Duration::milliseconds(40));
+    }
+}
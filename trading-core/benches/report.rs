@@ -0,0 +1,75 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use data::binance::market::Depth;
+use data::order::Symbol;
+use std::hint::black_box;
+use trading_core::OrderBook;
+use trading_core::engine::SymbolStates;
+use trading_core::report::SymbolReport;
+use trading_core::shared::state_channel;
+
+const BOOK_LEVELS: u64 = 500;
+
+/// States of two symbols with `BOOK_LEVELS` one-cent levels around 100.00 on each side
+fn seeded_states() -> SymbolStates {
+    let side = |levels: Vec<String>| format!("[{}]", levels.join(","));
+    let bids = (0..BOOK_LEVELS)
+        .map(|i| {
+            format!(
+                r#"["{}.{:02}","{}"]"#,
+                (9_999 - i) / 100,
+                (9_999 - i) % 100,
+                1 + i % 7
+            )
+        })
+        .collect();
+    let asks = (0..BOOK_LEVELS)
+        .map(|i| {
+            format!(
+                r#"["{}.{:02}","{}"]"#,
+                (10_001 + i) / 100,
+                (10_001 + i) % 100,
+                1 + i % 5
+            )
+        })
+        .collect();
+    let json = format!(
+        r#"{{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"SOLUSDT","U":1,"u":1,"pu":0,"b":{},"a":{}}}"#,
+        side(bids),
+        side(asks),
+    );
+    let depth: Depth = serde_json::from_str(&json).expect("depth parse failed");
+
+    let mut states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+    for state in states.iter_mut() {
+        let mut ob = OrderBook::new(state.symbol);
+        ob.extend(&depth);
+        state.apply_buffered(ob).expect("nothing is buffered");
+    }
+    states
+}
+
+/// What the event loop pays per report tick against what the report task pays to render it
+fn bench_report(c: &mut Criterion) {
+    let states = seeded_states();
+    let mut group = c.benchmark_group("report");
+
+    group.bench_function("publish", |b| {
+        let (publisher, _observer) = state_channel();
+        b.iter(|| publisher.publish(black_box(&states)));
+    });
+
+    group.bench_function("render", |b| {
+        let symbols: Vec<SymbolReport> = states.iter().map(SymbolReport::from).collect();
+        b.iter(|| {
+            for report in black_box(&symbols) {
+                let top = report.top.as_ref().map(ToString::to_string);
+                black_box(format!("{} {:?} {:?}", report.position, report.book, top));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_report);
+criterion_main!(benches);
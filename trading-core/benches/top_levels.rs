@@ -0,0 +1,89 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use data::binance::market::Depth;
+use data::order::Symbol;
+use std::hint::black_box;
+use trading_core::OrderBook;
+
+const TOP_N: usize = 10;
+const BOOK_LEVELS: u64 = 500;
+
+fn depth_json(update_id: u64, bids: &[(u64, u64)], asks: &[(u64, u64)]) -> String {
+    let side = |levels: &[(u64, u64)]| {
+        let levels: Vec<String> = levels
+            .iter()
+            .map(|(price, qty)| format!(r#"["{}.{:02}","{}"]"#, price / 100, price % 100, qty))
+            .collect();
+        format!("[{}]", levels.join(","))
+    };
+    format!(
+        r#"{{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"SOLUSDT","U":{},"u":{},"pu":{},"b":{},"a":{}}}"#,
+        update_id,
+        update_id,
+        update_id - 1,
+        side(bids),
+        side(asks),
+    )
+}
+
+fn parse(json: &str) -> Depth {
+    serde_json::from_str(json).expect("depth parse failed")
+}
+
+/// Book with `BOOK_LEVELS` one-cent levels around 100.00 on each side
+fn seeded_book() -> OrderBook {
+    let bids: Vec<(u64, u64)> = (0..BOOK_LEVELS).map(|i| (9_999 - i, 1 + i % 7)).collect();
+    let asks: Vec<(u64, u64)> = (0..BOOK_LEVELS).map(|i| (10_001 + i, 1 + i % 5)).collect();
+    let mut ob = OrderBook::new(Symbol::SOLUSDT);
+    ob.extend(&parse(&depth_json(1, &bids, &asks)));
+    ob
+}
+
+/// Diff frames touching a few levels each, one in five reaching into the top of the book
+fn update_stream() -> Vec<Depth> {
+    (0..1_000u64)
+        .map(|i| {
+            let offset = if i % 5 == 0 {
+                i % TOP_N as u64
+            } else {
+                20 + i * 37 % 400
+            };
+            let qty = i % 4; // zero removes the level
+            parse(&depth_json(
+                i + 2,
+                &[(9_999 - offset, qty), (9_999 - offset - 3, qty + 1)],
+                &[(10_001 + offset, qty)],
+            ))
+        })
+        .collect()
+}
+
+fn bench_top_levels(c: &mut Criterion) {
+    let updates = update_stream();
+    let mut group = c.benchmark_group("top_levels");
+
+    group.bench_with_input(BenchmarkId::new("naive", TOP_N), &updates, |b, updates| {
+        let mut ob = seeded_book();
+        b.iter(|| {
+            for depth in updates {
+                ob.extend(depth);
+                black_box(ob.top(TOP_N));
+            }
+        });
+    });
+
+    group.bench_with_input(BenchmarkId::new("cached", TOP_N), &updates, |b, updates| {
+        let mut ob = seeded_book();
+        ob.enable_top_cache(TOP_N);
+        b.iter(|| {
+            for depth in updates {
+                ob.extend(depth);
+                black_box(ob.cached_top());
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_top_levels);
+criterion_main!(benches);
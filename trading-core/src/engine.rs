@@ -1,22 +1,73 @@
-use chrono::{DateTime, Duration, Utc};
-use rust_decimal::Decimal;
+use chrono::Duration;
+use enum_map::EnumMap;
+use rust_decimal::{Decimal, dec};
 use rustc_hash::{FxBuildHasher, FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use uuid::Uuid;
 
 use crate::{
-    error::{Result as TradingCoreResult, TradingCoreError},
+    client_id::ClientIdGenerator,
+    error::{DepthGapError, Result as TradingCoreResult, TradingCoreError},
+    flow::TradeFlow,
+    latency::{LatencyStats, ProbeStats, TradeLatency},
     models::*,
+    spread::{SpreadHistory, SpreadStats},
+    strategy::{Quotes, Strategy},
 };
 use data::{
+    DataError,
     binance::{
-        account::OrderTradeUpdateEvent,
-        market::{BookTicker, Level},
+        account::{AccountUpdateEvent, OrderTradeUpdateEvent},
+        market::MarkPrice,
+        market::{BookTicker, Depth, Level, PartialDepth},
+        response::{BalanceResp, OrderSuccessResp, PercentPriceFilter},
     },
+    config::{DebounceThreshold, RiskConfig, StrategyConfig},
     order::*,
+    time::{ExchangeTime, LocalTime},
 };
-use tracing::debug;
+use tracing::{debug, error, info, warn};
 
-type BboPair = (Level, Level);
+const SPREAD_HISTORY_CAPACITY: usize = 4096;
+const SPREAD_HISTORY_HORIZON: Duration = Duration::minutes(10);
+/// Completed order ids kept to tell late updates of our own orders from untracked ones
+const HIST_ORDERS_CAPACITY: usize = 16_384;
+const HIST_ORDERS_HORIZON: Duration = Duration::hours(6);
+/// Smoothing factor of the rolling mid, roughly a window of 100 book ticker updates
+const REFERENCE_MID_ALPHA: Decimal = dec!(0.01);
+
+/// What happened to a depth update handed to [`State::on_depth`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthOutcome {
+    /// Applied to the local order book
+    Applied,
+    /// Kept until the order book snapshot arrives
+    Buffered,
+    /// The update does not chain, the order book was dropped and needs a new snapshot
+    GapDetected,
+}
+
+/// Rough place of a resting order in the queue of its price level, see [`State::queue_position`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    /// Quantity resting at the price when the order was placed, by the local book
+    pub initial_ahead: Decimal,
+    /// What is left of it after the trades seen at the price since. Cancellations ahead are not
+    /// visible, the order is likely further up than this.
+    pub ahead: Decimal,
+}
+
+/// Warm-start part of a [`State`], see [`State::export`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub symbol: Symbol,
+    pub active_orders: Vec<OrderSnapshot>,
+    /// Ids already used, kept so that they are still refused after a restart
+    pub hist_orders: Vec<Uuid>,
+    pub pnl: ProfitAndLoss,
+    pub turnover: Decimal,
+}
 
 #[derive(Debug)]
 pub struct State {
@@ -25,24 +76,90 @@ pub struct State {
     // best-available ask & bid
     pub bbo_level: Option<BboPair>, // (bid_level, ask_level)
 
+    // local receipt time of the last book ticker
+    bbo_received_at: Option<LocalTime>,
+
+    // exponential moving average of the bbo mid, reference for the price band
+    reference_mid: Option<Decimal>,
+
+    // external fair value to quote around instead of the book mid
+    fair_value: Option<Decimal>,
+
+    // latest mark price and the exchange band quotes must keep to around it
+    mark_price: Option<Decimal>,
+    percent_price: Option<PercentPriceFilter>,
+
+    // recent top of book changes, for spread and volatility statistics
+    spread_history: SpreadHistory,
+
+    // aggressor volume of recent public trades
+    trade_flow: TradeFlow,
+
     // local order book
     pub order_book: Option<OrderBook>,
 
+    // depth updates received before the order book snapshot
+    depth_buffer: Vec<Depth>,
+
+    // the book was kept across a market feed reconnect, the next depth update decides if it chains
+    resuming_book: bool,
+
+    // an order book snapshot is in flight, and failed snapshots in a row for the retry delay
+    snapshot_pending: bool,
+    snapshot_attempt: u32,
+
     // orders that may still receive updates
     active_orders: FxHashMap<Uuid, Order>,
 
+    // queue ahead of the active limit orders, estimated where the book was known at placement
+    queue_positions: FxHashMap<Uuid, QueuePosition>,
+
     // TODO: add a buffer for handling rejected orders
 
     // orders filled/cancelled/failed to sent (life ended)
-    hist_orders: FxHashSet<Uuid>,
+    hist_orders: CompletedOrders,
 
     pub pnl: ProfitAndLoss,
 
-    start_time: DateTime<Utc>,
+    // orders placed outside the bot, cumulative filled qty and average price while they are open
+    external_orders: FxHashMap<Uuid, (Decimal, Decimal)>,
+
+    // fills of the external orders, kept apart from the bot's own position
+    external_pnl: ProfitAndLoss,
+
+    // local time of the last fill on each side, for the re-quote cool-down
+    last_fill: EnumMap<Side, Option<LocalTime>>,
+
+    // best bid and ask prices the last quotes were made against, for the re-quote debounce
+    last_quoted: Option<((Decimal, Decimal), LocalTime)>,
+
+    // a fill asked for new quotes ahead of the send tick
+    requote_pending: bool,
+
+    // cross wallet balance per asset, seeded from REST then kept by account updates
+    balances: FxHashMap<Asset, Decimal>,
+
+    start_time: LocalTime,
 
     // total traded amount in USDT
     // TODO: deprecate in the future
     turnover: Decimal,
+
+    pub risk: RiskConfig,
+
+    pub strategy: StrategyConfig,
+
+    pub client_ids: ClientIdGenerator,
+
+    pub trade_latency: TradeLatency,
+
+    // order sent to its first update of any kind, a marketable order may open with a fill
+    placement_latency: LatencyStats,
+
+    // IOC probes in flight, when the last one was sent, and what came of them
+    probes: FxHashSet<Uuid>,
+    last_probe: Option<LocalTime>,
+    probe_stats: ProbeStats,
 }
 
 impl State {
@@ -50,17 +167,94 @@ impl State {
         Self {
             symbol,
             bbo_level: None,
+            bbo_received_at: None,
+            reference_mid: None,
+            fair_value: None,
+            mark_price: None,
+            percent_price: None,
+            spread_history: SpreadHistory::new(SPREAD_HISTORY_CAPACITY, SPREAD_HISTORY_HORIZON),
+            trade_flow: TradeFlow::default(),
             order_book: None,
+            depth_buffer: Vec::with_capacity(8),
+            resuming_book: false,
+            snapshot_pending: false,
+            snapshot_attempt: 0,
             active_orders: FxHashMap::with_capacity_and_hasher(128, FxBuildHasher),
-            hist_orders: FxHashSet::with_capacity_and_hasher(1024, FxBuildHasher),
+            queue_positions: FxHashMap::with_capacity_and_hasher(128, FxBuildHasher),
+            hist_orders: CompletedOrders::new(HIST_ORDERS_CAPACITY, HIST_ORDERS_HORIZON),
             // TODO: construct from init pos
             pnl: ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO),
-            start_time: Utc::now(),
+            external_orders: FxHashMap::default(),
+            external_pnl: ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO),
+            last_fill: EnumMap::default(),
+            last_quoted: None,
+            requote_pending: false,
+            balances: FxHashMap::default(),
+            start_time: LocalTime::now(),
             turnover: Decimal::ZERO,
+            risk: RiskConfig::default(),
+            strategy: StrategyConfig::default(),
+            client_ids: ClientIdGenerator::default(),
+            trade_latency: TradeLatency::default(),
+            placement_latency: LatencyStats::default(),
+            probes: FxHashSet::default(),
+            last_probe: None,
+            probe_stats: ProbeStats::default(),
+        }
+    }
+
+    /// Orders, position, PnL and turnover, enough to resume after a restart. Market data is left
+    /// out, it is stale by then anyway.
+    pub fn export(&self) -> StateSnapshot {
+        StateSnapshot {
+            symbol: self.symbol,
+            active_orders: self
+                .active_orders
+                .values()
+                .map(OrderSnapshot::from)
+                .collect(),
+            hist_orders: self.hist_orders.iter().collect(),
+            pnl: self.pnl,
+            turnover: self.turnover,
+        }
+    }
+
+    /// Resume from a snapshot of the same symbol, replacing the orders, PnL and turnover. The
+    /// exchange may have moved on since it was taken, reconcile with
+    /// [`State::reconcile_after_gap`] before trading on it.
+    pub fn import(&mut self, snapshot: StateSnapshot) -> TradingCoreResult<()> {
+        if snapshot.symbol != self.symbol {
+            return Err(DataError::BadDefinition {
+                reason: "State snapshot of another symbol",
+            }
+            .into());
+        }
+        self.active_orders = snapshot
+            .active_orders
+            .into_iter()
+            .map(Order::from)
+            .map(|order| (order.client_order_id(), order))
+            .collect();
+        // completion times are not kept, the imported ids age from now
+        let now = LocalTime::now();
+        self.hist_orders = CompletedOrders::new(HIST_ORDERS_CAPACITY, HIST_ORDERS_HORIZON);
+        for id in snapshot.hist_orders {
+            self.hist_orders.insert(id, now);
         }
+        // the books at placement are gone, so are the estimates
+        self.queue_positions.clear();
+        self.pnl = snapshot.pnl;
+        self.turnover = snapshot.turnover;
+        info!(
+            symbol = %self.symbol,
+            active_orders = self.active_orders.len(),
+            position = %self.get_position(),
+            "State imported"
+        );
+        Ok(())
     }
 
-    pub fn start_time(&self) -> DateTime<Utc> {
+    pub fn start_time(&self) -> LocalTime {
         self.start_time
     }
 
@@ -72,23 +266,231 @@ impl State {
         self.pnl.position()
     }
 
+    /// Position built by orders placed outside the bot, not part of [`State::get_position`]
+    pub fn external_position(&self) -> Decimal {
+        self.external_pnl.position()
+    }
+
+    pub fn external_pnl(&self) -> &ProfitAndLoss {
+        &self.external_pnl
+    }
+
+    /// External orders still open
+    pub fn external_order_count(&self) -> usize {
+        self.external_orders.len()
+    }
+
+    /// USDT cross wallet balance, unknown until seeded or the first account update
+    pub fn available_balance(&self) -> Option<Decimal> {
+        self.balance(Asset::USDT)
+    }
+
+    /// Cross wallet balance of `asset`, `None` if the exchange never reported it
+    pub fn balance(&self, asset: Asset) -> Option<Decimal> {
+        self.balances.get(&asset).copied()
+    }
+
+    /// Seed the balances from `GET /fapi/v2/balance`, account updates take over from there
+    pub fn seed_balances(&mut self, balances: &[BalanceResp]) {
+        for balance in balances {
+            self.set_balance(balance.asset(), balance.cross_wallet_balance());
+        }
+    }
+
+    pub fn on_account_update(&mut self, update_event: &AccountUpdateEvent) {
+        for balance in update_event.balances() {
+            self.set_balance(balance.asset(), balance.cross_wallet_balance());
+        }
+    }
+
+    fn set_balance(&mut self, asset: Asset, balance: Decimal) {
+        if asset != Asset::Unknown {
+            self.balances.insert(asset, balance);
+        }
+    }
+
     // Order book management
     pub fn remove_order_book(&mut self) {
         self.order_book = None;
+        self.depth_buffer.clear();
+        self.resuming_book = false;
+    }
+
+    /// Keep the order book across a market feed reconnect rather than taking a new snapshot.
+    /// A brief outage may have missed no update: the next depth update is applied if it chains
+    /// and drops the book otherwise, see [`State::on_depth`]. Returns whether there was a book.
+    pub fn resume_order_book(&mut self) -> bool {
+        self.resuming_book = self.order_book.is_some();
+        self.resuming_book
+    }
+
+    /// The book is kept from before a reconnect and not yet confirmed by a depth update,
+    /// it must not be quoted against
+    pub fn is_book_resuming(&self) -> bool {
+        self.resuming_book
     }
 
     pub fn has_order_book(&self) -> bool {
         self.order_book.is_some()
     }
 
+    pub fn on_depth(&mut self, depth: Depth) -> DepthOutcome {
+        // either outcome below settles a resumed book
+        let resuming = std::mem::take(&mut self.resuming_book);
+        let Some(ob) = &mut self.order_book else {
+            // Order book not constructed yet
+            self.depth_buffer.push(depth);
+            info!(buffer_size = %self.depth_buffer.len(), "Depth pushed to buffer");
+            return DepthOutcome::Buffered;
+        };
+
+        if let Err(gap) = ob.apply_depth(&depth) {
+            warn!(
+                %gap,
+                first_update_id = %depth.first_update_id(),
+                final_update_id = %depth.final_update_id(),
+                "Gap detected in depth updates"
+            );
+            self.remove_order_book();
+            return DepthOutcome::GapDetected;
+        }
+        if !ob.is_healthy() {
+            error!(ob = %ob.book_top(5), "Order book crossed or holds empty levels");
+            self.remove_order_book();
+            return DepthOutcome::GapDetected;
+        }
+        if resuming {
+            info!(final_update_id = %depth.final_update_id(), "Order book resumed after reconnect");
+        }
+        if self.bbo_level.is_some() && ob.get_bbo() != self.bbo_level {
+            warn!(
+                ob_bbo = ?ob.get_bbo(),
+                bbo = ?self.bbo_level,
+                "Orderbook and BBO level do not match"
+            )
+        }
+        DepthOutcome::Applied
+    }
+
+    /// Replace the order book with a partial depth frame, frames older than the book are
+    /// dropped. Only the levels of the frame are known, no snapshot is involved.
+    pub fn on_partial_depth(&mut self, partial: PartialDepth) {
+        if self
+            .order_book
+            .as_ref()
+            .is_some_and(|ob| ob.last_update_id() >= partial.final_update_id())
+        {
+            debug!(
+                final_update_id = partial.final_update_id(),
+                "Stale partial depth dropped"
+            );
+            return;
+        }
+        let ob = OrderBook::from_partial_depth(&partial);
+        if !ob.is_healthy() {
+            error!(ob = %ob.book_top(5), "Partial depth crossed or holds empty levels");
+            return;
+        }
+        self.order_book = Some(ob);
+        // a whole new book, nothing left to confirm
+        self.resuming_book = false;
+    }
+
+    /// Install a snapshot and replay the buffered depth updates on top of it. On a gap the
+    /// snapshot is dropped, the snapshot claim is kept for the next one.
+    pub fn apply_buffered(&mut self, mut ob: OrderBook) -> Result<(), DepthGapError> {
+        for depth in self.depth_buffer.drain(..) {
+            if let Err(gap) = ob.apply_depth(&depth) {
+                warn!(symbol = %self.symbol, %gap, "Buffered depth does not chain onto the snapshot");
+                return Err(gap);
+            }
+        }
+        ob.debug_assert_book_ok();
+        info!(symbol = %self.symbol, last_update_id = %ob.last_update_id(), "Order book ready");
+        self.order_book = Some(ob);
+        self.snapshot_pending = false;
+        self.snapshot_attempt = 0;
+        Ok(())
+    }
+
+    /// Claim the order book snapshot of this symbol, false if one is already in flight. The
+    /// claim holds until [`State::apply_buffered`] installs a book.
+    pub fn request_snapshot(&mut self) -> bool {
+        !std::mem::replace(&mut self.snapshot_pending, true)
+    }
+
+    /// Count a failed snapshot, returns the failures in a row before it for the retry delay.
+    /// The claim is kept for the retry.
+    pub fn on_snapshot_failed(&mut self) -> u32 {
+        let attempt = self.snapshot_attempt;
+        self.snapshot_attempt += 1;
+        attempt
+    }
+
     // Active order tracking
-    pub fn register_order(&mut self, order: Order) {
-        self.active_orders.insert(order.client_order_id(), order);
+    /// Track a new order, refusing an id that is or was already in use since overwriting would
+    /// lose track of the original order, and any order past `risk.max_active_orders`. Refused
+    /// orders must not be sent.
+    pub fn register_order(&mut self, order: Order) -> bool {
+        let client_id = order.client_order_id();
+        if self.active_orders.contains_key(&client_id) || self.hist_orders.contains(&client_id) {
+            error!(%client_id, "Duplicate client order id, order refused");
+            return false;
+        }
+        let active_orders = self.active_orders.len();
+        if self
+            .risk
+            .max_active_orders
+            .is_some_and(|max_active_orders| active_orders >= max_active_orders)
+        {
+            warn!(%client_id, active_orders, "Active order limit reached, order refused");
+            return false;
+        }
+        if let Some(ahead) = self.level_qty(*order.side(), *order.orig_price()) {
+            if *order.kind() == OrderKind::Limit {
+                let position = QueuePosition {
+                    initial_ahead: ahead,
+                    ahead,
+                };
+                self.queue_positions.insert(client_id, position);
+            }
+        }
+        self.active_orders.insert(client_id, order);
+        true
+    }
+
+    /// Quantity resting at `price` on `side` by the local book, or by the BBO without a book.
+    /// `None` if neither covers the price.
+    fn level_qty(&self, side: Side, price: Decimal) -> Option<Decimal> {
+        if let Some(ob) = &self.order_book {
+            return Some(ob.level_qty(side, price));
+        }
+        let (bid, ask) = self.bbo_level?;
+        let touch = match side {
+            Side::Buy => bid,
+            Side::Sell => ask,
+        };
+        (touch.price == price).then_some(touch.quantity)
+    }
+
+    /// Estimated queue ahead of an active limit order, `None` if it was placed without a view of
+    /// its price level
+    pub fn queue_position(&self, id: &Uuid) -> Option<QueuePosition> {
+        self.queue_positions.get(id).copied()
+    }
+
+    /// Track new orders, dropping the refused ones from `orders`
+    pub fn register_orders(&mut self, orders: &mut Quotes) {
+        orders.retain(|order| self.register_order(*order));
+    }
+
+    pub fn active_order_count(&self) -> usize {
+        self.active_orders.len()
     }
 
-    pub fn register_orders(&mut self, orders: &[Order]) {
-        self.active_orders
-            .extend(orders.iter().copied().map(|o| (o.client_order_id(), o)));
+    /// Completed order ids still remembered, bounded by count and age
+    pub fn completed_order_count(&self) -> usize {
+        self.hist_orders.len()
     }
 
     pub fn get_active_order(&self, id: &Uuid) -> Option<&Order> {
@@ -101,25 +503,331 @@ impl State {
 
     pub fn complete_order(&mut self, id: Uuid) {
         // TODO: add warnings for duplicate
-        if self.active_orders.remove(&id).is_some() {
-            self.hist_orders.insert(id);
+        if let Some(order) = self.active_orders.remove(&id) {
+            let now = LocalTime::now();
+            if self.probes.remove(&id) {
+                // without any update the probe never made it to the exchange
+                let latency = order.status().is_some().then(|| now - order.start_ts());
+                self.probe_stats
+                    .on_completed(!order.filled_qty().is_zero(), latency);
+            }
+            self.hist_orders.insert(id, now);
         }
+        self.queue_positions.remove(&id);
     }
 
+    /// A reduce-only order found nothing to reduce: a fill already flattened the position.
+    /// The order never rested, so it is completed. The position itself is left to the fill,
+    /// which may still be in flight on the account stream and would be counted twice.
+    pub fn on_reduce_only_rejected(&mut self, client_id: Uuid) {
+        self.complete_order(client_id);
+        if self.get_position().is_zero() {
+            debug!(%client_id, "Reduce-only order rejected, position already flat");
+        } else {
+            info!(
+                %client_id,
+                position = %self.get_position(),
+                "Reduce-only order rejected, flattening fill not received yet"
+            );
+        }
+    }
+
+    /// Total quoted value of the active orders in USDT
+    pub fn open_notional(&self) -> Decimal {
+        self.active_orders.values().map(Order::notional).sum()
+    }
+
+    /// Clamp oversized quotes and drop those that would breach the resting notional cap
+    pub fn apply_notional_limits(&self, quotes: &mut Quotes) {
+        if let Some(max_order_notional) = self.risk.max_order_notional {
+            quotes.retain(|order| {
+                let notional = order.notional();
+                if notional <= max_order_notional {
+                    return true;
+                }
+                let kept = order.clamp_notional(max_order_notional);
+                warn!(
+                    client_order_id = %order.client_order_id(),
+                    %notional,
+                    %max_order_notional,
+                    kept,
+                    "Order notional clamped"
+                );
+                kept
+            });
+        }
+
+        if let Some(max_open_notional) = self.risk.max_open_notional {
+            let mut open_notional = self.open_notional();
+            quotes.retain(|order| {
+                let notional = order.notional();
+                if open_notional + notional > max_open_notional {
+                    warn!(
+                        client_order_id = %order.client_order_id(),
+                        %notional,
+                        %open_notional,
+                        %max_open_notional,
+                        "Order refused, open notional limit reached"
+                    );
+                    false
+                } else {
+                    open_notional += notional;
+                    true
+                }
+            });
+        }
+    }
+
+    /// Orders without an update for `max_age`, oldest first and by id on a tie, so that they are
+    /// cancelled in a reproducible order with the longest silent first
     pub fn stale_order_ids(&self, max_age: Duration) -> Vec<Uuid> {
-        let now = Utc::now();
+        let now = LocalTime::now();
+
+        let mut stale: Vec<(LocalTime, Uuid)> = self
+            .active_orders
+            .iter()
+            .filter(|(_, order)| now - order.last_update_ts() >= max_age)
+            .map(|(id, order)| (order.last_update_ts(), *id))
+            .collect();
+        stale.sort_unstable();
+        stale.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Resolve a silent order with its state queried from the exchange, `None` if the exchange
+    /// no longer knows it. Returns whether the order was completed.
+    pub fn resolve_queried_order(
+        &mut self,
+        client_id: Uuid,
+        queried: Option<&OrderSuccessResp>,
+    ) -> bool {
+        let status = queried.map(OrderSuccessResp::status);
+        match status {
+            Some(OrderStatus::New | OrderStatus::PartiallyFilled) => false,
+            _ => {
+                warn!(%client_id, status = ?status, reason = "queried", "Order removed");
+                self.complete_order(client_id);
+                true
+            }
+        }
+    }
+
+    /// Resolve what was missed while the account stream was down: active orders older than
+    /// `grace` that the exchange no longer has are completed, returns their ids
+    pub fn reconcile_after_gap(
+        &mut self,
+        open_orders: &[OrderSuccessResp],
+        position: Option<Decimal>,
+        grace: Duration,
+    ) -> Vec<Uuid> {
+        let now = LocalTime::now();
+        let open_ids: FxHashSet<Uuid> = open_orders
+            .iter()
+            .map(OrderSuccessResp::client_order_id)
+            .collect();
 
-        self.active_orders
+        let missing: Vec<Uuid> = self
+            .active_orders
             .iter()
-            .filter(|(_, order)| now.signed_duration_since(order.last_update_ts()) >= max_age)
+            .filter(|(id, order)| !open_ids.contains(*id) && now - order.start_ts() >= grace)
             .map(|(id, _)| *id)
-            .collect()
+            .collect();
+
+        for client_id in &missing {
+            warn!(%client_id, reason = "reconciled", "Order removed");
+            self.complete_order(*client_id);
+        }
+
+        if let Some(position) = position {
+            if position != self.get_position() {
+                warn!(
+                    exchange_position = %position,
+                    local_position = %self.get_position(),
+                    "Position mismatch after account stream gap"
+                );
+            }
+        }
+        missing
+    }
+
+    /// Last word on the position before exiting, `false` if the exchange disagrees by more than
+    /// `tolerance` or reported no position for the symbol at all
+    pub fn check_final_position(
+        &self,
+        exchange_position: Option<Decimal>,
+        tolerance: Decimal,
+    ) -> bool {
+        let local_position = self.get_position();
+        match exchange_position {
+            Some(position) if (position - local_position).abs() <= tolerance => {
+                info!(%position, "Final position reconciled");
+                true
+            }
+            Some(position) => {
+                error!(
+                    exchange_position = %position,
+                    local_position = %local_position,
+                    %tolerance,
+                    "Final position mismatch at shutdown"
+                );
+                false
+            }
+            None => {
+                error!(local_position = %local_position, "Final position missing from exchange");
+                false
+            }
+        }
     }
 
     pub fn on_book_ticker_received(&mut self, book_ticker: BookTicker) {
         let bid_level = Level::from((book_ticker.bid_price(), book_ticker.bid_qty()));
         let ask_level = Level::from((book_ticker.ask_price(), book_ticker.ask_qty()));
         self.bbo_level = Some((bid_level, ask_level));
+        self.bbo_received_at = Some(LocalTime::now());
+        self.spread_history.push(
+            book_ticker.transaction_time(),
+            bid_level.price,
+            ask_level.price,
+        );
+
+        let mid = (bid_level.price + ask_level.price) / Decimal::TWO;
+        self.reference_mid = Some(match self.reference_mid {
+            Some(prev) => prev + (mid - prev) * REFERENCE_MID_ALPHA,
+            None => mid,
+        });
+    }
+
+    /// Spread and realized volatility over the last `window` of top of book changes
+    pub fn spread_stats(&self, window: Duration) -> Option<SpreadStats> {
+        self.spread_history.stats(window)
+    }
+
+    pub fn fair_value(&self) -> Option<Decimal> {
+        self.fair_value
+    }
+
+    /// Center the quotes on an externally supplied fair value, `None` falls back to the book mid
+    pub fn set_fair_value(&mut self, fair_value: Option<Decimal>) {
+        self.fair_value = fair_value;
+    }
+
+    /// Best bid and ask from the book ticker, or from the order book when the book ticker
+    /// stream is not subscribed or has not ticked yet
+    pub fn best_bid_ask(&self) -> Option<BboPair> {
+        self.bbo_level
+            .or_else(|| self.order_book.as_ref().and_then(OrderBook::get_bbo))
+    }
+
+    /// Whether the book ticker is older than the configured maximum age at `now`. Quotes taken
+    /// from the order book alone are never considered stale here.
+    pub fn is_bbo_stale(&self, now: LocalTime) -> bool {
+        match (self.risk.max_bbo_age_ms, self.bbo_received_at) {
+            (Some(max_age_ms), Some(received_at)) => {
+                now - received_at > Duration::milliseconds(max_age_ms as i64)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn reference_mid(&self) -> Option<Decimal> {
+        self.reference_mid
+    }
+
+    /// Whether every quote is within the configured band around the rolling mid.
+    /// A violation most likely means bad market data, so the whole cycle should be skipped.
+    pub fn check_price_band(&self, quotes: &Quotes) -> bool {
+        let (Some(max_deviation_pct), Some(reference)) =
+            (self.risk.max_price_deviation_pct, self.reference_mid)
+        else {
+            return true;
+        };
+        let max_deviation = reference * max_deviation_pct / Decimal::ONE_HUNDRED;
+
+        for order in quotes {
+            if (*order.orig_price() - reference).abs() > max_deviation {
+                error!(
+                    client_order_id = %order.client_order_id(),
+                    price = %order.orig_price(),
+                    %reference,
+                    %max_deviation_pct,
+                    "Quote outside price band"
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Count a public trade into the trade flow, and against the queue ahead of our orders on the
+    /// passive side of it
+    pub fn on_trade(&mut self, ts: ExchangeTime, price: Decimal, qty: Decimal, aggressor: Side) {
+        self.trade_flow.push(ts, qty, aggressor);
+
+        for (id, position) in self.queue_positions.iter_mut() {
+            let Some(order) = self.active_orders.get(id) else {
+                continue;
+            };
+            let order_price = *order.orig_price();
+            // a trade through the order's price took the whole level
+            let (at_level, through) = match (order.side(), aggressor) {
+                (Side::Buy, Side::Sell) => (price == order_price, price < order_price),
+                (Side::Sell, Side::Buy) => (price == order_price, price > order_price),
+                _ => continue,
+            };
+            if through {
+                position.ahead = Decimal::ZERO;
+            } else if at_level {
+                position.ahead = (position.ahead - qty).max(Decimal::ZERO);
+            }
+        }
+    }
+
+    pub fn trade_flow(&self) -> &TradeFlow {
+        &self.trade_flow
+    }
+
+    /// Aggressor buy minus sell volume over the last `window` of trades, positive
+    /// while the asks are being lifted
+    pub fn trade_flow_imbalance(&self, window: Duration) -> Decimal {
+        self.trade_flow.imbalance(window)
+    }
+
+    pub fn on_mark_price(&mut self, mark_price: &MarkPrice) {
+        self.mark_price = Some(mark_price.mark_price());
+    }
+
+    /// `PERCENT_PRICE` filter of the symbol from the exchange info
+    pub fn set_percent_price(&mut self, filter: Option<PercentPriceFilter>) {
+        self.percent_price = filter;
+    }
+
+    /// Drop quotes the `PERCENT_PRICE` filter would reject at the latest mark price: bids above
+    /// `mark * multiplier_up` and asks below `mark * multiplier_down`. Nothing is dropped until
+    /// both the filter and a mark price are known.
+    pub fn apply_percent_price(&self, quotes: &mut Quotes) {
+        let (Some(filter), Some(mark)) = (self.percent_price, self.mark_price) else {
+            return;
+        };
+        let (max_bid, min_ask) = (
+            mark * filter.multiplier_up(),
+            mark * filter.multiplier_down(),
+        );
+        quotes.retain(|order| {
+            let price = *order.orig_price();
+            let in_band = match order.side() {
+                Side::Buy => price <= max_bid,
+                Side::Sell => price >= min_ask,
+            };
+            if !in_band {
+                warn!(
+                    client_order_id = %order.client_order_id(),
+                    side = %order.side(),
+                    %price,
+                    %mark,
+                    "Quote outside PERCENT_PRICE band, skipped"
+                );
+            }
+            in_band
+        });
     }
 
     pub fn on_update_received(
@@ -130,15 +838,33 @@ impl State {
         use data::binance::account::ExecutionType as E;
         let client_id = update_event.client_order_id();
 
-        let order = self.active_orders.get_mut(&client_id).ok_or_else(|| {
-            // TODO: more robust
+        let Some(order) = self.active_orders.get_mut(&client_id) else {
             if self.hist_orders.contains(&client_id) {
-                Err::Unknown(format!("Order has been removed {}", client_id))
-            } else {
-                Err::Unknown(format!("Untracked order {}", client_id))
+                return Err(Err::Unknown(format!(
+                    "Order has been removed {}",
+                    client_id
+                )));
+            }
+            // one of ours the engine lost track of, not an external order
+            if self.client_ids.is_own(&client_id) {
+                return Err(Err::Unknown(format!("Untracked order {}", client_id)));
             }
-        })?;
+            self.on_external_update(update_event);
+            return Ok(());
+        };
 
+        // the first update is usually NEW, but an order crossing on arrival opens with TRADE
+        if order.status().is_none() {
+            self.placement_latency
+                .record(LocalTime::now() - order.start_ts());
+        }
+        // cumulative figures before this update, the fill is measured against them
+        let prev_filled = (*order.filled_qty(), *order.avg_fill_price());
+        // a fill is identified by its quantity and trade id, not by the execution type
+        let is_new_fill = !update_event.last_filled_qty().is_zero()
+            && order
+                .last_trade_id()
+                .is_none_or(|id| id < update_event.trade_id());
         order.on_update_received(update_event);
         match update_event.exec_type() {
             reason @ (E::Canceled | E::Calculated | E::Expired) => {
@@ -146,24 +872,1803 @@ impl State {
                 self.complete_order(client_id);
             }
             E::Trade => {
-                self.pnl.on_update_received(update_event);
-                self.turnover += update_event.last_filled_amount();
+                if is_new_fill {
+                    self.apply_fill(update_event, prev_filled);
+                }
                 if update_event.order_status() == OrderStatus::Filled {
                     debug!(%client_id, reason="TRADE", "Order removed");
                     self.complete_order(client_id);
                 }
             }
-            E::Amendment
+            E::Amendment => {
+                if is_new_fill {
+                    self.apply_fill(update_event, prev_filled);
+                }
                 if matches!(
                     update_event.order_status(),
                     OrderStatus::Filled | OrderStatus::Canceled
-                ) =>
-            {
-                debug!(%client_id, reason="AMENDMENT", "Order removed");
-                self.complete_order(client_id);
+                ) {
+                    debug!(%client_id, reason="AMENDMENT", "Order removed");
+                    self.complete_order(client_id);
+                }
             }
-            E::New | E::Amendment => {}
+            E::New => {}
         }
         Ok(())
     }
+
+    /// Local time from creating an order to its first update
+    pub fn placement_latency(&self) -> LatencyStats {
+        self.placement_latency
+    }
+
+    pub fn probe_stats(&self) -> ProbeStats {
+        self.probe_stats
+    }
+
+    /// IOC probe due at `now`, registered as an active order and ready to send. One at a time,
+    /// `probe.interval_ms` apart, sides alternating from the bid.
+    pub fn make_probe(&mut self, now: LocalTime) -> Option<Order> {
+        let probe = self.strategy.probe?;
+        if !self.probes.is_empty() {
+            return None;
+        }
+        if self
+            .last_probe
+            .is_some_and(|sent_at| now - sent_at < Duration::milliseconds(probe.interval_ms as i64))
+        {
+            return None;
+        }
+        let (bid, ask) = self.best_bid_ask()?;
+        let inside = self
+            .strategy
+            .tick_offset
+            .as_ref()
+            .and_then(|offset| offset.tick_size(self.symbol))
+            .map_or(Decimal::ZERO, |tick_size| {
+                tick_size * Decimal::from(probe.ticks_inside)
+            });
+        // never past the near touch, a probe there would only miss
+        let (side, price) = if self.probe_stats.sent() % 2 == 0 {
+            (Side::Buy, (ask.price - inside).max(bid.price))
+        } else {
+            (Side::Sell, (bid.price + inside).min(ask.price))
+        };
+        let order = match Order::try_new(
+            self.symbol,
+            side,
+            OrderKind::Limit,
+            price,
+            probe.qty,
+            TimeInForce::ImmediateOrCancel,
+            None,
+        ) {
+            Ok(order) => order
+                .with_client_order_id(self.client_ids.mint(self.symbol))
+                .with_stp_mode(Some(self.strategy.stp_mode)),
+            Err(err) => {
+                warn!(symbol = %self.symbol, %price, %err, "Skipping invalid probe");
+                return None;
+            }
+        };
+        if !self.register_order(order) {
+            return None;
+        }
+        self.probes.insert(order.client_order_id());
+        self.last_probe = Some(now);
+        self.probe_stats.on_sent();
+        debug!(symbol = %self.symbol, %side, %price, qty = %probe.qty, "Probe sent");
+        Some(order)
+    }
+
+    /// Fold a fill into PnL and turnover by the cumulative figures of its order, and start the
+    /// cool-down of its side
+    /// Update of an order placed outside the bot, e.g. by hand on the web UI. Its fills go to
+    /// the external view, the bot's own orders and position are left alone.
+    fn on_external_update(&mut self, update_event: &OrderTradeUpdateEvent) {
+        let client_id = update_event.client_order_id();
+        let (prev_filled_qty, prev_avg_price) = self
+            .external_orders
+            .get(&client_id)
+            .copied()
+            .unwrap_or_default();
+        let (qty, amount) = fill_delta(update_event, prev_filled_qty, prev_avg_price);
+        if !qty.is_zero() {
+            self.external_pnl.on_fill(update_event, qty, amount);
+        }
+        debug!(
+            %client_id,
+            exec_type = %update_event.exec_type(),
+            order_status = %update_event.order_status(),
+            %qty,
+            external_position = %self.external_pnl.position(),
+            "External order update"
+        );
+        match update_event.order_status() {
+            OrderStatus::New | OrderStatus::PartiallyFilled => {
+                self.external_orders.insert(
+                    client_id,
+                    (update_event.filled_qty(), update_event.avg_price()),
+                );
+            }
+            _ => {
+                self.external_orders.remove(&client_id);
+            }
+        }
+    }
+
+    fn apply_fill(
+        &mut self,
+        update_event: &OrderTradeUpdateEvent,
+        (prev_filled_qty, prev_avg_price): (Decimal, Decimal),
+    ) {
+        let (qty, amount) = fill_delta(update_event, prev_filled_qty, prev_avg_price);
+        self.pnl.on_fill(update_event, qty, amount);
+        self.turnover += amount;
+        self.last_fill[update_event.side()] = Some(LocalTime::now());
+        self.requote_pending |= self.strategy.requote_on_fill;
+    }
+
+    /// Whether `side` was filled within the configured cool-down before `now`
+    pub fn is_cooling_down(&self, side: Side, now: LocalTime) -> bool {
+        match (self.strategy.fill_cooldown_ms, self.last_fill[side]) {
+            (Some(cooldown_ms), Some(filled_at)) => {
+                now - filled_at < Duration::milliseconds(cooldown_ms as i64)
+            }
+            _ => false,
+        }
+    }
+
+    /// Quote size multiplier of the schedule windows open at `now`, the smallest where they
+    /// overlap. `None` outside every window.
+    pub fn scheduled_size_factor(&self, now: LocalTime) -> Option<Decimal> {
+        let time = now.as_datetime().time();
+        self.strategy
+            .schedule
+            .iter()
+            .filter(|window| window.contains(time))
+            .map(|window| window.size_factor)
+            .min()
+    }
+
+    /// Whether a schedule window stops quoting altogether at `now`
+    pub fn is_schedule_paused(&self, now: LocalTime) -> bool {
+        self.scheduled_size_factor(now)
+            .is_some_and(|factor| factor <= Decimal::ZERO)
+    }
+
+    /// Whether new quotes are due at `now`: the BBO moved past the debounce threshold since
+    /// the last quotes, or they were held for the maximum time. Always due without a debounce.
+    pub fn should_requote(&self, now: LocalTime) -> bool {
+        let Some(debounce) = self.strategy.debounce else {
+            return true;
+        };
+        let (Some(((quoted_bid, quoted_ask), quoted_at)), Some((bid, ask))) =
+            (self.last_quoted, self.best_bid_ask())
+        else {
+            return true;
+        };
+        if now - quoted_at >= Duration::milliseconds(debounce.max_hold_ms as i64) {
+            return true;
+        }
+
+        let threshold = match debounce.threshold {
+            DebounceThreshold::Ticks(ticks) => {
+                let tick_size = self
+                    .strategy
+                    .tick_offset
+                    .as_ref()
+                    .and_then(|offset| offset.tick_size(self.symbol));
+                match tick_size {
+                    Some(tick_size) => tick_size * Decimal::from(ticks),
+                    // no tick size to count with, better to churn than to hold forever
+                    None => return true,
+                }
+            }
+            DebounceThreshold::Bps(bps) => {
+                (quoted_bid + quoted_ask) / Decimal::TWO * bps / Decimal::from(10_000)
+            }
+        };
+        let moved = (bid.price - quoted_bid)
+            .abs()
+            .max((ask.price - quoted_ask).abs());
+        moved > threshold
+    }
+
+    /// Record the BBO the quotes sent at `now` were made against
+    pub fn mark_quoted(&mut self, now: LocalTime) {
+        self.last_quoted = self
+            .best_bid_ask()
+            .map(|(bid, ask)| ((bid.price, ask.price), now));
+    }
+
+    /// Quotes of strategy `S` due at `now`, checked against the exchange filters and
+    /// registered as active orders, ready to send. `force` skips the debounce.
+    pub fn make_quotes<S: Strategy>(
+        &mut self,
+        strategy: &S,
+        now: LocalTime,
+        force: bool,
+    ) -> Quotes {
+        if !force && !self.should_requote(now) {
+            debug!(symbol = %self.symbol, "BBO within debounce threshold, quotes held");
+            return Quotes::new();
+        }
+        self.requote_pending = false;
+        let mut quotes = strategy.generate_quotes(self.symbol, self);
+        if !self.check_price_band(&quotes) {
+            return Quotes::new();
+        }
+        self.apply_percent_price(&mut quotes);
+        self.apply_notional_limits(&mut quotes);
+        self.register_orders(&mut quotes);
+        if !quotes.is_empty() {
+            self.mark_quoted(now);
+        }
+        quotes
+    }
+
+    /// Quotes replenishing a fill seen since the last quotes, `None` if there was none or
+    /// `requote_on_fill` is off
+    pub fn requote_after_fill<S: Strategy>(
+        &mut self,
+        strategy: &S,
+        now: LocalTime,
+    ) -> Option<Quotes> {
+        if !self.requote_pending {
+            return None;
+        }
+        Some(self.make_quotes(strategy, now, true))
+    }
+}
+
+/// Ids of completed orders, oldest first, evicted past a capacity and an age. An evicted id is
+/// reported as untracked, which only matters for an update arriving hours late.
+#[derive(Debug)]
+struct CompletedOrders {
+    ids: FxHashSet<Uuid>,
+    // completion time of each id in `ids`, in completion order
+    queue: VecDeque<(LocalTime, Uuid)>,
+    capacity: usize,
+    horizon: Duration,
+}
+
+impl CompletedOrders {
+    fn new(capacity: usize, horizon: Duration) -> Self {
+        Self {
+            ids: FxHashSet::with_capacity_and_hasher(1024, FxBuildHasher),
+            queue: VecDeque::with_capacity(1024),
+            capacity,
+            horizon,
+        }
+    }
+
+    fn insert(&mut self, id: Uuid, now: LocalTime) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.queue.push_back((now, id));
+        while let Some(&(completed_at, oldest)) = self.queue.front() {
+            if self.queue.len() <= self.capacity && now - completed_at <= self.horizon {
+                break;
+            }
+            self.queue.pop_front();
+            self.ids.remove(&oldest);
+        }
+    }
+
+    fn contains(&self, id: &Uuid) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Ids oldest first
+    fn iter(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.queue.iter().map(|&(_, id)| id)
+    }
+}
+
+/// One [`State`] per traded symbol, each with its own book, orders and PnL
+pub struct SymbolStates {
+    states: EnumMap<Symbol, Option<State>>,
+}
+
+impl SymbolStates {
+    pub fn new(symbols: &[Symbol]) -> Self {
+        Self {
+            states: EnumMap::from_fn(|symbol| {
+                symbols.contains(&symbol).then(|| State::new(symbol))
+            }),
+        }
+    }
+
+    /// `None` for a symbol that is not traded
+    pub fn get(&self, symbol: Symbol) -> Option<&State> {
+        self.states[symbol].as_ref()
+    }
+
+    pub fn get_mut(&mut self, symbol: Symbol) -> Option<&mut State> {
+        self.states[symbol].as_mut()
+    }
+
+    /// Hand a depth update to the state of its symbol, `None` if the symbol is not traded. A gap
+    /// only drops the book of that symbol.
+    pub fn on_depth(&mut self, depth: Depth) -> Option<DepthOutcome> {
+        self.get_mut(depth.symbol())
+            .map(|state| state.on_depth(depth))
+    }
+
+    /// Balances are account wide, every symbol sees the same ones
+    pub fn seed_balances(&mut self, balances: &[BalanceResp]) {
+        for state in self.iter_mut() {
+            state.seed_balances(balances);
+        }
+    }
+
+    pub fn on_account_update(&mut self, update_event: &AccountUpdateEvent) {
+        for state in self.iter_mut() {
+            state.on_account_update(update_event);
+        }
+    }
+
+    /// Hand a book ticker to the state of its symbol, false if the symbol is not traded
+    pub fn on_book_ticker_received(&mut self, book_ticker: BookTicker) -> bool {
+        match self.get_mut(book_ticker.symbol()) {
+            Some(state) => {
+                state.on_book_ticker_received(book_ticker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn export(&self) -> Vec<StateSnapshot> {
+        self.iter().map(State::export).collect()
+    }
+
+    /// Import each snapshot into the state of its symbol, snapshots of untraded symbols are
+    /// skipped
+    pub fn import(&mut self, snapshots: Vec<StateSnapshot>) -> TradingCoreResult<()> {
+        for snapshot in snapshots {
+            match self.get_mut(snapshot.symbol) {
+                Some(state) => state.import(snapshot)?,
+                None => {
+                    warn!(symbol = %snapshot.symbol, "State snapshot of an untraded symbol skipped")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &State> {
+        self.states.values().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut State> {
+        self.states.values_mut().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_id::ClientIdScheme;
+    use data::binance::market::AggTrade;
+    use data::config::{ProbeConfig, QuoteDebounceConfig, QuoteWindowConfig, TickOffsetConfig};
+    use rust_decimal::dec;
+
+    fn make_order(side: Side) -> Order {
+        Order::new(
+            Symbol::SOLUSDT,
+            side,
+            OrderKind::Limit,
+            dec!(100),
+            dec!(2),
+            TimeInForce::GoodUntilCancel,
+            None,
+        )
+    }
+
+    fn make_update(
+        order: &Order,
+        exec_type: &str,
+        status: &str,
+        last_qty: &str,
+        trade_id: u64,
+    ) -> OrderTradeUpdateEvent {
+        let json = format!(
+            r#"{{
+                "e": "ORDER_TRADE_UPDATE", "E": 1568879465651, "T": 1568879465650,
+                "o": {{
+                    "s": "SOLUSDT", "c": "{}", "S": "{}", "o": "LIMIT", "f": "GTC",
+                    "q": "2", "p": "100", "ap": "100", "x": "{exec_type}", "X": "{status}",
+                    "i": 8886774, "l": "{last_qty}", "z": "{last_qty}", "L": "100", "n": "0",
+                    "T": 1568879465650, "t": {trade_id}, "m": true, "rp": "0"
+                }}
+            }}"#,
+            order.client_order_id(),
+            order.side(),
+        );
+        serde_json::from_str(&json).expect("Failed to parse order update")
+    }
+
+    fn make_book_ticker(bid: &str, ask: &str) -> BookTicker {
+        make_symbol_book_ticker(Symbol::SOLUSDT, bid, ask)
+    }
+
+    fn make_symbol_book_ticker(symbol: Symbol, bid: &str, ask: &str) -> BookTicker {
+        let json = format!(
+            r#"{{
+                "e": "bookTicker", "u": 400900217, "E": 1568014460893, "T": 1568014460891,
+                "s": "{symbol}", "b": "{bid}", "B": "31.21", "a": "{ask}", "A": "40.66"
+            }}"#
+        );
+        serde_json::from_str(&json).expect("Failed to parse book ticker")
+    }
+
+    fn make_account_update(asset: &str, cross_wallet: &str) -> AccountUpdateEvent {
+        let json = format!(
+            r#"{{
+                "e": "ACCOUNT_UPDATE", "E": 1564745798939, "T": 1564745798938,
+                "a": {{
+                    "m": "ORDER",
+                    "B": [{{"a": "{asset}", "wb": "{cross_wallet}", "cw": "{cross_wallet}", "bc": "0"}}],
+                    "P": []
+                }}
+            }}"#
+        );
+        serde_json::from_str(&json).expect("Failed to parse account update")
+    }
+
+    fn make_depth(first: u64, last: u64, prev_last: u64) -> Depth {
+        make_depth_levels(
+            first,
+            last,
+            prev_last,
+            r#"[["99.9", "1"]]"#,
+            r#"[["100.1", "1"]]"#,
+        )
+    }
+
+    fn make_depth_levels(first: u64, last: u64, prev_last: u64, bids: &str, asks: &str) -> Depth {
+        make_symbol_depth(Symbol::SOLUSDT, first, last, prev_last, bids, asks)
+    }
+
+    fn make_symbol_depth(
+        symbol: Symbol,
+        first: u64,
+        last: u64,
+        prev_last: u64,
+        bids: &str,
+        asks: &str,
+    ) -> Depth {
+        let json = format!(
+            r#"{{
+                "e": "depthUpdate", "E": 1571889248277, "T": 1571889248276, "s": "{symbol}",
+                "U": {first}, "u": {last}, "pu": {prev_last}, "b": {bids}, "a": {asks}
+            }}"#
+        );
+        serde_json::from_str(&json).expect("Failed to parse depth")
+    }
+
+    fn make_snapshot(last_update_id: u64, bids: &str, asks: &str) -> OrderBook {
+        let json = format!(
+            r#"{{
+                "lastUpdateId": {last_update_id}, "E": 1571889248277, "T": 1571889248268,
+                "bids": {bids}, "asks": {asks}
+            }}"#
+        );
+        let snapshot = serde_json::from_str(&json).expect("Failed to parse snapshot");
+        OrderBook::from_depth_snapshot(Symbol::SOLUSDT, snapshot)
+    }
+
+    /// Venue that fills nothing: records placed orders and replays canned market data
+    struct MockExchange {
+        placed: std::sync::Mutex<Vec<Order>>,
+        cancelled_all: std::sync::Mutex<Vec<Symbol>>,
+        position: Option<Decimal>,
+        book_tickers: Vec<BookTicker>,
+    }
+
+    impl crate::exchange::Exchange for MockExchange {
+        async fn open_order(&self, order: Order) -> TradingCoreResult<OrderSuccessResp> {
+            self.placed.lock().unwrap().push(order);
+            Ok(make_open_order(&order))
+        }
+
+        async fn cancel_order(
+            &self,
+            _symbol: Symbol,
+            client_id: Uuid,
+        ) -> TradingCoreResult<OrderSuccessResp> {
+            let placed = self.placed.lock().unwrap();
+            let order = placed
+                .iter()
+                .find(|order| order.client_order_id() == client_id)
+                .expect("Cancel of an unknown order");
+            Ok(make_open_order(order))
+        }
+
+        async fn cancel_all_orders(&self, symbol: Symbol) -> TradingCoreResult<()> {
+            self.cancelled_all.lock().unwrap().push(symbol);
+            Ok(())
+        }
+
+        async fn fetch_snapshot(
+            &self,
+            _symbol: Symbol,
+            _depth: u16,
+        ) -> TradingCoreResult<OrderBook> {
+            Ok(make_snapshot(1, "[]", "[]"))
+        }
+
+        async fn get_position(&self, _symbol: Symbol) -> TradingCoreResult<Option<Decimal>> {
+            Ok(self.position)
+        }
+
+        async fn subscribe(
+            &self,
+            _streams: Vec<data::binance::subscription::StreamSpec>,
+            _start_delay: std::time::Duration,
+        ) -> TradingCoreResult<crate::exchange::MarketFeed> {
+            let (commands, _) = tokio::sync::mpsc::channel(1);
+            let (tx, events) = tokio::sync::mpsc::channel(self.book_tickers.len().max(1));
+            for book_ticker in &self.book_tickers {
+                tx.try_send(data::binance::subscription::MarketStream::BookTicker(
+                    *book_ticker,
+                ))
+                .expect("Mock feed is sized for its book tickers");
+            }
+            Ok(crate::exchange::MarketFeed { commands, events })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quotes_round_trip_through_exchange() {
+        use crate::exchange::Exchange;
+        use crate::gate::RequestGate;
+        use crate::retry::RetryPolicy;
+        use crate::strategy::{QuoteStrategy, Strategy};
+        use data::binance::subscription::MarketStream;
+
+        let mock = MockExchange {
+            placed: std::sync::Mutex::new(Vec::new()),
+            cancelled_all: std::sync::Mutex::new(Vec::new()),
+            position: Some(dec!(0)),
+            book_tickers: vec![make_book_ticker("99.9", "100.1")],
+        };
+        let mut state = State::new(Symbol::SOLUSDT);
+
+        let mut feed = mock
+            .subscribe(Vec::new(), std::time::Duration::ZERO)
+            .await
+            .unwrap();
+        while let Ok(event) = feed.events.try_recv() {
+            if let MarketStream::BookTicker(book_ticker) = event {
+                state.on_book_ticker_received(book_ticker);
+            }
+        }
+
+        let mut quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+        state.register_orders(&mut quotes);
+        assert_eq!(quotes.len(), 2);
+
+        let policy = RetryPolicy::new(std::time::Duration::ZERO, std::time::Duration::ZERO)
+            .with_max_attempts(1);
+        let results = mock
+            .open_orders(&quotes, &policy, &RequestGate::new(2))
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(mock.placed.lock().unwrap().len(), 2);
+
+        let position = mock.get_position(Symbol::SOLUSDT).await.unwrap();
+        assert!(state.check_final_position(position, dec!(0)));
+        assert!(!state.check_final_position(None, dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_cancels_all_when_enabled() {
+        use crate::exchange::cancel_on_disconnect;
+
+        let mock = MockExchange {
+            placed: std::sync::Mutex::new(Vec::new()),
+            cancelled_all: std::sync::Mutex::new(Vec::new()),
+            position: None,
+            book_tickers: Vec::new(),
+        };
+        let symbols = [Symbol::SOLUSDT, Symbol::BTCUSDT];
+        let mut risk = RiskConfig::default();
+
+        // orders are kept by default
+        let cancelled = cancel_on_disconnect(&mock, &symbols, risk.cancel_on_disconnect).await;
+        assert!(!cancelled.unwrap());
+        assert!(mock.cancelled_all.lock().unwrap().is_empty());
+
+        risk.cancel_on_disconnect = true;
+        let cancelled = cancel_on_disconnect(&mock, &symbols, risk.cancel_on_disconnect).await;
+        assert!(cancelled.unwrap());
+        assert_eq!(*mock.cancelled_all.lock().unwrap(), symbols);
+    }
+
+    #[test]
+    fn test_balance_tracked_from_account_update() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert_eq!(state.available_balance(), None);
+
+        state.on_account_update(&make_account_update("BUSD", "50"));
+        assert_eq!(state.available_balance(), None);
+
+        state.on_account_update(&make_account_update("USDT", "1234.5"));
+        assert_eq!(state.available_balance(), Some(dec!(1234.5)));
+    }
+
+    #[test]
+    fn test_balance_map_from_account_update() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let seed: Vec<BalanceResp> = serde_json::from_str(
+            r#"[
+                {"accountAlias": "SgsR", "asset": "USDT", "balance": "100", "crossWalletBalance": "90",
+                 "crossUnPnl": "0", "availableBalance": "80", "maxWithdrawAmount": "80",
+                 "marginAvailable": true, "updateTime": 1617939110373},
+                {"accountAlias": "SgsR", "asset": "BNB", "balance": "1", "crossWalletBalance": "1",
+                 "crossUnPnl": "0", "availableBalance": "1", "maxWithdrawAmount": "1",
+                 "marginAvailable": true, "updateTime": 1617939110373}
+            ]"#,
+        )
+        .expect("Failed to parse balances");
+        state.seed_balances(&seed);
+        assert_eq!(state.balance(Asset::USDT), Some(dec!(90)));
+        assert_eq!(state.balance(Asset::BUSD), None);
+        assert_eq!(state.balance(Asset::Unknown), None);
+
+        state.on_account_update(&make_account_update("BUSD", "50"));
+        state.on_account_update(&make_account_update("USDT", "1234.5"));
+        assert_eq!(state.balance(Asset::BUSD), Some(dec!(50)));
+        assert_eq!(state.balance(Asset::USDT), Some(dec!(1234.5)));
+        assert_eq!(state.available_balance(), Some(dec!(1234.5)));
+    }
+
+    #[test]
+    fn test_depth_buffered_before_snapshot() {
+        let mut state = State::new(Symbol::SOLUSDT);
+
+        assert_eq!(state.on_depth(make_depth(1, 10, 0)), DepthOutcome::Buffered);
+        assert_eq!(
+            state.on_depth(make_depth(11, 20, 10)),
+            DepthOutcome::Buffered
+        );
+
+        state
+            .apply_buffered(OrderBook::new(Symbol::SOLUSDT))
+            .unwrap();
+        let ob = state.order_book.as_ref().unwrap();
+        assert_eq!(ob.last_update_id(), 20);
+    }
+
+    #[test]
+    fn test_buffered_gap_drops_snapshot() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert!(state.request_snapshot());
+        state.on_depth(make_depth(11, 20, 10));
+        // 21..=30 is lost
+        state.on_depth(make_depth(31, 40, 30));
+
+        let gap = state.apply_buffered(make_snapshot(15, "[]", "[]"));
+        assert_eq!(
+            gap,
+            Err(DepthGapError {
+                expected: 20,
+                actual: 30
+            })
+        );
+        assert!(!state.has_order_book());
+        // the claim carries over to the retry
+        assert!(!state.request_snapshot());
+    }
+
+    #[test]
+    fn test_depth_applied_when_chained() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state
+            .apply_buffered(OrderBook::new(Symbol::SOLUSDT))
+            .unwrap();
+
+        assert_eq!(state.on_depth(make_depth(1, 10, 0)), DepthOutcome::Applied);
+        assert_eq!(
+            state.on_depth(make_depth(11, 20, 10)),
+            DepthOutcome::Applied
+        );
+        assert_eq!(state.order_book.as_ref().unwrap().last_update_id(), 20);
+    }
+
+    #[test]
+    fn test_gap_is_isolated_to_its_symbol() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+
+        let mut states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+        let depth = |symbol, first, last, prev_last| {
+            make_symbol_depth(
+                symbol,
+                first,
+                last,
+                prev_last,
+                r#"[["99.9", "1"]]"#,
+                r#"[["100.1", "1"]]"#,
+            )
+        };
+        for symbol in [Symbol::SOLUSDT, Symbol::BTCUSDT] {
+            let state = states.get_mut(symbol).unwrap();
+            assert!(state.request_snapshot());
+            assert!(!state.request_snapshot());
+            state.apply_buffered(OrderBook::new(symbol)).unwrap();
+            states.on_book_ticker_received(make_symbol_book_ticker(symbol, "99.9", "100.1"));
+            assert_eq!(
+                states.on_depth(depth(symbol, 1, 10, 0)),
+                Some(DepthOutcome::Applied)
+            );
+        }
+
+        assert_eq!(
+            states.on_depth(depth(Symbol::SOLUSDT, 21, 30, 20)),
+            Some(DepthOutcome::GapDetected)
+        );
+        let sol = states.get_mut(Symbol::SOLUSDT).unwrap();
+        assert!(!sol.has_order_book());
+        // nothing is in flight for the dropped book yet
+        assert!(sol.request_snapshot());
+
+        let btc = states.get_mut(Symbol::BTCUSDT).unwrap();
+        assert!(btc.has_order_book());
+        assert_eq!(
+            states.on_depth(depth(Symbol::BTCUSDT, 11, 20, 10)),
+            Some(DepthOutcome::Applied)
+        );
+        let btc = states.get(Symbol::BTCUSDT).unwrap();
+        assert_eq!(btc.order_book.as_ref().unwrap().last_update_id(), 20);
+        assert_eq!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::BTCUSDT, btc)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_resumed_book_kept_only_if_chained() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert!(!state.resume_order_book());
+        state
+            .apply_buffered(OrderBook::new(Symbol::SOLUSDT))
+            .unwrap();
+        assert_eq!(state.on_depth(make_depth(1, 10, 0)), DepthOutcome::Applied);
+
+        // a blip that missed nothing, the book carries on
+        assert!(state.resume_order_book());
+        assert!(state.is_book_resuming());
+        assert_eq!(
+            state.on_depth(make_depth(11, 20, 10)),
+            DepthOutcome::Applied
+        );
+        assert!(!state.is_book_resuming());
+        assert_eq!(state.order_book.as_ref().unwrap().last_update_id(), 20);
+
+        // updates were missed, the book goes and a snapshot is due
+        assert!(state.resume_order_book());
+        assert_eq!(
+            state.on_depth(make_depth(31, 40, 30)),
+            DepthOutcome::GapDetected
+        );
+        assert!(!state.has_order_book());
+        assert!(!state.is_book_resuming());
+    }
+
+    fn make_partial_depth(update_id: u64, bids: &str, asks: &str) -> PartialDepth {
+        let json = format!(
+            r#"{{
+                "e": "depthUpdate", "E": 1571889248277, "T": 1571889248276, "s": "SOLUSDT",
+                "U": {update_id}, "u": {update_id}, "pu": {}, "b": {bids}, "a": {asks}
+            }}"#,
+            update_id - 1
+        );
+        serde_json::from_str(&json).expect("Failed to parse partial depth")
+    }
+
+    #[test]
+    fn test_book_built_from_partial_depth() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.on_partial_depth(make_partial_depth(
+            10,
+            r#"[["99.9", "5"], ["99.8", "7"]]"#,
+            r#"[["100.1", "4"], ["100.2", "0"]]"#,
+        ));
+        let ob = state
+            .order_book
+            .as_ref()
+            .expect("Book built without a snapshot");
+        assert_eq!(ob.last_update_id(), 10);
+        assert_eq!(
+            ob.top(5),
+            (
+                levels(&[(dec!(99.9), dec!(5)), (dec!(99.8), dec!(7))]),
+                levels(&[(dec!(100.1), dec!(4))]),
+            )
+        );
+
+        // the next frame replaces the book, the 99.8 bid is no longer in the top levels
+        let next = make_partial_depth(12, r#"[["99.95", "1"]]"#, r#"[["100.0", "2"]]"#);
+        let diff: Depth = serde_json::from_str(
+            r#"{
+                "e": "depthUpdate", "E": 1571889248277, "T": 1571889248276, "s": "SOLUSDT",
+                "U": 11, "u": 11, "pu": 10, "b": [["99.7", "3"]], "a": []
+            }"#,
+        )
+        .unwrap();
+        state.on_partial_depth(next);
+        // a frame from the shared depthUpdate parse path, older than the book
+        state.on_partial_depth(PartialDepth::from(diff));
+        let ob = state.order_book.as_ref().unwrap();
+        assert_eq!(ob.last_update_id(), 12);
+        assert_eq!(
+            ob.top(5),
+            (
+                levels(&[(dec!(99.95), dec!(1))]),
+                levels(&[(dec!(100.0), dec!(2))])
+            )
+        );
+    }
+
+    #[test]
+    fn test_depth_gap_drops_order_book() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state
+            .apply_buffered(OrderBook::new(Symbol::SOLUSDT))
+            .unwrap();
+
+        assert_eq!(state.on_depth(make_depth(1, 10, 0)), DepthOutcome::Applied);
+        assert_eq!(
+            state.on_depth(make_depth(21, 30, 20)),
+            DepthOutcome::GapDetected
+        );
+        assert!(!state.has_order_book());
+        assert_eq!(
+            state.on_depth(make_depth(31, 40, 30)),
+            DepthOutcome::Buffered
+        );
+    }
+
+    fn levels(levels: &[(Decimal, Decimal)]) -> Vec<Level> {
+        levels.iter().copied().map(Level::from).collect()
+    }
+
+    #[test]
+    fn test_depth_gap_recovery_replay() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let mut gaps = 0;
+        let mut feed = |state: &mut State, depth: Depth| {
+            let outcome = state.on_depth(depth);
+            if outcome == DepthOutcome::GapDetected {
+                gaps += 1;
+            }
+            outcome
+        };
+
+        // frames arriving before the first snapshot, the first one predates it
+        let pre_snapshot = [
+            make_depth_levels(95, 100, 90, r#"[["100", "1"]]"#, "[]"),
+            make_depth_levels(
+                101,
+                110,
+                100,
+                r#"[["100", "6"], ["97", "1"]]"#,
+                r#"[["101", "0"], ["101.5", "2"]]"#,
+            ),
+        ];
+        for depth in pre_snapshot {
+            assert_eq!(feed(&mut state, depth), DepthOutcome::Buffered);
+        }
+        state
+            .apply_buffered(make_snapshot(
+                105,
+                r#"[["100", "5"], ["99", "3"], ["98", "2"]]"#,
+                r#"[["101", "4"], ["102", "6"], ["103", "1"]]"#,
+            ))
+            .unwrap();
+
+        let live = make_depth_levels(111, 120, 110, r#"[["99", "0"]]"#, r#"[["102", "7"]]"#);
+        assert_eq!(feed(&mut state, live), DepthOutcome::Applied);
+
+        // 121..=130 is lost
+        let after_gap = make_depth_levels(131, 140, 130, r#"[["100.5", "9"]]"#, "[]");
+        assert_eq!(feed(&mut state, after_gap), DepthOutcome::GapDetected);
+        assert!(!state.has_order_book());
+
+        let pre_resnapshot = [
+            make_depth_levels(141, 150, 140, "[]", r#"[["104", "1"]]"#),
+            make_depth_levels(151, 160, 150, r#"[["100", "2"]]"#, r#"[["101.5", "0"]]"#),
+        ];
+        for depth in pre_resnapshot {
+            assert_eq!(feed(&mut state, depth), DepthOutcome::Buffered);
+        }
+        state
+            .apply_buffered(make_snapshot(
+                155,
+                r#"[["100", "3"], ["99.5", "1"], ["98", "2"]]"#,
+                r#"[["101", "1"], ["101.5", "2"], ["102", "7"]]"#,
+            ))
+            .unwrap();
+
+        let live = make_depth_levels(161, 170, 160, r#"[["99.5", "4"]]"#, r#"[["103", "2"]]"#);
+        assert_eq!(feed(&mut state, live), DepthOutcome::Applied);
+        assert_eq!(gaps, 1);
+
+        let ob = state.order_book.as_ref().unwrap();
+        assert_eq!(ob.last_update_id(), 170);
+        let (bids, asks) = ob.top(10);
+        assert_eq!(
+            bids,
+            levels(&[
+                (dec!(100), dec!(2)),
+                (dec!(99.5), dec!(4)),
+                (dec!(98), dec!(2))
+            ])
+        );
+        assert_eq!(
+            asks,
+            levels(&[
+                (dec!(101), dec!(1)),
+                (dec!(102), dec!(7)),
+                (dec!(103), dec!(2))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_depth_only_book_is_quoted() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+
+        state
+            .apply_buffered(make_snapshot(
+                10,
+                r#"[["99.8", "1"], ["99.7", "2"]]"#,
+                r#"[["100.2", "1"], ["100.3", "2"]]"#,
+            ))
+            .unwrap();
+        let depth = make_depth_levels(11, 20, 10, r#"[["99.9", "3"]]"#, "[]");
+        assert_eq!(state.on_depth(depth), DepthOutcome::Applied);
+        assert!(state.bbo_level.is_none());
+
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(*quotes[0].orig_price(), dec!(99.9));
+        assert_eq!(*quotes[1].orig_price(), dec!(100.2));
+    }
+
+    #[test]
+    fn test_one_sided_book_is_not_quoted() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+
+        let mut state = State::new(Symbol::SOLUSDT);
+        state
+            .apply_buffered(make_snapshot(10, r#"[["99.9", "1"]]"#, "[]"))
+            .unwrap();
+
+        assert!(state.best_bid_ask().is_none());
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_quote_outside_price_band_is_rejected() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.risk.max_price_deviation_pct = Some(dec!(5));
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+
+        let inside = Quotes::from_slice(&[make_order(Side::Buy)]);
+        let far_off = Order::new(
+            Symbol::SOLUSDT,
+            Side::Sell,
+            OrderKind::Limit,
+            dec!(110),
+            dec!(1),
+            TimeInForce::GoodUntilCancel,
+            None,
+        );
+        let outside = Quotes::from_slice(&[make_order(Side::Buy), far_off]);
+
+        assert!(state.check_price_band(&inside));
+        assert!(!state.check_price_band(&outside));
+    }
+
+    #[test]
+    fn test_quote_outside_percent_price_is_skipped() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.set_percent_price(Some(PercentPriceFilter::new(dec!(1.05), dec!(0.95))));
+        let mark_price = serde_json::from_str(
+            r#"{
+                "e": "markPriceUpdate", "E": 1562305380000, "s": "SOLUSDT", "p": "100",
+                "i": "100.01", "P": "100.02", "r": "0.0001", "T": 1562306400000
+            }"#,
+        )
+        .expect("Failed to parse mark price");
+
+        let quote = |side, price| {
+            Order::new(
+                Symbol::SOLUSDT,
+                side,
+                OrderKind::Limit,
+                price,
+                dec!(1),
+                TimeInForce::GoodUntilCancel,
+                None,
+            )
+        };
+        let mut quotes =
+            Quotes::from_slice(&[quote(Side::Buy, dec!(106)), quote(Side::Sell, dec!(101))]);
+        // no mark price yet, nothing to check against
+        state.apply_percent_price(&mut quotes);
+        assert_eq!(quotes.len(), 2);
+
+        state.on_mark_price(&mark_price);
+        state.apply_percent_price(&mut quotes);
+        assert_eq!(quotes.len(), 1);
+        assert!(matches!(quotes[0].side(), Side::Sell));
+    }
+
+    #[test]
+    fn test_order_notional_is_clamped() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.risk.max_order_notional = Some(dec!(150));
+
+        let mut quotes = Quotes::from_slice(&[make_order(Side::Buy), make_order(Side::Sell)]);
+        state.apply_notional_limits(&mut quotes);
+
+        assert_eq!(quotes.len(), 2);
+        assert!(quotes.iter().all(|o| o.notional() == dec!(100)));
+    }
+
+    #[test]
+    fn test_open_notional_limit_refuses_orders() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.risk.max_open_notional = Some(dec!(500));
+        state.register_order(make_order(Side::Buy));
+
+        // 200 resting, each quote adds 200
+        let mut quotes = Quotes::from_slice(&[make_order(Side::Buy), make_order(Side::Sell)]);
+        state.apply_notional_limits(&mut quotes);
+
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(state.open_notional() + quotes[0].notional(), dec!(400));
+    }
+
+    fn make_open_order(order: &Order) -> OrderSuccessResp {
+        let json = format!(
+            r#"{{
+                "orderId": 22542179, "symbol": "SOLUSDT", "status": "NEW",
+                "clientOrderId": "{}", "price": "100", "origQty": "2", "executedQty": "0",
+                "cumQty": "0", "cumQuote": "0", "side": "BUY", "updateTime": 1566818724722
+            }}"#,
+            order.client_order_id()
+        );
+        serde_json::from_str(&json).expect("Failed to parse open order")
+    }
+
+    #[test]
+    fn test_duplicate_client_id_is_refused() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let original = make_order(Side::Buy);
+        let colliding = make_order(Side::Sell).with_client_order_id(original.client_order_id());
+        assert!(state.register_order(original));
+
+        let mut quotes = Quotes::from_slice(&[colliding, make_order(Side::Sell)]);
+        state.register_orders(&mut quotes);
+
+        assert_eq!(quotes.len(), 1);
+        assert_ne!(quotes[0].client_order_id(), original.client_order_id());
+        let tracked = state.get_active_order(&original.client_order_id()).unwrap();
+        assert!(matches!(tracked.side(), Side::Buy));
+    }
+
+    #[test]
+    fn test_external_order_kept_apart() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert!(state.register_order(make_order(Side::Buy)));
+        let external = make_order(Side::Sell);
+
+        state
+            .on_update_received(&make_update(&external, "NEW", "NEW", "0", 0))
+            .unwrap();
+        assert_eq!(state.external_order_count(), 1);
+        state
+            .on_update_received(&make_update(&external, "TRADE", "PARTIALLY_FILLED", "1", 7))
+            .unwrap();
+        assert_eq!(state.external_position(), dec!(-1));
+        assert_eq!(state.get_position(), Decimal::ZERO);
+
+        // a redelivered fill is not counted twice, cancelling ends the external order
+        state
+            .on_update_received(&make_update(&external, "TRADE", "PARTIALLY_FILLED", "1", 7))
+            .unwrap();
+        state
+            .on_update_received(&make_update(&external, "CANCELED", "CANCELED", "1", 0))
+            .unwrap();
+        assert_eq!(state.external_position(), dec!(-1));
+        assert_eq!(state.external_order_count(), 0);
+        assert_eq!(state.active_order_count(), 1);
+
+        // an id minted by the bot's own scheme is never taken for an external order
+        state.client_ids = ClientIdGenerator::new(ClientIdScheme::Structured { strategy: 1 });
+        let lost =
+            make_order(Side::Buy).with_client_order_id(state.client_ids.mint(Symbol::SOLUSDT));
+        let err = state
+            .on_update_received(&make_update(&lost, "NEW", "NEW", "0", 0))
+            .unwrap_err();
+        assert!(err.to_string().contains("Untracked order"));
+    }
+
+    #[test]
+    fn test_probe_is_minimal_ioc_and_outcome_recorded() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.bbo_level = Some((
+            Level::from((dec!(99.9), dec!(1))),
+            Level::from((dec!(100.1), dec!(1))),
+        ));
+        state.strategy.probe = Some(ProbeConfig {
+            interval_ms: 1_000,
+            qty: dec!(0.01),
+            ticks_inside: 0,
+        });
+        let start = LocalTime::now();
+
+        let probe = state.make_probe(start).unwrap();
+        assert!(matches!(
+            probe.time_in_force(),
+            TimeInForce::ImmediateOrCancel
+        ));
+        assert_eq!(*probe.orig_qty(), dec!(0.01));
+        assert!(matches!(probe.side(), Side::Buy));
+        assert_eq!(*probe.orig_price(), dec!(100.1));
+        // one at a time
+        assert!(state.make_probe(start + Duration::seconds(5)).is_none());
+
+        state
+            .on_update_received(&make_update(&probe, "EXPIRED", "EXPIRED", "0", 0))
+            .unwrap();
+        assert_eq!(state.probe_stats().missed(), 1);
+        assert_eq!(state.active_order_count(), 0);
+
+        // not before the interval, then on the other side
+        assert!(
+            state
+                .make_probe(start + Duration::milliseconds(500))
+                .is_none()
+        );
+        let probe = state.make_probe(start + Duration::seconds(1)).unwrap();
+        assert!(matches!(probe.side(), Side::Sell));
+        assert_eq!(*probe.orig_price(), dec!(99.9));
+        state
+            .on_update_received(&make_update(&probe, "TRADE", "FILLED", "0.01", 3))
+            .unwrap();
+
+        let stats = state.probe_stats();
+        assert_eq!((stats.sent(), stats.filled(), stats.missed()), (2, 1, 1));
+        assert_eq!(stats.latency().count(), 2);
+    }
+
+    #[test]
+    fn test_completed_orders_stay_bounded() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let mut ids = Vec::new();
+        for _ in 0..HIST_ORDERS_CAPACITY + 100 {
+            let order = make_order(Side::Buy);
+            ids.push(order.client_order_id());
+            assert!(state.register_order(order));
+            state.complete_order(*ids.last().unwrap());
+        }
+        assert_eq!(state.completed_order_count(), HIST_ORDERS_CAPACITY);
+        assert_eq!(state.active_order_count(), 0);
+
+        // the oldest ids are forgotten and pass for external orders, the recent ones report as
+        // removed
+        let late_update = |id: Uuid| {
+            let order = make_order(Side::Buy).with_client_order_id(id);
+            make_update(&order, "CANCELED", "CANCELED", "0", 0)
+        };
+        assert!(state.on_update_received(&late_update(ids[0])).is_ok());
+        assert_eq!(state.external_order_count(), 0);
+        let err = state
+            .on_update_received(&late_update(*ids.last().unwrap()))
+            .unwrap_err();
+        assert!(err.to_string().contains("Order has been removed"));
+
+        // ids also age out
+        let start = LocalTime::now();
+        let mut completed = CompletedOrders::new(10, Duration::minutes(1));
+        let (old, recent) = (Uuid::new_v4(), Uuid::new_v4());
+        completed.insert(old, start);
+        completed.insert(recent, start + Duration::seconds(30));
+        completed.insert(Uuid::new_v4(), start + Duration::seconds(61));
+        assert!(!completed.contains(&old));
+        assert!(completed.contains(&recent));
+        assert_eq!(completed.len(), 2);
+    }
+
+    #[test]
+    fn test_active_order_cap_refuses_excess() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.risk.max_active_orders = Some(3);
+        assert!(state.register_order(make_order(Side::Buy)));
+
+        let mut quotes: Quotes = (0..4).map(|_| make_order(Side::Sell)).collect();
+        state.register_orders(&mut quotes);
+
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(state.active_order_count(), 3);
+    }
+
+    #[test]
+    fn test_import_then_reconcile_converges() {
+        let mut before = State::new(Symbol::SOLUSDT);
+        let (filled, resting) = (make_order(Side::Buy), make_order(Side::Sell));
+        before.register_order(filled);
+        before.register_order(resting);
+        before.pnl = ProfitAndLoss::new(dec!(100), dec!(1));
+
+        let mut after = State::new(Symbol::SOLUSDT);
+        after.import(before.export()).unwrap();
+        assert_eq!(after.active_order_count(), 2);
+        assert!(State::new(Symbol::BTCUSDT).import(before.export()).is_err());
+
+        // the buy was filled while the bot was down, only the sell is still open
+        let removed = after.reconcile_after_gap(
+            &[make_open_order(&resting)],
+            Some(dec!(1)),
+            Duration::zero(),
+        );
+        assert_eq!(removed, vec![filled.client_order_id()]);
+        assert!(after.get_active_order(&resting.client_order_id()).is_some());
+        assert!(after.check_final_position(Some(dec!(1)), Decimal::ZERO));
+    }
+
+    #[test]
+    fn test_reconcile_after_gap_completes_missing_orders() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let resting = make_order(Side::Buy);
+        let gone = make_order(Side::Buy);
+        state.register_orders(&mut Quotes::from_slice(&[resting, gone]));
+
+        let removed = state.reconcile_after_gap(
+            &[make_open_order(&resting)],
+            Some(Decimal::ZERO),
+            Duration::zero(),
+        );
+
+        assert_eq!(removed, vec![gone.client_order_id()]);
+        assert!(state.get_active_order(&resting.client_order_id()).is_some());
+        assert!(state.get_active_order(&gone.client_order_id()).is_none());
+    }
+
+    #[test]
+    fn test_resolve_queried_order() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let open = make_order(Side::Buy);
+        let gone = make_order(Side::Sell);
+        state.register_order(open);
+        state.register_order(gone);
+
+        assert!(
+            !state.resolve_queried_order(open.client_order_id(), Some(&make_open_order(&open)))
+        );
+        assert!(state.resolve_queried_order(gone.client_order_id(), None));
+
+        assert!(state.get_active_order(&open.client_order_id()).is_some());
+        assert!(state.get_active_order(&gone.client_order_id()).is_none());
+    }
+
+    /// Order last updated `age` ago, through the persisted form since nothing else sets it
+    fn make_aged_order(side: Side, age: Duration) -> Order {
+        let mut json = serde_json::to_value(OrderSnapshot::from(&make_order(side))).unwrap();
+        json["last_update_ts"] = (LocalTime::now() - age).timestamp_millis().into();
+        Order::from(serde_json::from_value::<OrderSnapshot>(json).unwrap())
+    }
+
+    #[test]
+    fn test_stale_order_ids_oldest_first() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let ages = [40, 90, 5, 60, 31];
+        let orders: Vec<Order> = ages
+            .iter()
+            .map(|&secs| make_aged_order(Side::Buy, Duration::seconds(secs)))
+            .collect();
+        for order in &orders {
+            assert!(state.register_order(*order));
+        }
+
+        let id = |i: usize| orders[i].client_order_id();
+        assert_eq!(
+            state.stale_order_ids(Duration::seconds(30)),
+            vec![id(1), id(3), id(0), id(4)]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_after_gap_keeps_recent_orders() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let in_flight = make_order(Side::Sell);
+        state.register_order(in_flight);
+
+        let removed = state.reconcile_after_gap(&[], None, Duration::seconds(5));
+
+        assert!(removed.is_empty());
+        assert!(
+            state
+                .get_active_order(&in_flight.client_order_id())
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_reduce_only_rejection_completes_order() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let order = make_order(Side::Sell);
+        state.register_order(order);
+
+        state.on_reduce_only_rejected(order.client_order_id());
+        assert!(state.get_active_order(&order.client_order_id()).is_none());
+        assert!(!state.register_order(order));
+        assert!(state.get_position().is_zero());
+    }
+
+    #[test]
+    fn test_amendment_with_fill_is_accounted() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let order = make_order(Side::Buy);
+        state.register_order(order);
+
+        let amend = make_update(&order, "AMENDMENT", "PARTIALLY_FILLED", "1", 42);
+        state.on_update_received(&amend).unwrap();
+
+        assert_eq!(state.get_position(), dec!(1));
+        assert_eq!(state.turnover(), dec!(100));
+        assert!(state.get_active_order(&order.client_order_id()).is_some());
+    }
+
+    #[test]
+    fn test_fill_as_first_update_completes_order() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let order = make_order(Side::Buy);
+        state.register_order(order);
+
+        // crossed on arrival, no NEW was ever sent
+        let fill = make_update(&order, "TRADE", "FILLED", "2", 7);
+        state.on_update_received(&fill).unwrap();
+
+        assert!(state.get_active_order(&order.client_order_id()).is_none());
+        assert_eq!(state.get_position(), dec!(2));
+        assert_eq!(state.turnover(), dec!(200));
+        assert_eq!(state.placement_latency().count(), 1);
+        assert!(state.placement_latency().min() >= Some(Duration::zero()));
+    }
+
+    #[test]
+    fn test_duplicate_trade_id_is_ignored() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let order = make_order(Side::Buy);
+        state.register_order(order);
+
+        let trade = make_update(&order, "TRADE", "PARTIALLY_FILLED", "1", 42);
+        let amend = make_update(&order, "AMENDMENT", "PARTIALLY_FILLED", "1", 42);
+        state.on_update_received(&trade).unwrap();
+        state.on_update_received(&amend).unwrap();
+
+        assert_eq!(state.get_position(), dec!(1));
+        assert_eq!(state.turnover(), dec!(100));
+    }
+
+    #[test]
+    fn test_fill_cools_down_its_side() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.strategy.fill_cooldown_ms = Some(500);
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        let order = make_order(Side::Buy);
+        state.register_order(order);
+
+        let trade = make_update(&order, "TRADE", "PARTIALLY_FILLED", "1", 42);
+        state.on_update_received(&trade).unwrap();
+
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+        assert_eq!(quotes.len(), 1);
+        assert!(matches!(quotes[0].side(), Side::Sell));
+
+        let later = LocalTime::now() + Duration::milliseconds(500);
+        assert!(!state.is_cooling_down(Side::Buy, later));
+        assert!(!state.is_cooling_down(Side::Sell, LocalTime::now()));
+    }
+
+    #[test]
+    fn test_fill_triggers_requote_of_its_symbol() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct RecordingStrategy {
+            quoted: RefCell<Vec<Symbol>>,
+        }
+        impl Strategy for RecordingStrategy {
+            fn generate_quotes(&self, symbol: Symbol, state: &State) -> Quotes {
+                self.quoted.borrow_mut().push(symbol);
+                QuoteStrategy::default().generate_quotes(symbol, state)
+            }
+        }
+        let strategy = RecordingStrategy::default();
+        let fill = |state: &mut State, trade_id| {
+            let order = make_order(Side::Buy);
+            state.register_order(order);
+            let trade = make_update(&order, "TRADE", "FILLED", "2", trade_id);
+            state.on_update_received(&trade).unwrap();
+        };
+
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        fill(&mut state, 1);
+        assert!(
+            state
+                .requote_after_fill(&strategy, LocalTime::now())
+                .is_none()
+        );
+        assert!(strategy.quoted.borrow().is_empty());
+
+        state.strategy.requote_on_fill = true;
+        // quotes held by the debounce still go out after a fill
+        state.strategy.debounce = Some(QuoteDebounceConfig {
+            threshold: DebounceThreshold::Bps(dec!(100)),
+            max_hold_ms: 60_000,
+        });
+        state.mark_quoted(LocalTime::now());
+        fill(&mut state, 2);
+        let quotes = state.requote_after_fill(&strategy, LocalTime::now());
+        assert_eq!(quotes.map(|quotes| quotes.len()), Some(2));
+        assert_eq!(*strategy.quoted.borrow(), vec![Symbol::SOLUSDT]);
+        // one fill, one re-quote
+        assert!(
+            state
+                .requote_after_fill(&strategy, LocalTime::now())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_schedule_window_wraps_midnight() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let time = |hms: &str| chrono::NaiveTime::parse_from_str(hms, "%H:%M:%S").unwrap();
+        let at = |hms: &str| {
+            let day = chrono::NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+            LocalTime::from(day.and_time(time(hms)).and_utc())
+        };
+        state.strategy.schedule = vec![
+            QuoteWindowConfig {
+                start: time("23:55:00"),
+                end: time("00:05:00"),
+                size_factor: dec!(0),
+            },
+            QuoteWindowConfig {
+                start: time("23:00:00"),
+                end: time("23:59:00"),
+                size_factor: dec!(0.5),
+            },
+        ];
+
+        assert_eq!(state.scheduled_size_factor(at("12:00:00")), None);
+        assert_eq!(state.scheduled_size_factor(at("23:30:00")), Some(dec!(0.5)));
+        assert!(state.is_schedule_paused(at("23:57:00")));
+        assert!(state.is_schedule_paused(at("00:04:59")));
+        assert!(!state.is_schedule_paused(at("00:05:00")));
+    }
+
+    fn make_debounced_state() -> State {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.strategy.tick_offset = Some(TickOffsetConfig {
+            ticks: 0,
+            tick_sizes: [(Symbol::SOLUSDT, dec!(0.01))].into_iter().collect(),
+            placement: None,
+        });
+        state.strategy.debounce = Some(QuoteDebounceConfig {
+            threshold: DebounceThreshold::Ticks(2),
+            max_hold_ms: 30_000,
+        });
+        state.on_book_ticker_received(make_book_ticker("99.99", "100.01"));
+        state
+    }
+
+    #[test]
+    fn test_debounce_suppresses_small_oscillation() {
+        let mut state = make_debounced_state();
+        let now = LocalTime::now();
+        assert!(state.should_requote(now));
+        state.mark_quoted(now);
+
+        for (bid, ask) in [
+            ("100.00", "100.02"),
+            ("99.98", "100.00"),
+            ("99.99", "100.01"),
+        ] {
+            state.on_book_ticker_received(make_book_ticker(bid, ask));
+            assert!(!state.should_requote(now + Duration::seconds(1)));
+        }
+    }
+
+    #[test]
+    fn test_debounce_large_move_requotes() {
+        let mut state = make_debounced_state();
+        let now = LocalTime::now();
+        state.mark_quoted(now);
+
+        state.on_book_ticker_received(make_book_ticker("100.02", "100.04"));
+        assert!(state.should_requote(now + Duration::seconds(1)));
+
+        state.strategy.debounce = Some(QuoteDebounceConfig {
+            threshold: DebounceThreshold::Bps(dec!(5)),
+            max_hold_ms: 30_000,
+        });
+        assert!(!state.should_requote(now + Duration::seconds(1)));
+        state.on_book_ticker_received(make_book_ticker("100.05", "100.07"));
+        assert!(state.should_requote(now + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_debounce_max_hold_forces_refresh() {
+        let mut state = make_debounced_state();
+        let now = LocalTime::now();
+        state.mark_quoted(now);
+
+        assert!(!state.should_requote(now + Duration::milliseconds(29_999)));
+        assert!(state.should_requote(now + Duration::milliseconds(30_000)));
+    }
+
+    #[test]
+    fn test_stale_bbo_is_not_quoted() {
+        use crate::strategy::{QuoteStrategy, Strategy};
+
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.risk.max_bbo_age_ms = Some(2_000);
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        assert_eq!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .len(),
+            2
+        );
+
+        state.bbo_received_at = Some(LocalTime::now() - Duration::milliseconds(2_500));
+        assert!(state.is_bbo_stale(LocalTime::now()));
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_amendment_without_fill_is_ignored() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let order = make_order(Side::Buy);
+        state.register_order(order);
+
+        let amend = make_update(&order, "AMENDMENT", "NEW", "0", 0);
+        state.on_update_received(&amend).unwrap();
+
+        assert_eq!(state.get_position(), Decimal::ZERO);
+        assert_eq!(state.turnover(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_trade_flow_signed_volume() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let ts = |secs: i64| ExchangeTime::from_millis((1_700_000_000 + secs) * 1_000).unwrap();
+        // the buyer is the maker of the second and fourth trades, sellers took those
+        let trades: Vec<AggTrade> = [
+            (0, "1.5", false),
+            (10, "0.5", true),
+            (30, "2", false),
+            (65, "1", true),
+        ]
+        .into_iter()
+        .map(|(secs, qty, is_maker)| {
+            let json = format!(
+                r#"{{
+                        "e": "aggTrade", "E": 1, "T": {}, "s": "SOLUSDT", "a": 5933014,
+                        "p": "100", "q": "{qty}", "f": 100, "l": 105, "m": {is_maker}
+                    }}"#,
+                ts(secs).timestamp_millis()
+            );
+            serde_json::from_str(&json).expect("Failed to parse agg trade")
+        })
+        .collect();
+
+        for trade in &trades[..3] {
+            state.on_trade(
+                trade.transaction_time(),
+                trade.price(),
+                trade.quantity(),
+                trade.aggressor(),
+            );
+        }
+        assert_eq!(state.trade_flow().buy_volume(), dec!(3.5));
+        assert_eq!(state.trade_flow().sell_volume(), dec!(0.5));
+        assert_eq!(state.trade_flow().net_volume(), dec!(3));
+
+        // a minute after the first trade, it rolls out of the flow
+        let last = &trades[3];
+        state.on_trade(
+            last.transaction_time(),
+            last.price(),
+            last.quantity(),
+            last.aggressor(),
+        );
+        assert_eq!(state.trade_flow().trades(), 3);
+        assert_eq!(state.trade_flow().net_volume(), dec!(0.5));
+    }
+
+    #[test]
+    fn test_trades_at_level_shrink_queue_ahead() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state
+            .apply_buffered(make_snapshot(
+                100,
+                r#"[["99.9", "5"], ["99.8", "7"]]"#,
+                r#"[["100.1", "4"]]"#,
+            ))
+            .unwrap();
+        let ts = ExchangeTime::from_millis(1_700_000_000_000).unwrap();
+        let bid = Order::new(
+            Symbol::SOLUSDT,
+            Side::Buy,
+            OrderKind::Limit,
+            dec!(99.9),
+            dec!(1),
+            TimeInForce::GoodUntilCancel,
+            None,
+        );
+        let deep_bid = Order::new(
+            Symbol::SOLUSDT,
+            Side::Buy,
+            OrderKind::Limit,
+            dec!(99.8),
+            dec!(1),
+            TimeInForce::GoodUntilCancel,
+            None,
+        );
+        let (bid_id, deep_id) = (bid.client_order_id(), deep_bid.client_order_id());
+        assert!(state.register_order(bid));
+        assert!(state.register_order(deep_bid));
+        assert_eq!(
+            state.queue_position(&bid_id),
+            Some(QueuePosition {
+                initial_ahead: dec!(5),
+                ahead: dec!(5),
+            })
+        );
+
+        // sells at the level eat into the queue, buys and other levels leave it alone
+        state.on_trade(ts, dec!(99.9), dec!(2), Side::Sell);
+        state.on_trade(ts, dec!(99.9), dec!(3), Side::Buy);
+        state.on_trade(ts, dec!(100.1), dec!(1), Side::Buy);
+        assert_eq!(state.queue_position(&bid_id).unwrap().ahead, dec!(3));
+        assert_eq!(state.queue_position(&deep_id).unwrap().ahead, dec!(7));
+
+        // the queue never goes negative
+        state.on_trade(ts, dec!(99.9), dec!(4), Side::Sell);
+        assert_eq!(state.queue_position(&bid_id).unwrap().ahead, Decimal::ZERO);
+        // trading through the deeper level clears its queue
+        state.on_trade(ts, dec!(99.7), dec!(1), Side::Sell);
+        assert_eq!(state.queue_position(&deep_id).unwrap().ahead, Decimal::ZERO);
+
+        state.complete_order(bid_id);
+        assert_eq!(state.queue_position(&bid_id), None);
+    }
+
+    #[test]
+    fn test_aggressive_buys_give_positive_imbalance() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        let start = ExchangeTime::from_millis(1_700_000_000_000).unwrap();
+        assert_eq!(
+            state.trade_flow_imbalance(Duration::seconds(5)),
+            Decimal::ZERO
+        );
+
+        // sellers dominate early on, then a burst of buys lifts the ask
+        state.on_trade(start, dec!(100), dec!(4), Side::Sell);
+        for i in 0..5 {
+            state.on_trade(
+                start + Duration::seconds(20) + Duration::milliseconds(i * 100),
+                dec!(100.1),
+                dec!(1),
+                Side::Buy,
+            );
+        }
+        state.on_trade(
+            start + Duration::seconds(21),
+            dec!(100),
+            dec!(0.5),
+            Side::Sell,
+        );
+
+        assert_eq!(state.trade_flow_imbalance(Duration::seconds(5)), dec!(4.5));
+        // the wider window reaches back to the early selling
+        assert_eq!(state.trade_flow_imbalance(Duration::seconds(30)), dec!(0.5));
+        assert_eq!(
+            state.trade_flow_imbalance(Duration::seconds(30)),
+            state.trade_flow().net_volume()
+        );
+    }
+
+    #[test]
+    fn test_spread_stats_follow_book_ticker() {
+        let mut state = State::new(Symbol::SOLUSDT);
+        assert!(state.spread_stats(Duration::seconds(60)).is_none());
+
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        state.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        state.on_book_ticker_received(make_book_ticker("100.9", "101.1"));
+
+        let stats = state.spread_stats(Duration::seconds(60)).unwrap();
+        assert_eq!(stats.samples(), 2);
+        assert!(stats.realized_volatility() > 0.0);
+    }
+
+    #[test]
+    fn test_symbol_states_are_isolated() {
+        let mut states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+        assert!(states.get(Symbol::ETHUSDT).is_none());
+        assert_eq!(states.iter().count(), 2);
+
+        let sol = states.get_mut(Symbol::SOLUSDT).unwrap();
+        sol.on_book_ticker_received(make_book_ticker("99.9", "100.1"));
+        let order = make_order(Side::Buy);
+        sol.register_order(order);
+        sol.on_update_received(&make_update(&order, "TRADE", "FILLED", "2", 1))
+            .unwrap();
+
+        let btc = states.get(Symbol::BTCUSDT).unwrap();
+        assert!(btc.bbo_level.is_none());
+        assert!(btc.get_active_order(&order.client_order_id()).is_none());
+        assert_eq!(btc.get_position(), Decimal::ZERO);
+        assert_eq!(states.get(Symbol::SOLUSDT).unwrap().get_position(), dec!(2));
+    }
+
+    #[test]
+    fn test_book_tickers_are_routed_by_symbol() {
+        let mut states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+
+        assert!(states.on_book_ticker_received(make_symbol_book_ticker(
+            Symbol::BTCUSDT,
+            "64999.9",
+            "65000.1"
+        )));
+        assert!(states.on_book_ticker_received(make_book_ticker("99.9", "100.1")));
+        assert!(!states.on_book_ticker_received(make_symbol_book_ticker(
+            Symbol::ETHUSDT,
+            "2999.9",
+            "3000.1"
+        )));
+
+        let (btc_bid, _) = states.get(Symbol::BTCUSDT).unwrap().bbo_level.unwrap();
+        let (sol_bid, _) = states.get(Symbol::SOLUSDT).unwrap().bbo_level.unwrap();
+        assert_eq!(btc_bid.price, dec!(64999.9));
+        assert_eq!(sol_bid.price, dec!(99.9));
+    }
 }
@@ -1,9 +1,10 @@
 use data::DataError;
 use hmac::digest::InvalidLength as HmacInvalidLength;
 use reqwest::StatusCode;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum ApiError {
     #[error("rate limited: {status} body {body}")]
     RateLimit { status: StatusCode, body: String },
@@ -14,6 +15,20 @@ pub enum ApiError {
     #[error("order rejected: {status} body {body}")]
     OrderRejected { status: StatusCode, body: String },
 
+    /// Expected when a reduce-only order races a fill that already flattened the position
+    #[error("reduce-only order rejected: {status} body {body}")]
+    ReduceOnlyRejected { status: StatusCode, body: String },
+
+    #[error("order does not exist: {status} body {body}")]
+    OrderNotFound { status: StatusCode, body: String },
+
+    /// HTTP 418, the IP is banned for hammering through rate limits
+    #[error("ip banned, retry after {retry_after:?}: body {body}")]
+    Banned {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
     #[error("exchange error: {status} body {body}")]
     Unknown { status: StatusCode, body: String },
 }
@@ -43,6 +58,16 @@ pub enum MessageCodecError {
     Hmac(#[from] HmacInvalidLength),
 }
 
+/// A depth update that does not follow on from the order book, which needs a new snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("depth update does not chain: expected {expected}, got {actual}")]
+pub struct DepthGapError {
+    /// Last update id of the order book
+    pub expected: u64,
+    /// `pu` of the update, or its `U` for the first update after a snapshot
+    pub actual: u64,
+}
+
 #[derive(Debug, Error)]
 pub enum TradingCoreError {
     #[error(transparent)]
@@ -64,6 +89,30 @@ pub enum TradingCoreError {
     Unknown(String),
 }
 
+impl TradingCoreError {
+    /// Whether the request surely never reached the exchange, so repeating it is safe even
+    /// for non-idempotent requests like placing an order
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            TradingCoreError::Connectivity(ConnectivityError::Network(err)) => err.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Whether the exchange banned the IP, or the request was held back for an earlier ban
+    pub fn is_banned(&self) -> bool {
+        matches!(self, TradingCoreError::Api(ApiError::Banned { .. }))
+    }
+
+    /// Whether a reduce-only order was refused because there was nothing left to reduce
+    pub fn is_reduce_only_rejected(&self) -> bool {
+        matches!(
+            self,
+            TradingCoreError::Api(ApiError::ReduceOnlyRejected { .. })
+        )
+    }
+}
+
 impl From<reqwest::Error> for TradingCoreError {
     fn from(err: reqwest::Error) -> Self {
         TradingCoreError::Connectivity(ConnectivityError::Network(err))
@@ -1,103 +1,439 @@
 use crate::error::{ApiError, MessageCodecError, Result, TradingCoreError};
-use crate::models::Order;
+use crate::gate::RequestGate;
+use crate::models::{DepthSnapshot, Order, OrderBook};
+use crate::retry::{RetryPolicy, retry};
+use crate::signer::Signer;
 use chrono::Utc;
 use data::{
     DataError,
-    binance::response::OrderSuccessResp,
-    config::AccountConfidential,
-    order::{Symbol, TimeInForce},
+    binance::response::{
+        BalanceResp, BatchOrderResp, ErrorResp, ExchangeInfoResp, OrderSuccessResp,
+        PositionRiskResp,
+    },
+    binance::subscription::{MarketStream, StreamCommand, StreamSpec, WsSession},
+    config::{AccountConfidential, EndpointMap, Environment, RestConfig},
+    order::Symbol,
+    time::LocalTime,
 };
-use hmac::{Hmac, Mac};
-use reqwest::{self, Response, StatusCode};
+use futures_util::future::join_all;
+use reqwest::{self, RequestBuilder, Response, StatusCode, header::HeaderMap};
+use rust_decimal::Decimal;
 use serde_json::Value;
-use sha2::Sha256;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tracing::{error, trace};
+use url::Url;
 use uuid::Uuid;
 
 #[derive(Debug)]
-pub struct Client {
+pub struct BinanceClient {
     pub api_key: String,
-    api_secret: String,
+    signer: Signer,
     http_client: reqwest::Client,
     endpoint: String,
+    market_ws: Url,
+    ws_config: WebSocketConfig,
+    ws_idle_timeout: Option<Duration>,
+    ban_fallback: Duration,
+    // end of the current IP ban, no request leaves before it
+    banned_until: Mutex<Option<Instant>>,
+}
+
+/// Binance accepts at most 5 orders per `batchOrders` request
+const MAX_BATCH_ORDERS: usize = 5;
+/// Binance accepts at most 10 client ids per batch cancel
+const MAX_BATCH_CANCELS: usize = 10;
+const DEFAULT_BAN_FALLBACK: Duration = Duration::from_secs(600);
+const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const PRODUCTION_REST: &str = "https://fapi.binance.com";
+const PRODUCTION_WS: &str = "wss://fstream.binance.com/ws";
+const TESTNET_REST: &str = "https://testnet.binancefuture.com";
+const TESTNET_WS: &str = "wss://stream.binancefuture.com/ws";
+const DEMO_REST: &str = "https://demo-fapi.binance.com";
+const DEMO_WS: &str = "wss://fstream.binancefuture.com/ws";
+
+pub type BatchOrderResult = std::result::Result<OrderSuccessResp, ApiError>;
+
+/// What the engine needs from a venue. Payloads are still the Binance models, another venue
+/// maps its own onto them.
+pub trait Exchange: Send + Sync {
+    fn open_order(&self, order: Order) -> impl Future<Output = Result<OrderSuccessResp>> + Send;
+
+    fn cancel_order(
+        &self,
+        symbol: Symbol,
+        client_id: Uuid,
+    ) -> impl Future<Output = Result<OrderSuccessResp>> + Send;
+
+    /// Cancel every open order of `symbol` in one request
+    fn cancel_all_orders(&self, symbol: Symbol) -> impl Future<Output = Result<()>> + Send;
+
+    /// Order book with the best `depth` levels of each side
+    fn fetch_snapshot(
+        &self,
+        symbol: Symbol,
+        depth: u16,
+    ) -> impl Future<Output = Result<OrderBook>> + Send;
+
+    /// Signed position of `symbol`, `None` if the venue reports none
+    fn get_position(&self, symbol: Symbol) -> impl Future<Output = Result<Option<Decimal>>> + Send;
+
+    /// Start a market data session on `streams` once `start_delay` has passed
+    fn subscribe(
+        &self,
+        streams: Vec<StreamSpec>,
+        start_delay: Duration,
+    ) -> impl Future<Output = Result<MarketFeed>> + Send;
+
+    /// Place orders concurrently within the gate, retrying those that never reached the exchange
+    fn open_orders(
+        &self,
+        requests: &[Order],
+        policy: &RetryPolicy,
+        gate: &RequestGate,
+    ) -> impl Future<Output = Vec<Result<OrderSuccessResp>>> + Send {
+        // TODO: the slowest order will block
+        join_all(requests.iter().copied().map(move |req| {
+            gate.run(retry(
+                policy,
+                move || self.open_order(req),
+                TradingCoreError::is_retriable,
+            ))
+        }))
+    }
+
+    /// Cancel orders one by one, results keep the request order
+    fn cancel_orders(
+        &self,
+        symbol: Symbol,
+        client_ids: &[Uuid],
+    ) -> impl Future<Output = Vec<Result<OrderSuccessResp>>> + Send {
+        async move {
+            let mut results = Vec::with_capacity(client_ids.len());
+            for &client_id in client_ids {
+                results.push(self.cancel_order(symbol, client_id).await);
+            }
+            results
+        }
+    }
+}
+
+/// Disconnect policy: with `cancel` set, pull every resting order of `symbols` since their fills,
+/// or the market they rest in, can no longer be seen. Otherwise they are left to GTD expiry and
+/// the countdown cancel. Returns whether cancels were sent.
+pub async fn cancel_on_disconnect<X: Exchange>(
+    exchange: &X,
+    symbols: &[Symbol],
+    cancel: bool,
+) -> Result<bool> {
+    if !cancel {
+        return Ok(false);
+    }
+    for &symbol in symbols {
+        exchange.cancel_all_orders(symbol).await?;
+    }
+    Ok(true)
+}
+
+/// Handle on a market data session, dropping `commands` ends the session
+#[derive(Debug)]
+pub struct MarketFeed {
+    pub commands: mpsc::Sender<StreamCommand>,
+    pub events: mpsc::Receiver<MarketStream>,
+}
+
+/// Shared HTTP client of the REST API, pooled connections tuned by `rest`
+pub fn http_client(rest: &RestConfig) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .tcp_nodelay(true)
+        .timeout(HTTP_REQUEST_TIMEOUT)
+        .pool_idle_timeout(
+            rest.pool_idle_timeout_ms
+                .map_or(DEFAULT_POOL_IDLE_TIMEOUT, Duration::from_millis),
+        )
+        .tcp_keepalive(rest.tcp_keepalive_ms.map(Duration::from_millis));
+    if let Some(max_idle) = rest.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if rest.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    Ok(builder.build()?)
 }
 
 fn map_api_error(status: StatusCode, body: String) -> ApiError {
     // TODO: parsing status & body correctly
-    match status {
-        StatusCode::TOO_MANY_REQUESTS => ApiError::RateLimit { status, body },
+    let code = serde_json::from_str::<ErrorResp>(&body)
+        .ok()
+        .map(|err| err.code());
+    map_api_code(status, code, body)
+}
+
+/// Ban length announced with a 418, Binance sends it in whole seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn map_api_code(status: StatusCode, code: Option<i64>, body: String) -> ApiError {
+    match (status, code) {
+        (StatusCode::TOO_MANY_REQUESTS, _) => ApiError::RateLimit { status, body },
+        (_, Some(-2019)) => ApiError::BalanceInsufficient { status, body },
+        (_, Some(-2013)) => ApiError::OrderNotFound { status, body },
+        (_, Some(-2022)) => ApiError::ReduceOnlyRejected { status, body },
         _ => ApiError::Unknown { status, body },
     }
 }
 
-impl Client {
+fn parse_batch_response(status: StatusCode, body: &str) -> Result<Vec<BatchOrderResult>> {
+    let results: Vec<BatchOrderResp> = serde_json::from_str(body)?;
+    Ok(results
+        .into_iter()
+        .map(|result| match result {
+            BatchOrderResp::Success(success) => Ok(success),
+            BatchOrderResp::Error(err) => {
+                Err(map_api_code(status, Some(err.code()), err.to_string()))
+            }
+        })
+        .collect())
+}
+
+/// `METHOD path?query` with the signature stripped, safe to log. The api key travels in the
+/// `X-MBX-APIKEY` header and is never part of the description.
+fn describe_request(method: &str, path: &str, query: &str) -> String {
+    let redacted: Vec<&str> = query
+        .split('&')
+        .map(|param| {
+            if param.starts_with("signature=") {
+                "signature=<redacted>"
+            } else {
+                param
+            }
+        })
+        .collect();
+    format!("{method} {path}?{}", redacted.join("&"))
+}
+
+/// Every configured symbol must be listed and trading, a typo or a delisted symbol would
+/// otherwise be subscribed and quoted without a single fill
+pub fn check_symbols(exchange_info: &ExchangeInfoResp, symbols: &[Symbol]) -> Result<()> {
+    for &symbol in symbols {
+        match exchange_info.symbol_status(symbol) {
+            Some("TRADING") => {}
+            Some(status) => {
+                return Err(TradingCoreError::ClientInitialization(format!(
+                    "symbol {symbol} is {status}, not TRADING"
+                )));
+            }
+            None => {
+                return Err(TradingCoreError::ClientInitialization(format!(
+                    "symbol {symbol} is not listed in exchangeInfo"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Credentials are issued per environment, signing with the wrong ones only shows up as an
+/// opaque auth failure on the first request
+fn check_environment(confidential: &AccountConfidential, environment: Environment) -> Result<()> {
+    if confidential.environment() != environment {
+        return Err(TradingCoreError::ClientInitialization(format!(
+            "account {} has {} credentials but account.environment is {}",
+            confidential.name,
+            confidential.environment().as_str(),
+            environment.as_str(),
+        )));
+    }
+    Ok(())
+}
+
+/// Configured endpoint of `environment`, `name` tells which section lacks it
+fn configured_endpoint<'a>(
+    endpoints: &'a EndpointMap,
+    environment: Environment,
+    name: &str,
+) -> Result<&'a str> {
+    endpoints.get(environment).ok_or_else(|| {
+        TradingCoreError::ClientInitialization(format!(
+            "exchange.{name}.endpoints has no {} endpoint",
+            environment.as_str()
+        ))
+    })
+}
+
+impl BinanceClient {
+    /// Client on the default endpoints of `environment`, for programs that source the
+    /// credentials themselves
+    ///
+    /// ```
+    /// use data::config::Environment;
+    /// use trading_core::exchange::BinanceClient;
+    ///
+    /// let client = BinanceClient::with_credentials(
+    ///     "api-key".to_string(),
+    ///     "api-secret".to_string(),
+    ///     Environment::Demo,
+    ///     reqwest::Client::new(),
+    /// );
+    /// assert_eq!(client.endpoint(), "https://demo-fapi.binance.com");
+    /// ```
+    pub fn with_credentials(
+        api_key: String,
+        api_secret: String,
+        environment: Environment,
+        http_client: reqwest::Client,
+    ) -> Self {
+        let (endpoint, market_ws) = match environment {
+            Environment::Production => (PRODUCTION_REST, PRODUCTION_WS),
+            Environment::Testnet => (TESTNET_REST, TESTNET_WS),
+            Environment::Demo => (DEMO_REST, DEMO_WS),
+        };
+        Self {
+            api_key,
+            signer: Signer::hmac_sha256(&api_secret),
+            http_client,
+            endpoint: endpoint.to_string(),
+            market_ws: Url::parse(market_ws).expect("Default endpoints are valid urls"),
+            ws_config: WebSocketConfig::default(),
+            ws_idle_timeout: None,
+            ban_fallback: DEFAULT_BAN_FALLBACK,
+            banned_until: Mutex::new(None),
+        }
+    }
+
+    /// Client for the account in `cfg`, credentials are read from its csv file and endpoints
+    /// taken from the config rather than the defaults
     pub fn from_config(
         cfg: &data::config::DataCenterConfig,
         http_client: reqwest::Client,
     ) -> Result<Self> {
         let confidential = AccountConfidential::from_csv(&cfg.account.name, &cfg.account.csv_path)?;
+        let environment = cfg.account.environment;
+        check_environment(&confidential, environment)?;
 
-        let endpoint = match cfg.account.environment {
-            data::config::Environment::Production => cfg.exchange.rest.endpoints.production.clone(),
-            data::config::Environment::Testnet => cfg.exchange.rest.endpoints.testnet.clone(),
-        };
+        let endpoint = configured_endpoint(&cfg.exchange.rest.endpoints, environment, "rest")?;
+        let ws_endpoint = configured_endpoint(&cfg.exchange.ws.endpoints, environment, "ws")?;
+        let market_ws = Url::parse(ws_endpoint).map_err(MessageCodecError::from)?;
 
         Ok(Self {
-            api_key: confidential.api_key,
-            api_secret: confidential.api_secret,
-            http_client,
-            endpoint,
+            signer: Signer::new(confidential.key_type, &confidential.api_secret)?,
+            endpoint: endpoint.to_string(),
+            market_ws,
+            ws_idle_timeout: cfg.exchange.ws.idle_timeout_ms.map(Duration::from_millis),
+            ban_fallback: cfg
+                .exchange
+                .ban_fallback_secs
+                .map_or(DEFAULT_BAN_FALLBACK, Duration::from_secs),
+            ..Self::with_credentials(
+                confidential.api_key,
+                confidential.api_secret,
+                environment,
+                http_client,
+            )
         })
     }
 
+    /// Base url of the REST API
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Url the market data sessions connect to
+    pub fn market_ws(&self) -> &Url {
+        &self.market_ws
+    }
+
+    /// Frame and buffer limits of the market data sessions
+    pub fn with_ws_config(mut self, ws_config: WebSocketConfig) -> Self {
+        self.ws_config = ws_config;
+        self
+    }
+
+    /// Time left on the current IP ban, `None` if requests may be sent
+    pub fn banned_for(&self) -> Option<Duration> {
+        let banned_until = (*self.banned_until.lock().expect("Ban lock poisoned"))?;
+        let remaining = banned_until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Send with the api key attached. While banned nothing is sent, a new ban suspends all
+    /// further requests for its Retry-After since each one would only extend it.
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        if let Some(remaining) = self.banned_for() {
+            return Err(TradingCoreError::from(ApiError::Banned {
+                retry_after: Some(remaining),
+                body: "held back locally".to_string(),
+            }));
+        }
+
+        let response = request.header("X-MBX-APIKEY", &self.api_key).send().await?;
+        if response.status() != StatusCode::IM_A_TEAPOT {
+            return Ok(response);
+        }
+
+        let retry_after = retry_after(response.headers());
+        let suspend = retry_after.unwrap_or(self.ban_fallback);
+        *self.banned_until.lock().expect("Ban lock poisoned") = Some(Instant::now() + suspend);
+        error!(
+            ?suspend,
+            "IP banned by the exchange, REST requests suspended"
+        );
+        let body = response.text().await?;
+        Err(TradingCoreError::from(ApiError::Banned {
+            retry_after,
+            body,
+        }))
+    }
+
     fn now_u64() -> u64 {
         Utc::now().timestamp_millis() as u64
     }
 
     pub fn sign(&self, query_string: &str) -> Result<String> {
-        let mut mac = Hmac::<Sha256>::new_from_slice(self.api_secret.as_bytes())
-            .map_err(MessageCodecError::from)?;
-        mac.update(query_string.as_bytes());
-        let result = mac.finalize();
-        let signature = hex::encode(result.into_bytes());
+        // an Ed25519 signature is base64, its `+`, `/` and `=` must be escaped
+        let signature: String =
+            url::form_urlencoded::byte_serialize(self.signer.sign(query_string).as_bytes())
+                .collect();
 
         let signed_request = format!("{}&signature={}", query_string, signature);
         Ok(signed_request)
     }
 
+    async fn signed_get(&self, path: &str, query: String) -> Result<Response> {
+        trace!(request = %describe_request("GET", path, &query), "Signed request");
+        let url = format!("{}{}?{}", self.endpoint, path, query);
+        self.send(self.http_client.get(url)).await
+    }
+
     async fn signed_post(&self, path: &str, body: String) -> Result<Response> {
+        trace!(request = %describe_request("POST", path, &body), "Signed request");
         let url = format!("{}{}", self.endpoint, path);
-        let response = self
-            .http_client
-            .post(url)
-            .header("X-MBX-APIKEY", &self.api_key)
-            .body(body)
-            .send()
-            .await?;
-        Ok(response)
+        self.send(self.http_client.post(url).body(body)).await
     }
 
     async fn signed_put(&self, path: &str, body: String) -> Result<Response> {
+        trace!(request = %describe_request("PUT", path, &body), "Signed request");
         let url = format!("{}{}", self.endpoint, path);
-        let response = self
-            .http_client
-            .put(url)
-            .header("X-MBX-APIKEY", &self.api_key)
-            .body(body)
-            .send()
-            .await?;
-        Ok(response)
+        self.send(self.http_client.put(url).body(body)).await
     }
 
     async fn signed_delete(&self, path: &str, body: String) -> Result<Response> {
+        trace!(request = %describe_request("DELETE", path, &body), "Signed request");
         // For Binance signed DELETE endpoints, send the signed query on the URL.
         let url = format!("{}{}?{}", self.endpoint, path, body);
-        let response = self
-            .http_client
-            .delete(url)
-            .header("X-MBX-APIKEY", &self.api_key)
-            .send()
-            .await?;
-        Ok(response)
+        self.send(self.http_client.delete(url)).await
     }
 
     pub async fn get_listen_key(&self) -> Result<String> {
@@ -147,9 +483,9 @@ impl Client {
         if let Some(symbol) = symbol {
             query_string.push_str(&format!("&symbol={}", symbol));
         }
-        let signed_request = self.sign("")?;
+        let signed_request = self.sign(&query_string)?;
         let response = self
-            .signed_put("/fapi/v1/openOrders", signed_request)
+            .signed_get("/fapi/v1/openOrders", signed_request)
             .await?;
 
         let status = response.status();
@@ -165,19 +501,183 @@ impl Client {
         Ok(orders)
     }
 
-    pub async fn open_order(&self, request: Order) -> Result<OrderSuccessResp> {
-        // TODO: remove this check
-        match (request.time_in_force(), request.good_till_date()) {
-            (TimeInForce::GoodUntilDate, Some(_)) => {}
-            (TimeInForce::GoodUntilDate, None) | (_, Some(_)) => {
-                return Err(DataError::BadDefinition {
-                    reason: "Unmatched timeInForce and goodTilDate",
-                }
-                .into());
+    /// Trading rules of every listed symbol, a public endpoint
+    pub async fn get_exchange_info(&self) -> Result<ExchangeInfoResp> {
+        let url = format!("{}/fapi/v1/exchangeInfo", self.endpoint);
+        let response = self.send(self.http_client.get(url)).await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Balance of every asset in the futures wallet
+    pub async fn get_balance(&self) -> Result<Vec<BalanceResp>> {
+        let query_string = format!("timestamp={}", Self::now_u64());
+        let signed_request = self.sign(&query_string)?;
+        let response = self.signed_get("/fapi/v2/balance", signed_request).await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    pub async fn get_position_risk(&self, symbol: Symbol) -> Result<Vec<PositionRiskResp>> {
+        let query_string = format!("symbol={}&timestamp={}", symbol, Self::now_u64());
+        let signed_request = self.sign(&query_string)?;
+        let response = self
+            .signed_get("/fapi/v3/positionRisk", signed_request)
+            .await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        let positions: Vec<PositionRiskResp> = serde_json::from_str(&body)?;
+
+        Ok(positions)
+    }
+
+    /// Place up to `MAX_BATCH_ORDERS` orders in one request, results keep the request order
+    pub async fn open_batch_orders(&self, requests: &[Order]) -> Result<Vec<BatchOrderResult>> {
+        if requests.len() > MAX_BATCH_ORDERS {
+            return Err(DataError::BadDefinition {
+                reason: "Too many orders in one batch",
             }
-            _ => {}
+            .into());
+        }
+
+        let batch = serde_json::to_string(requests)?;
+        let mut query_string = serde_urlencoded::to_string([("batchOrders", batch)])
+            .map_err(MessageCodecError::from)?;
+        query_string.push_str(&format!("&timestamp={}", Self::now_u64()));
+
+        let signed_request = self.sign(&query_string)?;
+        let response = self
+            .signed_post("/fapi/v1/batchOrders", signed_request)
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        parse_batch_response(status, &body)
+    }
+
+    async fn cancel_batch_orders(
+        &self,
+        symbol: Symbol,
+        client_ids: &[Uuid],
+    ) -> Result<Vec<BatchOrderResult>> {
+        let id_list = serde_json::to_string(client_ids)?;
+        let mut query_string = serde_urlencoded::to_string([
+            ("symbol", symbol.to_string()),
+            ("origClientOrderIdList", id_list),
+        ])
+        .map_err(MessageCodecError::from)?;
+        query_string.push_str(&format!("&timestamp={}", Self::now_u64()));
+
+        let signed_request = self.sign(&query_string)?;
+        let response = self
+            .signed_delete("/fapi/v1/batchOrders", signed_request)
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        let results = parse_batch_response(status, &body)?;
+        if results.len() != client_ids.len() {
+            return Err(TradingCoreError::Unknown(format!(
+                "batch cancel answered {} of {} orders",
+                results.len(),
+                client_ids.len()
+            )));
+        }
+        Ok(results)
+    }
+
+    /// Arm the exchange-side dead man's switch: all orders of `symbol` are cancelled unless this
+    /// is called again within `countdown_ms`. Zero disarms it.
+    pub async fn set_countdown_cancel(&self, symbol: Symbol, countdown_ms: u64) -> Result<()> {
+        let query_string = format!(
+            "symbol={}&countdownTime={}&timestamp={}",
+            symbol,
+            countdown_ms,
+            Self::now_u64()
+        );
+        let signed_request = self.sign(&query_string)?;
+        let response = self
+            .signed_post("/fapi/v1/countdownCancelAll", signed_request)
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        Ok(())
+    }
+
+    /// Current state of an order on the exchange. An order the exchange does not know (-2013)
+    /// is either terminal and archived or was never accepted, both resolve to `Ok(None)`.
+    pub async fn query_order(
+        &self,
+        symbol: Symbol,
+        client_id: Uuid,
+    ) -> Result<Option<OrderSuccessResp>> {
+        let query_string = format!(
+            "symbol={}&origClientOrderId={}&timestamp={}",
+            symbol,
+            client_id,
+            Self::now_u64()
+        );
+        let signed_request = self.sign(&query_string)?;
+        let response = self.signed_get("/fapi/v1/order", signed_request).await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return match map_api_error(status, body) {
+                ApiError::OrderNotFound { .. } => Ok(None),
+                api_err => Err(TradingCoreError::from(api_err)),
+            };
         }
 
+        let order: OrderSuccessResp = serde_json::from_str(&body)?;
+        Ok(Some(order))
+    }
+}
+
+impl Exchange for BinanceClient {
+    async fn open_order(&self, request: Order) -> Result<OrderSuccessResp> {
+        // rejected here rather than by the exchange, saves a request against the rate limit
+        request.check_time_in_force(LocalTime::now())?;
+
         // TODO: use copy? maybe benchmark first
         let mut query_string =
             serde_urlencoded::to_string(request).map_err(MessageCodecError::from)?;
@@ -200,13 +700,7 @@ impl Client {
         Ok(success)
     }
 
-    pub async fn open_orders(&self, requests: &[Order]) -> Vec<Result<OrderSuccessResp>> {
-        use futures_util::future::join_all;
-        // TODO: the slowest order will block
-        join_all(requests.iter().copied().map(|req| self.open_order(req))).await
-    }
-
-    pub async fn cancel_order(&self, symbol: Symbol, client_id: Uuid) -> Result<OrderSuccessResp> {
+    async fn cancel_order(&self, symbol: Symbol, client_id: Uuid) -> Result<OrderSuccessResp> {
         let query_string = format!(
             "symbol={}&origClientOrderId={}&timestamp={}",
             symbol,
@@ -226,6 +720,95 @@ impl Client {
         let success: OrderSuccessResp = serde_json::from_str(&body)?;
         Ok(success)
     }
+
+    /// Batches of `MAX_BATCH_CANCELS` instead of one request per order. A batch whose request
+    /// fails as a whole fails every order in it.
+    async fn cancel_orders(
+        &self,
+        symbol: Symbol,
+        client_ids: &[Uuid],
+    ) -> Vec<Result<OrderSuccessResp>> {
+        let mut results = Vec::with_capacity(client_ids.len());
+        for batch in client_ids.chunks(MAX_BATCH_CANCELS) {
+            match self.cancel_batch_orders(symbol, batch).await {
+                Ok(batch_results) => results.extend(
+                    batch_results
+                        .into_iter()
+                        .map(|result| result.map_err(TradingCoreError::from)),
+                ),
+                Err(TradingCoreError::Api(api_err)) => results.extend(
+                    batch
+                        .iter()
+                        .map(|_| Err(TradingCoreError::from(api_err.clone()))),
+                ),
+                Err(err) => {
+                    let reason = err.to_string();
+                    results.extend(
+                        batch
+                            .iter()
+                            .map(|_| Err(TradingCoreError::Unknown(reason.clone()))),
+                    )
+                }
+            }
+        }
+        results
+    }
+
+    async fn cancel_all_orders(&self, symbol: Symbol) -> Result<()> {
+        let query_string = format!("symbol={}&timestamp={}", symbol, Self::now_u64());
+        let signed_request = self.sign(&query_string)?;
+        let response = self
+            .signed_delete("/fapi/v1/allOpenOrders", signed_request)
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            let api_err = map_api_error(status, body);
+            return Err(TradingCoreError::from(api_err));
+        }
+
+        Ok(())
+    }
+
+    async fn fetch_snapshot(&self, symbol: Symbol, depth: u16) -> Result<OrderBook> {
+        let url = format!(
+            "{}/fapi/v1/depth?symbol={symbol}&limit={depth}",
+            self.endpoint
+        );
+        let response = self.send(self.http_client.get(url)).await?;
+        response.error_for_status_ref()?;
+        let snapshot = response.json::<DepthSnapshot>().await?;
+        Ok(OrderBook::from_depth_snapshot(symbol, snapshot))
+    }
+
+    async fn get_position(&self, symbol: Symbol) -> Result<Option<Decimal>> {
+        let positions = self.get_position_risk(symbol).await?;
+        Ok(positions
+            .iter()
+            .find(|position| position.symbol() == symbol)
+            .map(PositionRiskResp::position_amt))
+    }
+
+    async fn subscribe(
+        &self,
+        streams: Vec<StreamSpec>,
+        start_delay: Duration,
+    ) -> Result<MarketFeed> {
+        let (commands, cmd_rx) = mpsc::channel(32);
+        let (evt_tx, events) = mpsc::channel(1024);
+        WsSession::market(self.market_ws.clone(), self.ws_config, cmd_rx, evt_tx)
+            .with_start_delay(start_delay)
+            .with_idle_timeout(self.ws_idle_timeout)
+            .spawn_named("ws.market.session");
+        commands
+            .send(StreamCommand::Subscribe(streams))
+            .await
+            .map_err(|_| {
+                TradingCoreError::Unknown("market session ended before subscribing".to_string())
+            })?;
+        Ok(MarketFeed { commands, events })
+    }
 }
 
 #[cfg(test)]
@@ -239,11 +822,11 @@ mod tests {
     };
     use rust_decimal::{Decimal, dec};
 
-    fn make_client() -> Client {
+    fn make_client() -> BinanceClient {
         let cfg_path = std::env::var("CERAUNUS_CONFIG")
             .unwrap_or_else(|_| "../config/datacenter-config.toml".to_string());
         let cfg = DataCenterConfig::load(&cfg_path).expect("Failed to load config");
-        Client::from_config(&cfg, reqwest::Client::new()).expect("Failed to create client")
+        BinanceClient::from_config(&cfg, reqwest::Client::new()).expect("Failed to create client")
     }
 
     fn make_order() -> Order {
@@ -260,6 +843,408 @@ mod tests {
         )
     }
 
+    /// Serve a single canned HTTP response, returns the endpoint to point a client at
+    async fn mock_server(status_line: &'static str, body: &'static str) -> String {
+        mock_server_with_headers(status_line, "", body).await
+    }
+
+    /// `headers` are extra `name: value\r\n` lines of the response
+    async fn mock_server_with_headers(
+        status_line: &'static str,
+        headers: &'static str,
+        body: &'static str,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let addr = listener.local_addr().expect("Failed to read mock address");
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("Failed to accept");
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            let response = format!(
+                "HTTP/1.1 {status_line}\r\ncontent-type: application/json\r\n{headers}\
+                 content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+        format!("http://{addr}")
+    }
+
+    fn make_mock_client(endpoint: String) -> BinanceClient {
+        BinanceClient {
+            endpoint,
+            market_ws: Url::parse("ws://127.0.0.1:1").unwrap(),
+            ..BinanceClient::with_credentials(
+                "key".to_string(),
+                "secret".to_string(),
+                Environment::Testnet,
+                reqwest::Client::new(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_credentials_select_endpoints() {
+        let production = BinanceClient::with_credentials(
+            "key".to_string(),
+            "secret".to_string(),
+            Environment::Production,
+            reqwest::Client::new(),
+        );
+        assert_eq!(production.endpoint(), PRODUCTION_REST);
+        assert_eq!(production.market_ws().as_str(), PRODUCTION_WS);
+        assert_eq!(production.api_key, "key");
+
+        let testnet = BinanceClient::with_credentials(
+            "key".to_string(),
+            "secret".to_string(),
+            Environment::Testnet,
+            reqwest::Client::new(),
+        );
+        assert_eq!(testnet.endpoint(), TESTNET_REST);
+        assert_eq!(testnet.market_ws().as_str(), TESTNET_WS);
+
+        let demo = BinanceClient::with_credentials(
+            "key".to_string(),
+            "secret".to_string(),
+            Environment::Demo,
+            reqwest::Client::new(),
+        );
+        assert_eq!(demo.endpoint(), DEMO_REST);
+        assert_eq!(demo.market_ws().as_str(), DEMO_WS);
+    }
+
+    #[test]
+    fn test_configured_endpoint_per_environment() {
+        let endpoints = make_rest_config(false).endpoints;
+
+        assert_eq!(
+            configured_endpoint(&endpoints, Environment::Production, "rest").unwrap(),
+            PRODUCTION_REST
+        );
+        assert_eq!(
+            configured_endpoint(&endpoints, Environment::Testnet, "rest").unwrap(),
+            TESTNET_REST
+        );
+        assert_eq!(
+            configured_endpoint(&endpoints, Environment::Demo, "rest").unwrap(),
+            DEMO_REST
+        );
+
+        let without_demo = EndpointMap {
+            demo: None,
+            ..endpoints
+        };
+        let err = configured_endpoint(&without_demo, Environment::Demo, "rest").unwrap_err();
+        assert!(
+            matches!(err, TradingCoreError::ClientInitialization(msg) if msg.contains("no demo"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ban_suspends_requests() {
+        let endpoint = mock_server_with_headers(
+            "418 I'm a teapot",
+            "retry-after: 120\r\n",
+            r#"{"code": -1003, "msg": "Way too many requests; IP banned until 1579276876075."}"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+
+        let err = client.get_open_orders(Some(BNBUSDT)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            TradingCoreError::Api(ApiError::Banned {
+                retry_after: Some(retry_after),
+                ..
+            }) if retry_after == std::time::Duration::from_secs(120)
+        ));
+        let remaining = client.banned_for().expect("Client should be banned");
+        assert!(remaining > std::time::Duration::from_secs(115));
+
+        // the mock only answers once, a request reaching the network would fail to connect
+        let err = client.get_open_orders(Some(BNBUSDT)).await.unwrap_err();
+        assert!(err.is_banned());
+    }
+
+    #[tokio::test]
+    async fn test_query_order() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"{
+                "orderId": 1917641, "symbol": "BNBUSDT", "status": "PARTIALLY_FILLED",
+                "clientOrderId": "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61", "price": "69",
+                "avgPrice": "69", "origQty": "2", "executedQty": "1", "cumQuote": "69",
+                "timeInForce": "GTC", "type": "LIMIT", "side": "BUY", "time": 1579276756075,
+                "updateTime": 1579276756075
+            }"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+        let client_id = "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61".parse().unwrap();
+
+        let order = client
+            .query_order(BNBUSDT, client_id)
+            .await
+            .expect("Failed to query order")
+            .expect("Order should exist");
+
+        assert_eq!(order.client_order_id(), client_id);
+        assert_eq!(order.status(), OrderStatus::PartiallyFilled);
+        assert_eq!(order.executed_qty(), Decimal::ONE);
+    }
+
+    fn make_rest_config(http2_prior_knowledge: bool) -> RestConfig {
+        RestConfig {
+            endpoints: EndpointMap {
+                production: PRODUCTION_REST.to_string(),
+                testnet: TESTNET_REST.to_string(),
+                demo: Some(DEMO_REST.to_string()),
+            },
+            pool_max_idle: Some(1),
+            http2_prior_knowledge,
+            pool_idle_timeout_ms: Some(5_000),
+            tcp_keepalive_ms: Some(1_000),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_client_from_config() {
+        // the mock speaks HTTP/1.1 only, an HTTP/2 client cannot talk to it
+        let endpoint = mock_server("200 OK", "{}").await;
+        let http1 = http_client(&make_rest_config(false)).expect("Failed to build client");
+        let response = http1.get(&endpoint).send().await.expect("HTTP/1.1 failed");
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+
+        let endpoint = mock_server("200 OK", "{}").await;
+        let http2 = http_client(&make_rest_config(true)).expect("Failed to build client");
+        assert!(http2.get(&endpoint).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delisted_symbol_fails_startup() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"{"symbols": [
+                {"symbol": "SOLUSDT", "status": "TRADING", "filters": [
+                    {"filterType": "PERCENT_PRICE", "multiplierUp": "1.05", "multiplierDown": "0.95",
+                     "multiplierDecimal": "4"},
+                    {"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000", "tickSize": "0.01"}
+                ]},
+                {"symbol": "BTCUSDT", "status": "SETTLING", "filters": []},
+                {"symbol": "XRPUSDT", "status": "TRADING", "filters": []}
+            ]}"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+
+        let exchange_info = client
+            .get_exchange_info()
+            .await
+            .expect("Failed to get exchange info");
+
+        assert!(check_symbols(&exchange_info, &[Symbol::SOLUSDT]).is_ok());
+        let err = check_symbols(&exchange_info, &[Symbol::SOLUSDT, Symbol::BTCUSDT]).unwrap_err();
+        assert!(
+            matches!(err, TradingCoreError::ClientInitialization(msg) if msg.contains("BTCUSDT is SETTLING"))
+        );
+        let err = check_symbols(&exchange_info, &[Symbol::ETHUSDT]).unwrap_err();
+        assert!(
+            matches!(err, TradingCoreError::ClientInitialization(msg) if msg.contains("ETHUSDT"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_balance() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"[{
+                "accountAlias": "SgsR", "asset": "USDT", "balance": "122607.35137903",
+                "crossWalletBalance": "23.72469206", "crossUnPnl": "0.00000000",
+                "availableBalance": "23.72469206", "maxWithdrawAmount": "23.72469206",
+                "marginAvailable": true, "updateTime": 1617939110373
+            }]"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+
+        let balances = client.get_balance().await.expect("Failed to get balance");
+
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].asset(), data::order::Asset::USDT);
+        assert_eq!(balances[0].cross_wallet_balance(), dec!(23.72469206));
+    }
+
+    #[tokio::test]
+    async fn test_final_position_mismatch() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"[{
+                "symbol": "BNBUSDT", "positionAmt": "2", "entryPrice": "69",
+                "unRealizedProfit": "0", "updateTime": 1579276756075
+            }]"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+        let state = crate::engine::State::new(BNBUSDT);
+
+        let positions = client
+            .get_position_risk(BNBUSDT)
+            .await
+            .expect("Failed to get position risk");
+        let position = positions
+            .iter()
+            .find(|p| p.symbol() == BNBUSDT)
+            .map(|p| p.position_amt());
+
+        assert!(!state.check_final_position(position, dec!(0.001)));
+        assert!(state.check_final_position(position, dec!(2)));
+        assert!(!state.check_final_position(None, dec!(2)));
+    }
+
+    #[test]
+    fn test_request_description_is_redacted() {
+        let client = make_mock_client("http://localhost".to_string());
+        let query = "symbol=BNBUSDT&side=BUY&timestamp=1700000000000";
+        let signed = client.sign(query).unwrap();
+        let signature = signed.rsplit("signature=").next().unwrap();
+
+        let described = describe_request("POST", "/fapi/v1/order", &signed);
+
+        assert_eq!(
+            described,
+            format!("POST /fapi/v1/order?{query}&signature=<redacted>")
+        );
+        assert!(!described.contains(signature));
+        assert!(!described.contains(&client.api_key));
+        assert!(!described.contains("secret"));
+    }
+
+    #[test]
+    fn test_environment_mismatch_is_rejected() {
+        let path = std::env::temp_dir().join("ceraunus_env_mismatch.csv");
+        std::fs::write(
+            &path,
+            "account_name,api_key,api_secret,environment\n\
+             demo,key,secret,demo\n\
+             testnet,key,secret,testnet\n",
+        )
+        .expect("Failed to write csv");
+        let demo = AccountConfidential::from_csv("demo", &path).expect("Failed to load");
+        let testnet = AccountConfidential::from_csv("testnet", &path).expect("Failed to load");
+
+        assert!(check_environment(&demo, Environment::Demo).is_ok());
+        // demo credentials are good neither on production nor on the testnet
+        let err = check_environment(&demo, Environment::Production).unwrap_err();
+        assert!(matches!(
+            err,
+            TradingCoreError::ClientInitialization(msg)
+                if msg.contains("demo credentials") && msg.contains("is production")
+        ));
+        assert!(check_environment(&demo, Environment::Testnet).is_err());
+        assert!(check_environment(&testnet, Environment::Demo).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_countdown_cancel() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"{"symbol": "BNBUSDT", "countdownTime": "30000"}"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+
+        client
+            .set_countdown_cancel(BNBUSDT, 30_000)
+            .await
+            .expect("Failed to set countdown cancel");
+    }
+
+    #[tokio::test]
+    async fn test_query_unknown_order_is_none() {
+        let endpoint = mock_server(
+            "400 Bad Request",
+            r#"{"code": -2013, "msg": "Order does not exist."}"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+
+        let res = client.query_order(BNBUSDT, Uuid::new_v4()).await;
+
+        assert!(matches!(res, Ok(None)));
+    }
+
+    #[test]
+    fn test_parse_mixed_batch_response() {
+        let body = r#"[
+            {
+                "orderId": 22542179, "symbol": "BNBUSDT", "status": "FILLED",
+                "clientOrderId": "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61", "price": "69",
+                "origQty": "1", "executedQty": "1", "cumQty": "1", "cumQuote": "69",
+                "side": "BUY", "updateTime": 1566818724722
+            },
+            {"code": -2019, "msg": "Margin is insufficient."}
+        ]"#;
+        let results = parse_batch_response(StatusCode::OK, body).expect("Failed to parse batch");
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(&results[0], Ok(success) if success.status() == OrderStatus::Filled));
+        assert!(matches!(
+            &results[1],
+            Err(ApiError::BalanceInsufficient { body, .. }) if body.contains("-2019")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_orders_mixed_batch() {
+        let endpoint = mock_server(
+            "200 OK",
+            r#"[
+                {
+                    "orderId": 283194212, "symbol": "BNBUSDT", "status": "CANCELED",
+                    "clientOrderId": "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61", "price": "69",
+                    "origQty": "1", "executedQty": "0", "cumQty": "0", "cumQuote": "0",
+                    "side": "BUY", "updateTime": 1571110484038
+                },
+                {"code": -2011, "msg": "Unknown order sent."}
+            ]"#,
+        )
+        .await;
+        let client = make_mock_client(endpoint);
+        let client_ids = [
+            "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61".parse().unwrap(),
+            Uuid::new_v4(),
+        ];
+
+        let results = client.cancel_orders(BNBUSDT, &client_ids).await;
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            &results[0],
+            Ok(success) if success.status() == OrderStatus::Canceled
+                && success.client_order_id() == client_ids[0]
+        ));
+        assert!(matches!(
+            &results[1],
+            Err(TradingCoreError::Api(ApiError::Unknown { body, .. })) if body.contains("-2011")
+        ));
+    }
+
+    #[test]
+    fn test_reduce_only_rejection_is_mapped() {
+        let body = r#"{"code": -2022, "msg": "ReduceOnly Order is rejected."}"#;
+        let err = TradingCoreError::from(map_api_error(StatusCode::BAD_REQUEST, body.to_string()));
+        assert!(err.is_reduce_only_rejected());
+
+        let body = r#"{"code": -2019, "msg": "Margin is insufficient."}"#;
+        let err = TradingCoreError::from(map_api_error(StatusCode::BAD_REQUEST, body.to_string()));
+        assert!(!err.is_reduce_only_rejected());
+    }
+
     #[tokio::test]
     async fn test_get_listen_key() {
         let client = make_client();
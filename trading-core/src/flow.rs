@@ -0,0 +1,89 @@
+//! Rolling aggressor volume from the public trade streams, the input for trade-flow signals.
+
+use chrono::Duration;
+use data::order::Side;
+use data::time::ExchangeTime;
+use enum_map::EnumMap;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy)]
+struct FlowSample {
+    ts: ExchangeTime,
+    qty: Decimal,
+    aggressor: Side,
+}
+
+/// Volume taken by buyers and by sellers over the last `horizon` of trades
+#[derive(Debug, Clone)]
+pub struct TradeFlow {
+    samples: VecDeque<FlowSample>,
+    horizon: Duration,
+    // running sums of `samples`, by aggressor side
+    volume: EnumMap<Side, Decimal>,
+}
+
+impl TradeFlow {
+    pub fn new(horizon: Duration) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            horizon,
+            volume: EnumMap::default(),
+        }
+    }
+
+    /// Record a trade, trades older than the horizon from it are dropped
+    pub fn push(&mut self, ts: ExchangeTime, qty: Decimal, aggressor: Side) {
+        while let Some(first) = self.samples.front().copied() {
+            if ts - first.ts <= self.horizon {
+                break;
+            }
+            self.volume[first.aggressor] -= first.qty;
+            self.samples.pop_front();
+        }
+        self.volume[aggressor] += qty;
+        self.samples.push_back(FlowSample { ts, qty, aggressor });
+    }
+
+    pub fn trades(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Volume bought by aggressors, i.e. lifting the ask
+    pub fn buy_volume(&self) -> Decimal {
+        self.volume[Side::Buy]
+    }
+
+    /// Volume sold by aggressors, i.e. hitting the bid
+    pub fn sell_volume(&self) -> Decimal {
+        self.volume[Side::Sell]
+    }
+
+    /// Buy minus sell volume, positive when buyers are the more aggressive
+    pub fn net_volume(&self) -> Decimal {
+        self.buy_volume() - self.sell_volume()
+    }
+
+    /// Buy minus sell volume of the trades within `window` of the latest one,
+    /// capped at the horizon, zero without trades
+    pub fn imbalance(&self, window: Duration) -> Decimal {
+        let Some(latest) = self.samples.back() else {
+            return Decimal::ZERO;
+        };
+        self.samples
+            .iter()
+            .rev()
+            .take_while(|sample| latest.ts - sample.ts <= window)
+            .map(|sample| match sample.aggressor {
+                Side::Buy => sample.qty,
+                Side::Sell => -sample.qty,
+            })
+            .sum()
+    }
+}
+
+impl Default for TradeFlow {
+    fn default() -> Self {
+        Self::new(Duration::minutes(1))
+    }
+}
@@ -0,0 +1,71 @@
+//! Bound on in-flight exchange requests, so a burst of orders or cancels queues up locally
+//! instead of flooding the connection pool and the exchange rate limits.
+
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone)]
+pub struct RequestGate {
+    permits: Arc<Semaphore>,
+}
+
+impl RequestGate {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limit.max(1))),
+        }
+    }
+
+    /// Requests that may start right now without queuing
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Wait for a free slot, then run `fut` while holding it
+    pub async fn run<F: Future>(&self, fut: F) -> F::Output {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("Request gate semaphore is never closed");
+        fut.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gate_bounds_concurrency() {
+        let gate = RequestGate::new(2);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let cancels: Vec<_> = (0..10)
+            .map(|_| {
+                let gate = gate.clone();
+                let in_flight = Arc::clone(&in_flight);
+                let peak = Arc::clone(&peak);
+                tokio::spawn(async move {
+                    gate.run(async {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect();
+        for cancel in cancels {
+            cancel.await.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(gate.available(), 2);
+    }
+}
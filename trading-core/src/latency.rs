@@ -0,0 +1,183 @@
+//! How much earlier `TRADE_LITE` arrives than the verbose `ORDER_TRADE_UPDATE` of the same trade.
+
+use chrono::Duration;
+use data::time::LocalTime;
+use derive_getters::Getters;
+use rustc_hash::{FxBuildHasher, FxHashMap};
+
+#[derive(Debug, Clone, Copy)]
+enum Seen {
+    TradeLite(LocalTime),
+    OrderUpdate(LocalTime),
+}
+
+impl Seen {
+    fn received_at(&self) -> LocalTime {
+        match self {
+            Seen::TradeLite(ts) | Seen::OrderUpdate(ts) => *ts,
+        }
+    }
+}
+
+/// Summary of latency samples, e.g. the lead of `TRADE_LITE` over `ORDER_TRADE_UPDATE`, which
+/// is negative when the verbose update came first
+#[derive(Debug, Clone, Copy, Default, Getters)]
+pub struct LatencyStats {
+    count: u64,
+    #[getter(copy)]
+    total: Duration,
+    #[getter(copy)]
+    min: Option<Duration>,
+    #[getter(copy)]
+    max: Option<Duration>,
+}
+
+impl LatencyStats {
+    pub(crate) fn record(&mut self, lead: Duration) {
+        self.count += 1;
+        self.total += lead;
+        self.min = Some(self.min.map_or(lead, |min| min.min(lead)));
+        self.max = Some(self.max.map_or(lead, |max| max.max(lead)));
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count as i32)
+    }
+}
+
+/// Outcome of the IOC probes, the latency runs from sending to the final update
+#[derive(Debug, Clone, Copy, Default, Getters)]
+pub struct ProbeStats {
+    sent: u64,
+    filled: u64,
+    missed: u64,
+    latency: LatencyStats,
+}
+
+impl ProbeStats {
+    pub(crate) fn on_sent(&mut self) {
+        self.sent += 1;
+    }
+
+    /// A probe completed, with any fill at all or none. `latency` is `None` for a probe that
+    /// never reached the exchange.
+    pub(crate) fn on_completed(&mut self, filled: bool, latency: Option<Duration>) {
+        if filled {
+            self.filled += 1;
+        } else {
+            self.missed += 1;
+        }
+        if let Some(latency) = latency {
+            self.latency.record(latency);
+        }
+    }
+}
+
+/// Pairs the two account events of a trade by trade id, using local receipt times.
+/// A trade whose counterpart does not show up within `window` is dropped by [`Self::evict`].
+#[derive(Debug)]
+pub struct TradeLatency {
+    window: Duration,
+    pending: FxHashMap<u64, Seen>,
+    stats: LatencyStats,
+}
+
+impl TradeLatency {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: FxHashMap::with_capacity_and_hasher(64, FxBuildHasher),
+            stats: LatencyStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn on_trade_lite(&mut self, trade_id: u64, received_at: LocalTime) {
+        match self.pending.remove(&trade_id) {
+            Some(Seen::OrderUpdate(update_at)) => self.stats.record(update_at - received_at),
+            // a repeated TRADE_LITE keeps the first receipt
+            Some(seen @ Seen::TradeLite(_)) => {
+                self.pending.insert(trade_id, seen);
+            }
+            None => {
+                self.pending.insert(trade_id, Seen::TradeLite(received_at));
+            }
+        }
+    }
+
+    pub fn on_order_update(&mut self, trade_id: u64, received_at: LocalTime) {
+        match self.pending.remove(&trade_id) {
+            Some(Seen::TradeLite(lite_at)) => self.stats.record(received_at - lite_at),
+            Some(seen @ Seen::OrderUpdate(_)) => {
+                self.pending.insert(trade_id, seen);
+            }
+            None => {
+                self.pending
+                    .insert(trade_id, Seen::OrderUpdate(received_at));
+            }
+        }
+    }
+
+    /// Drop trades seen on one stream only for longer than the window, returns how many
+    pub fn evict(&mut self, now: LocalTime) -> usize {
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, seen| now - seen.received_at() < self.window);
+        before - self.pending.len()
+    }
+}
+
+impl Default for TradeLatency {
+    fn default() -> Self {
+        Self::new(Duration::seconds(10))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn ts(ms: i64) -> LocalTime {
+        LocalTime::from(DateTime::from_timestamp_millis(1_568_879_465_000 + ms).unwrap())
+    }
+
+    #[test]
+    fn test_lead_is_recorded() {
+        let mut latency = TradeLatency::default();
+        latency.on_trade_lite(1, ts(0));
+        latency.on_order_update(1, ts(3));
+        // verbose update first
+        latency.on_order_update(2, ts(10));
+        latency.on_trade_lite(2, ts(11));
+
+        let stats = latency.stats();
+        assert_eq!(stats.count(), 2);
+        assert_eq!(stats.min(), Some(Duration::milliseconds(-1)));
+        assert_eq!(stats.max(), Some(Duration::milliseconds(3)));
+        assert_eq!(stats.mean(), Some(Duration::milliseconds(1)));
+        assert_eq!(latency.pending(), 0);
+    }
+
+    #[test]
+    fn test_unpaired_trades_are_evicted() {
+        let mut latency = TradeLatency::new(Duration::seconds(1));
+        latency.on_trade_lite(1, ts(0));
+        latency.on_order_update(2, ts(800));
+
+        assert_eq!(latency.evict(ts(1_500)), 1);
+        assert_eq!(latency.pending(), 1);
+
+        // the counterpart of an evicted trade starts over instead of pairing
+        latency.on_order_update(1, ts(1_600));
+        assert_eq!(latency.stats().count(), 0);
+        assert_eq!(latency.pending(), 2);
+    }
+}
@@ -1,8 +1,19 @@
+pub mod client_id;
 pub mod engine;
 pub mod error;
 pub mod exchange;
+pub mod flow;
+pub mod gate;
+pub mod latency;
 pub mod models;
+pub mod recovery;
+pub mod report;
+pub mod retry;
+pub mod shared;
+pub mod signer;
+pub mod spread;
 pub mod strategy;
+pub mod watchdog;
 
 pub use error::{ApiError, ConnectivityError, Error, Result, TradingCoreError};
 pub use models::OrderBook;
@@ -1,19 +1,21 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration};
+use data::DataError;
 use data::binance::account::OrderTradeUpdateEvent;
-use data::binance::market::{Depth, Level};
+use data::binance::market::{Depth, Level, PartialDepth};
 use data::order::*;
+use data::time::{ExchangeTime, LocalTime};
 use derive_getters::Getters;
 use reqwest::Client;
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
 use std::fmt::{self, Formatter};
 use tracing::warn;
 use uuid::Uuid;
 
-use crate::error::Result as TradingCoreResult;
+use crate::error::{DepthGapError, Result as TradingCoreResult};
 
-type BboPair = (Level, Level); // (bid_level, ask_level)
+pub type BboPair = (Level, Level); // (bid_level, ask_level)
 
 /// Local record for an order
 #[derive(Debug, Clone, Copy, Serialize, Getters)]
@@ -22,7 +24,7 @@ pub struct Order {
     side: Side,
     #[getter(copy)]
     #[serde(skip)]
-    start_ts: DateTime<Utc>,
+    start_ts: LocalTime,
     #[serde(skip_serializing)]
     order_id: Option<u64>,
     #[serde(rename = "newClientOrderId")]
@@ -30,7 +32,7 @@ pub struct Order {
     client_order_id: Uuid,
     #[serde(skip_serializing)]
     #[getter(copy)]
-    last_update_ts: DateTime<Utc>,
+    last_update_ts: LocalTime,
 
     #[serde(rename = "type")]
     kind: OrderKind, // a limit order can be transformed into market order due to price drift
@@ -38,6 +40,12 @@ pub struct Order {
     curr_price: Decimal,
     #[serde(skip_serializing)]
     curr_qty: Decimal,
+    /// Cumulative filled quantity across partial fills
+    #[serde(skip_serializing)]
+    filled_qty: Decimal,
+    /// Average price over `filled_qty`, zero until the first fill
+    #[serde(skip_serializing)]
+    avg_fill_price: Decimal,
     #[serde(rename = "price")]
     orig_price: Decimal,
     #[serde(rename = "quantity")]
@@ -46,8 +54,108 @@ pub struct Order {
     time_in_force: TimeInForce,
     #[serde(rename = "goodTillDate", skip_serializing_if = "Option::is_none")]
     good_till_date: Option<u64>,
+    #[serde(
+        rename = "selfTradePreventionMode",
+        skip_serializing_if = "Option::is_none"
+    )]
+    stp_mode: Option<SelfTradePreventionMode>,
     #[serde(skip_serializing)]
     status: Option<OrderStatus>,
+    #[serde(skip_serializing)]
+    #[getter(copy)]
+    last_trade_id: Option<u64>,
+}
+
+/// Every field of an [`Order`], for persisting it across restarts. The `Serialize` impl of
+/// `Order` itself is the request body sent to the exchange.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OrderSnapshot {
+    symbol: Symbol,
+    side: Side,
+    /// Local times in milliseconds
+    start_ts: i64,
+    last_update_ts: i64,
+    order_id: Option<u64>,
+    client_order_id: Uuid,
+    kind: OrderKind,
+    curr_price: Decimal,
+    curr_qty: Decimal,
+    filled_qty: Decimal,
+    avg_fill_price: Decimal,
+    orig_price: Decimal,
+    orig_qty: Decimal,
+    time_in_force: TimeInForce,
+    good_till_date: Option<u64>,
+    stp_mode: Option<SelfTradePreventionMode>,
+    status: Option<OrderStatus>,
+    last_trade_id: Option<u64>,
+}
+
+impl From<&Order> for OrderSnapshot {
+    fn from(order: &Order) -> Self {
+        Self {
+            symbol: order.symbol,
+            side: order.side,
+            start_ts: order.start_ts.timestamp_millis(),
+            last_update_ts: order.last_update_ts.timestamp_millis(),
+            order_id: order.order_id,
+            client_order_id: order.client_order_id,
+            kind: order.kind,
+            curr_price: order.curr_price,
+            curr_qty: order.curr_qty,
+            filled_qty: order.filled_qty,
+            avg_fill_price: order.avg_fill_price,
+            orig_price: order.orig_price,
+            orig_qty: order.orig_qty,
+            time_in_force: order.time_in_force,
+            good_till_date: order.good_till_date,
+            stp_mode: order.stp_mode,
+            status: order.status,
+            last_trade_id: order.last_trade_id,
+        }
+    }
+}
+
+impl From<OrderSnapshot> for Order {
+    fn from(snapshot: OrderSnapshot) -> Self {
+        // out of range times only come from a corrupt file, count them from now
+        let local_time = |ms| {
+            DateTime::from_timestamp_millis(ms)
+                .map(LocalTime::from)
+                .unwrap_or_else(LocalTime::now)
+        };
+        Self {
+            symbol: snapshot.symbol,
+            side: snapshot.side,
+            start_ts: local_time(snapshot.start_ts),
+            last_update_ts: local_time(snapshot.last_update_ts),
+            order_id: snapshot.order_id,
+            client_order_id: snapshot.client_order_id,
+            kind: snapshot.kind,
+            curr_price: snapshot.curr_price,
+            curr_qty: snapshot.curr_qty,
+            filled_qty: snapshot.filled_qty,
+            avg_fill_price: snapshot.avg_fill_price,
+            orig_price: snapshot.orig_price,
+            orig_qty: snapshot.orig_qty,
+            time_in_force: snapshot.time_in_force,
+            good_till_date: snapshot.good_till_date,
+            stp_mode: snapshot.stp_mode,
+            status: snapshot.status,
+            last_trade_id: snapshot.last_trade_id,
+        }
+    }
+}
+
+/// Binance rejects a `goodTillDate` less than this far ahead
+pub const GTD_MIN_WINDOW: Duration = Duration::minutes(10);
+/// Slack on top of [`GTD_MIN_WINDOW`] for generated dates, covers request latency and clock skew
+const GTD_MARGIN: Duration = Duration::seconds(5);
+
+/// `goodTillDate` in milliseconds, `ttl` after `now` but never sooner than the exchange accepts
+pub fn good_till_date(now: LocalTime, ttl: Duration) -> u64 {
+    let ttl = ttl.max(GTD_MIN_WINDOW + GTD_MARGIN);
+    (now + ttl).timestamp_millis() as u64
 }
 
 impl Order {
@@ -60,7 +168,7 @@ impl Order {
         time_in_force: TimeInForce,
         good_till_date: Option<u64>,
     ) -> Self {
-        let now = Utc::now();
+        let now = LocalTime::now();
         Self {
             symbol,
             side,
@@ -71,21 +179,120 @@ impl Order {
             kind,
             curr_price: price,
             curr_qty: quantity,
+            filled_qty: Decimal::ZERO,
+            avg_fill_price: Decimal::ZERO,
             orig_price: price,
             orig_qty: quantity,
             time_in_force,
             good_till_date,
+            stp_mode: Some(SelfTradePreventionMode::default()),
             status: None,
+            last_trade_id: None,
+        }
+    }
+
+    /// Like [`Order::new`], but rejects non-positive quantities and limit prices
+    /// so that sizing bugs surface locally instead of as exchange rejections
+    pub fn try_new(
+        symbol: Symbol,
+        side: Side,
+        kind: OrderKind,
+        price: Decimal,
+        quantity: Decimal,
+        time_in_force: TimeInForce,
+        good_till_date: Option<u64>,
+    ) -> TradingCoreResult<Self> {
+        if kind == OrderKind::Limit && price <= Decimal::ZERO {
+            return Err(DataError::BadDefinition {
+                reason: "Order price must be positive",
+            }
+            .into());
         }
+        if quantity <= Decimal::ZERO {
+            return Err(DataError::BadDefinition {
+                reason: "Order quantity must be positive",
+            }
+            .into());
+        }
+        Ok(Self::new(
+            symbol,
+            side,
+            kind,
+            price,
+            quantity,
+            time_in_force,
+            good_till_date,
+        ))
+    }
+
+    /// `goodTillDate` is set with GTD only, and then at least [`GTD_MIN_WINDOW`] after `now`
+    pub fn check_time_in_force(&self, now: LocalTime) -> TradingCoreResult<()> {
+        match (self.time_in_force, self.good_till_date) {
+            (TimeInForce::GoodUntilDate, Some(gtd)) => {
+                let earliest = (now + GTD_MIN_WINDOW).timestamp_millis();
+                if (gtd as i64) < earliest {
+                    return Err(DataError::BadDefinition {
+                        reason: "goodTillDate is less than 10 minutes ahead",
+                    }
+                    .into());
+                }
+                Ok(())
+            }
+            (TimeInForce::GoodUntilDate, None) | (_, Some(_)) => Err(DataError::BadDefinition {
+                reason: "Unmatched timeInForce and goodTilDate",
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn with_client_order_id(mut self, client_order_id: Uuid) -> Self {
+        self.client_order_id = client_order_id;
+        self
+    }
+
+    /// Override the self-trade prevention mode, `None` leaves it to the exchange default
+    pub fn with_stp_mode(mut self, stp_mode: Option<SelfTradePreventionMode>) -> Self {
+        self.stp_mode = stp_mode;
+        self
+    }
+
+    /// Quoted value of the order in USDT
+    pub fn notional(&self) -> Decimal {
+        self.orig_price * self.orig_qty
+    }
+
+    /// Shrink the quantity so that the notional stays within `max_notional`,
+    /// returns false if nothing is left to quote
+    pub fn clamp_notional(&mut self, max_notional: Decimal) -> bool {
+        if self.notional() <= max_notional {
+            return true;
+        }
+        let qty = (max_notional / self.orig_price)
+            .round_dp_with_strategy(self.orig_qty.scale(), RoundingStrategy::ToZero);
+        if qty <= Decimal::ZERO {
+            return false;
+        }
+        self.orig_qty = qty;
+        self.curr_qty = qty;
+        true
     }
 
     pub fn on_update_received(&mut self, update_event: &OrderTradeUpdateEvent) {
-        // TODO: what timestamp is best here?
-        self.last_update_ts = update_event.transaction_time();
+        // local receipt time, staleness is judged against the local clock
+        self.last_update_ts = LocalTime::now();
         self.order_id = Some(update_event.order_id());
         self.status = Some(update_event.order_status());
         self.curr_price = update_event.last_filled_price();
         self.curr_qty = update_event.last_filled_qty();
+        // the exchange already sums the partials, an older update arriving late must not undo that
+        if update_event.filled_qty() >= self.filled_qty {
+            self.filled_qty = update_event.filled_qty();
+            self.avg_fill_price = update_event.avg_price();
+        }
+        if !update_event.last_filled_qty().is_zero() {
+            self.last_trade_id = Some(update_event.trade_id());
+        }
         if update_event.order_kind() == OrderKind::Market && self.kind == OrderKind::Limit {
             warn!(
                 client_id = %update_event.client_order_id(),
@@ -102,25 +309,73 @@ impl Order {
 
 type Price = Decimal;
 type Quantity = Decimal;
-#[derive(Debug, Getters)]
+#[derive(Debug, Clone, Getters)]
 pub struct OrderBook {
     symbol: Symbol,
-    local_ts: DateTime<Utc>,
-    xchg_ts: DateTime<Utc>,
+    local_ts: LocalTime,
+    xchg_ts: ExchangeTime,
     last_update_id: u64,
     bids: BTreeMap<Price, Quantity>,
     asks: BTreeMap<Price, Quantity>,
+    #[getter(skip)]
+    top_cache: Option<TopLevels>,
+    // a depth update has followed on from the snapshot, from then on updates chain by `pu`
+    #[getter(skip)]
+    bridged: bool,
+}
+
+/// Best levels of each side, best first, kept in sync by [`OrderBook::extend`]
+#[derive(Debug, Clone)]
+struct TopLevels {
+    depth: usize,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+}
+
+impl TopLevels {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            bids: Vec::with_capacity(depth),
+            asks: Vec::with_capacity(depth),
+        }
+    }
+
+    /// Whether a bid update at `price` can change the cached window
+    fn touches_bids(&self, price: Price) -> bool {
+        self.bids.len() < self.depth || self.bids.last().is_none_or(|worst| price >= worst.price)
+    }
+
+    /// Whether an ask update at `price` can change the cached window
+    fn touches_asks(&self, price: Price) -> bool {
+        self.asks.len() < self.depth || self.asks.last().is_none_or(|worst| price <= worst.price)
+    }
+
+    fn refresh_bids(&mut self, bids: &BTreeMap<Price, Quantity>) {
+        self.bids.clear();
+        self.bids
+            .extend(bids.iter().rev().take(self.depth).map(Level::from));
+    }
+
+    fn refresh_asks(&mut self, asks: &BTreeMap<Price, Quantity>) {
+        self.asks.clear();
+        self.asks
+            .extend(asks.iter().take(self.depth).map(Level::from));
+    }
 }
 
 impl OrderBook {
     pub fn new(symbol: Symbol) -> Self {
         OrderBook {
             symbol,
-            local_ts: Utc::now(),
-            xchg_ts: Utc::now(),
+            local_ts: LocalTime::now(),
+            // no update seen yet
+            xchg_ts: ExchangeTime::from(DateTime::UNIX_EPOCH),
             last_update_id: 0, // this is the id for the depth update
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            top_cache: None,
+            bridged: false,
         }
     }
 
@@ -135,43 +390,100 @@ impl OrderBook {
 
         response.error_for_status_ref()?;
         let snapshot = response.json::<DepthSnapshot>().await?;
-        Ok(OrderBook {
+        Ok(Self::from_depth_snapshot(symbol, snapshot))
+    }
+
+    pub(crate) fn from_depth_snapshot(symbol: Symbol, snapshot: DepthSnapshot) -> Self {
+        OrderBook {
             symbol,
-            local_ts: Utc::now(),
+            local_ts: LocalTime::now(),
             last_update_id: snapshot.last_update_id,
             xchg_ts: snapshot.xchg_ts,
             bids: snapshot.bids,
             asks: snapshot.asks,
-        })
+            top_cache: None,
+            bridged: false,
+        }
     }
 
-    pub fn show(&self, depth: usize) -> String {
-        //TODO: benchmark the perf
-        format!(
-            "[B:{}|A:{}]",
-            self.bids
+    /// Book holding only the levels of a partial depth frame, see [`State::on_partial_depth`]
+    ///
+    /// [`State::on_partial_depth`]: crate::engine::State::on_partial_depth
+    pub fn from_partial_depth(partial: &PartialDepth) -> Self {
+        let side = |levels: &[Level]| -> BTreeMap<Price, Quantity> {
+            levels
                 .iter()
-                .rev()
-                .take(depth)
-                .map(|(p, q)| format!("{}@{}", q, p))
-                .collect::<Vec<_>>()
-                .join(","),
-            self.asks
-                .iter()
-                .take(depth)
-                .map(|(p, q)| format!("{}@{}", q, p))
-                .collect::<Vec<_>>()
-                .join(",")
-        )
+                .filter(|level| !level.quantity.is_zero())
+                .map(|level| (level.price, level.quantity))
+                .collect()
+        };
+        OrderBook {
+            symbol: partial.symbol(),
+            local_ts: LocalTime::now(),
+            last_update_id: partial.final_update_id(),
+            xchg_ts: partial.transaction_time(),
+            bids: side(partial.bids().as_slice()),
+            asks: side(partial.asks().as_slice()),
+            top_cache: None,
+            bridged: true,
+        }
+    }
+
+    pub fn show(&self, depth: usize) -> String {
+        self.book_top(depth).to_string()
+    }
+
+    /// Keep the best `depth` levels of each side cached for [`OrderBook::cached_top`].
+    /// Updates outside the cached window are then cheap, those inside rebuild the touched side.
+    pub fn enable_top_cache(&mut self, depth: usize) {
+        let mut cache = TopLevels::new(depth);
+        cache.refresh_bids(&self.bids);
+        cache.refresh_asks(&self.asks);
+        self.top_cache = Some(cache);
+    }
+
+    /// Apply `depth` if it follows on from the book, per the Binance futures rules: updates
+    /// older than the snapshot are skipped, the first one applied must straddle it
+    /// (`U <= lastUpdateId + 1`, `u >= lastUpdateId`) and every later one chains by
+    /// `pu == ` the previous `u`. The book is left untouched on error.
+    pub fn apply_depth(&mut self, depth: &Depth) -> Result<(), DepthGapError> {
+        if self.bridged {
+            if depth.last_final_update_id() != self.last_update_id {
+                return Err(DepthGapError {
+                    expected: self.last_update_id,
+                    actual: depth.last_final_update_id(),
+                });
+            }
+        } else {
+            if depth.final_update_id() < self.last_update_id {
+                return Ok(()); // already in the snapshot
+            }
+            if depth.first_update_id() > self.last_update_id + 1 {
+                return Err(DepthGapError {
+                    expected: self.last_update_id,
+                    actual: depth.first_update_id(),
+                });
+            }
+        }
+        self.extend(depth);
+        self.bridged = true;
+        Ok(())
     }
 
-    pub fn extend(&mut self, depth: Depth) {
-        // WARN: This is a dumb method, please check the last_update_id by yourself
+    /// Apply `depth` without checking the update ids, see [`OrderBook::apply_depth`]
+    pub fn extend(&mut self, depth: &Depth) {
         self.xchg_ts = depth.transaction_time();
-        self.local_ts = Utc::now();
+        self.local_ts = LocalTime::now();
         self.last_update_id = depth.final_update_id();
 
+        let mut bids_dirty = false;
+        let mut asks_dirty = false;
+
         for level in depth.bids() {
+            bids_dirty |= self
+                .top_cache
+                .as_ref()
+                .is_some_and(|cache| cache.touches_bids(level.price));
             if level.quantity.is_zero() {
                 self.bids.remove(&level.price);
             } else {
@@ -180,12 +492,140 @@ impl OrderBook {
         }
 
         for level in depth.asks() {
+            asks_dirty |= self
+                .top_cache
+                .as_ref()
+                .is_some_and(|cache| cache.touches_asks(level.price));
             if level.quantity.is_zero() {
                 self.asks.remove(&level.price);
             } else {
                 self.asks.insert(level.price, level.quantity);
             }
         }
+
+        if let Some(cache) = &mut self.top_cache {
+            if bids_dirty {
+                cache.refresh_bids(&self.bids);
+            }
+            if asks_dirty {
+                cache.refresh_asks(&self.asks);
+            }
+        }
+        self.debug_assert_book_ok();
+    }
+
+    /// Sides do not cross and every level has a positive quantity
+    pub fn is_healthy(&self) -> bool {
+        let crossed = matches!(
+            (self.bids.last_key_value(), self.asks.first_key_value()),
+            (Some((bid, _)), Some((ask, _))) if bid >= ask
+        );
+        !crossed
+            && self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .all(|qty| qty.is_sign_positive() && !qty.is_zero())
+    }
+
+    /// Catch book maintenance bugs in tests and debug builds, free in release
+    pub(crate) fn debug_assert_book_ok(&self) {
+        debug_assert!(
+            self.is_healthy(),
+            "Order book invariant violated: {}",
+            self.book_top(5)
+        );
+    }
+
+    /// Best `depth` levels of each side, best first, walking the book
+    pub fn top(&self, depth: usize) -> (Vec<Level>, Vec<Level>) {
+        (
+            self.bids
+                .iter()
+                .rev()
+                .take(depth)
+                .map(Level::from)
+                .collect(),
+            self.asks.iter().take(depth).map(Level::from).collect(),
+        )
+    }
+
+    /// Same as [`OrderBook::top`], as a value that renders only when displayed
+    pub fn book_top(&self, depth: usize) -> BookTop {
+        let (bids, asks) = self.top(depth);
+        BookTop { bids, asks }
+    }
+
+    /// Same as [`OrderBook::top`] for the cached depth, `None` unless the cache is enabled
+    pub fn cached_top(&self) -> Option<(&[Level], &[Level])> {
+        self.top_cache
+            .as_ref()
+            .map(|cache| (cache.bids.as_slice(), cache.asks.as_slice()))
+    }
+
+    /// Quantity resting at `price` on one side, bids for [`Side::Buy`], zero without a level
+    pub fn level_qty(&self, side: Side, price: Price) -> Quantity {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.get(&price).copied().unwrap_or(Decimal::ZERO)
+    }
+
+    /// Notional resting within the best `depth` levels of one side, bids for [`Side::Buy`].
+    /// `None` if that side is empty.
+    pub fn side_notional(&self, side: Side, depth: usize) -> Option<Decimal> {
+        let notional = |level: (&Price, &Quantity)| Level::from(level).notional();
+        match side {
+            Side::Buy if !self.bids.is_empty() => {
+                Some(self.bids.iter().rev().take(depth).map(notional).sum())
+            }
+            Side::Sell if !self.asks.is_empty() => {
+                Some(self.asks.iter().take(depth).map(notional).sum())
+            }
+            _ => None,
+        }
+    }
+
+    /// Which sides hold any level
+    pub fn shape(&self) -> BookShape {
+        match (self.bids.is_empty(), self.asks.is_empty()) {
+            (true, true) => BookShape::Empty,
+            (false, true) => BookShape::BidsOnly,
+            (true, false) => BookShape::AsksOnly,
+            (false, false) => BookShape::TwoSided,
+        }
+    }
+
+    pub fn mid_price(&self) -> Option<Price> {
+        let (bid, ask) = self.get_bbo()?;
+        Some((bid.price + ask.price) / Decimal::TWO)
+    }
+
+    /// Average fill price of a market order of `side` for `qty`, walking the opposite side of
+    /// the book. `None` if the book cannot absorb `qty`.
+    pub fn vwap(&self, side: Side, qty: Quantity) -> Option<Price> {
+        match side {
+            Side::Buy => walk_vwap(self.asks.iter(), qty),
+            Side::Sell => walk_vwap(self.bids.iter().rev(), qty),
+        }
+    }
+
+    /// Cost of crossing with `qty` on `side`, in basis points from the best opposite price to
+    /// the VWAP. `None` if the book cannot absorb `qty`.
+    pub fn price_impact(&self, side: Side, qty: Quantity) -> Option<Decimal> {
+        let vwap = self.vwap(side, qty)?;
+        let (best, slippage) = match side {
+            Side::Buy => {
+                let (best, _) = self.asks.first_key_value()?;
+                (*best, vwap - *best)
+            }
+            Side::Sell => {
+                let (best, _) = self.bids.last_key_value()?;
+                (*best, *best - vwap)
+            }
+        };
+        Some(slippage / best * Decimal::from(10_000))
     }
 
     pub fn get_bbo(&self) -> Option<BboPair> {
@@ -193,6 +633,107 @@ impl OrderBook {
         let (ap, aq) = self.asks.first_key_value()?;
         Some((Level::from((bp, bq)), Level::from((ap, aq))))
     }
+
+    /// Aggregate change from `previous` to this book, e.g. consecutive frames of one symbol
+    pub fn diff(&self, previous: &OrderBook) -> BookDiff {
+        BookDiff {
+            from_update_id: previous.last_update_id,
+            to_update_id: self.last_update_id,
+            bids: SideDiff::between(
+                &previous.bids,
+                &self.bids,
+                previous.bids.last_key_value(),
+                self.bids.last_key_value(),
+            ),
+            asks: SideDiff::between(
+                &previous.asks,
+                &self.asks,
+                previous.asks.first_key_value(),
+                self.asks.first_key_value(),
+            ),
+        }
+    }
+}
+
+/// What changed between two order books, see [`OrderBook::diff`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BookDiff {
+    pub from_update_id: u64,
+    pub to_update_id: u64,
+    pub bids: SideDiff,
+    pub asks: SideDiff,
+}
+
+/// Change of one side of the book
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SideDiff {
+    /// Quantity on levels that appeared or grew
+    pub added_qty: Quantity,
+    /// Quantity on levels that disappeared or shrank
+    pub removed_qty: Quantity,
+    pub new_levels: usize,
+    pub removed_levels: usize,
+    /// Best price now minus before, `None` if either book had this side empty
+    pub touch_move: Option<Price>,
+}
+
+impl SideDiff {
+    fn between(
+        previous: &BTreeMap<Price, Quantity>,
+        current: &BTreeMap<Price, Quantity>,
+        previous_best: Option<(&Price, &Quantity)>,
+        current_best: Option<(&Price, &Quantity)>,
+    ) -> Self {
+        let mut diff = SideDiff {
+            touch_move: previous_best
+                .zip(current_best)
+                .map(|((before, _), (now, _))| *now - *before),
+            ..SideDiff::default()
+        };
+        for (price, qty) in current {
+            let before = previous.get(price).copied().unwrap_or_else(|| {
+                diff.new_levels += 1;
+                Decimal::ZERO
+            });
+            if *qty > before {
+                diff.added_qty += *qty - before;
+            } else {
+                diff.removed_qty += before - *qty;
+            }
+        }
+        for (price, qty) in previous {
+            if !current.contains_key(price) {
+                diff.removed_levels += 1;
+                diff.removed_qty += *qty;
+            }
+        }
+        diff
+    }
+
+    /// Liquidity added minus removed
+    pub fn net_qty(&self) -> Quantity {
+        self.added_qty - self.removed_qty
+    }
+}
+
+fn walk_vwap<'a>(
+    levels: impl Iterator<Item = (&'a Price, &'a Quantity)>,
+    qty: Quantity,
+) -> Option<Price> {
+    if qty <= Decimal::ZERO {
+        return None;
+    }
+    let mut remaining = qty;
+    let mut notional = Decimal::ZERO;
+    for (price, level_qty) in levels {
+        let taken = remaining.min(*level_qty);
+        notional += *price * taken;
+        remaining -= taken;
+        if remaining.is_zero() {
+            return Some(notional / qty);
+        }
+    }
+    None
 }
 
 impl fmt::Display for OrderBook {
@@ -205,11 +746,49 @@ impl fmt::Display for OrderBook {
     }
 }
 
+/// Best levels of each side, best first. Displays as `[B:qty@price,..|A:qty@price,..]`, so a
+/// log field holding it is only formatted when the event is enabled.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BookTop {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl fmt::Display for BookTop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let levels = |f: &mut Formatter<'_>, levels: &[Level]| {
+            for (i, level) in levels.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(",")?;
+                }
+                write!(f, "{}@{}", level.quantity, level.price)?;
+            }
+            Ok(())
+        };
+        f.write_str("[B:")?;
+        levels(f, &self.bids)?;
+        f.write_str("|A:")?;
+        levels(f, &self.asks)?;
+        f.write_str("]")
+    }
+}
+
+/// Sides of an order book that hold levels. Every analytic needing a missing side returns
+/// `None`, strategies skip the cycle and reports show the shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookShape {
+    Empty,
+    BidsOnly,
+    AsksOnly,
+    TwoSided,
+}
+
 /// Helper struct to construct OrderBook from binance snapshot
 #[derive(Deserialize)]
-struct DepthSnapshot {
-    #[serde(rename = "T", with = "chrono::serde::ts_milliseconds")]
-    xchg_ts: DateTime<Utc>,
+pub(crate) struct DepthSnapshot {
+    #[serde(rename = "T")]
+    xchg_ts: ExchangeTime,
     #[serde(rename = "lastUpdateId")]
     last_update_id: u64,
     #[serde(deserialize_with = "de_side")]
@@ -233,8 +812,25 @@ where
     Ok(side)
 }
 
+/// Quantity and amount an order filled since it stood at `prev_filled_qty` for
+/// `prev_avg_price`, from the cumulative figures of `update_event`. Partial fills with a missed
+/// update in between are caught up, an update older than the previous figures is zero.
+pub fn fill_delta(
+    update_event: &OrderTradeUpdateEvent,
+    prev_filled_qty: Decimal,
+    prev_avg_price: Decimal,
+) -> (Decimal, Decimal) {
+    let qty = update_event.filled_qty() - prev_filled_qty;
+    if qty <= Decimal::ZERO {
+        return (Decimal::ZERO, Decimal::ZERO);
+    }
+    let amount =
+        update_event.avg_price() * update_event.filled_qty() - prev_avg_price * prev_filled_qty;
+    (qty, amount)
+}
+
 /// PnL per symbol
-#[derive(Debug, Clone, Copy, Getters)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Getters)]
 pub struct ProfitAndLoss {
     #[getter(copy)]
     execution_pnl: Decimal, // WARN: in USDT, Commission??
@@ -251,6 +847,10 @@ pub struct ProfitAndLoss {
     buy_amount: Decimal,
     #[getter(copy)]
     sell_amount: Decimal,
+    #[getter(copy)]
+    total_fees: Decimal, // commission paid, positive
+    #[getter(copy)]
+    total_rebates: Decimal, // maker rebates received, positive
 }
 
 impl ProfitAndLoss {
@@ -266,25 +866,46 @@ impl ProfitAndLoss {
             sell_qty: ZERO,
             buy_amount: ZERO,
             sell_amount: ZERO,
+            total_fees: ZERO,
+            total_rebates: ZERO,
         }
     }
 
+    /// Fold the fill of a single update, by its own quantity and price
     pub fn on_update_received(&mut self, update_event: &OrderTradeUpdateEvent) {
+        self.on_fill(
+            update_event,
+            update_event.last_filled_qty(),
+            update_event.last_filled_amount(),
+        );
+    }
+
+    /// Fold `qty` for `amount` on the side of `update_event`, e.g. a [`fill_delta`], along
+    /// with its commission
+    pub fn on_fill(&mut self, update_event: &OrderTradeUpdateEvent, qty: Decimal, amount: Decimal) {
         // TODO: benchmark the time usage
         // This method should only be called when trade event received
-        self.execution_pnl -= update_event.commission();
-        let price = update_event.last_filled_price();
-        let qty = update_event.last_filled_qty();
-        let amount = update_event.last_filled_amount();
+        // Binance reports maker rebates as negative commission
+        let commission = update_event.commission();
+        if commission.is_sign_negative() {
+            self.total_rebates -= commission;
+        } else {
+            self.total_fees += commission;
+        }
+        self.execution_pnl -= commission;
 
-        match update_event.side() {
-            // handle realized pnl & position
-            Side::Buy => self.handle_buy(price, qty, amount),
-            Side::Sell => self.handle_sell(price, qty, amount),
+        if qty > Decimal::ZERO {
+            let price = amount / qty;
+            match update_event.side() {
+                // handle realized pnl & position
+                Side::Buy => self.handle_buy(price, qty, amount),
+                Side::Sell => self.handle_sell(price, qty, amount),
+            }
         }
 
         // update unrealized pnl
-        self.unrealized_pnl = (price - self.avg_entry_price) * self.position;
+        self.unrealized_pnl =
+            (update_event.last_filled_price() - self.avg_entry_price) * self.position;
     }
 
     fn handle_buy(&mut self, price: Decimal, qty: Decimal, amount: Decimal) {
@@ -310,8 +931,8 @@ impl ProfitAndLoss {
         self.sell_amount += amount;
 
         if old_pos <= Decimal::ZERO {
-            let total_cost = amount - self.avg_entry_price * self.position;
-            self.avg_entry_price = -total_cost / old_pos;
+            let total_cost = self.avg_entry_price * -old_pos + amount;
+            self.avg_entry_price = total_cost / -self.position;
         } else if qty <= old_pos {
             self.realized_pnl += (price - self.avg_entry_price) * qty;
         } else {
@@ -320,3 +941,591 @@ impl ProfitAndLoss {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TradingCoreError;
+    use rust_decimal::dec;
+
+    const CLIENT_ID: &str = "9b2c3f1e-8d4a-4b6e-a1f0-5c7d9e2b4a61";
+
+    fn make_order(time_in_force: TimeInForce, good_till_date: Option<u64>) -> Order {
+        Order::new(
+            Symbol::SOLUSDT,
+            Side::Buy,
+            OrderKind::Limit,
+            dec!(100.5),
+            dec!(2),
+            time_in_force,
+            good_till_date,
+        )
+        .with_client_order_id(CLIENT_ID.parse().unwrap())
+    }
+
+    fn make_fill(commission: &str, is_maker: bool) -> OrderTradeUpdateEvent {
+        let json = format!(
+            r#"{{
+                "e": "ORDER_TRADE_UPDATE", "E": 1568879465651, "T": 1568879465650,
+                "o": {{
+                    "s": "SOLUSDT", "c": "{CLIENT_ID}", "S": "BUY", "o": "LIMIT", "f": "GTC",
+                    "q": "2", "p": "100", "ap": "100", "x": "TRADE", "X": "PARTIALLY_FILLED",
+                    "i": 8886774, "l": "1", "z": "1", "L": "100", "n": "{commission}",
+                    "T": 1568879465650, "t": 42, "m": {is_maker}, "rp": "0"
+                }}
+            }}"#
+        );
+        serde_json::from_str(&json).expect("Failed to parse order update")
+    }
+
+    fn make_partial_fill(
+        order: &Order,
+        last_qty: &str,
+        last_price: &str,
+        filled_qty: &str,
+        avg_price: &str,
+    ) -> OrderTradeUpdateEvent {
+        let json = format!(
+            r#"{{
+                "e": "ORDER_TRADE_UPDATE", "E": 1568879465651, "T": 1568879465650,
+                "o": {{
+                    "s": "SOLUSDT", "c": "{}", "S": "BUY", "o": "LIMIT", "f": "GTC",
+                    "q": "3", "p": "100", "ap": "{avg_price}", "x": "TRADE", "X": "PARTIALLY_FILLED",
+                    "i": 8886774, "l": "{last_qty}", "z": "{filled_qty}", "L": "{last_price}",
+                    "n": "0", "T": 1568879465650, "t": 42, "m": true, "rp": "0"
+                }}
+            }}"#,
+            order.client_order_id()
+        );
+        serde_json::from_str(&json).expect("Failed to parse order update")
+    }
+
+    #[test]
+    fn test_partial_fills_are_averaged() {
+        let mut order = make_order(TimeInForce::GoodUntilCancel, None);
+        order.on_update_received(&make_partial_fill(&order, "1", "100", "1", "100"));
+        order.on_update_received(&make_partial_fill(&order, "2", "97", "3", "98"));
+
+        assert_eq!(*order.filled_qty(), dec!(3));
+        assert_eq!(*order.avg_fill_price(), dec!(98));
+        assert_eq!(*order.curr_qty(), dec!(2));
+
+        // a late update from before the second fill changes nothing
+        order.on_update_received(&make_partial_fill(&order, "1", "100", "1", "100"));
+        assert_eq!(*order.filled_qty(), dec!(3));
+        assert_eq!(*order.avg_fill_price(), dec!(98));
+    }
+
+    #[test]
+    fn test_cumulative_fills_survive_a_missed_partial() {
+        let order = make_order(TimeInForce::GoodUntilCancel, None);
+        let fills = [
+            make_partial_fill(&order, "1", "100", "1", "100"),
+            make_partial_fill(&order, "1", "97", "2", "98.5"),
+            make_partial_fill(&order, "1", "97", "3", "98"),
+        ];
+        let mut summed = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+        let mut summed_missing = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+        let mut cumulative = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+        for fill in &fills {
+            summed.on_update_received(fill);
+        }
+
+        // the second update never arrives
+        let mut prev = (Decimal::ZERO, Decimal::ZERO);
+        for fill in [&fills[0], &fills[2]] {
+            summed_missing.on_update_received(fill);
+            let (qty, amount) = fill_delta(fill, prev.0, prev.1);
+            cumulative.on_fill(fill, qty, amount);
+            prev = (fill.filled_qty(), fill.avg_price());
+        }
+
+        assert_eq!(summed.position(), dec!(3));
+        assert_eq!(summed.buy_amount(), dec!(294));
+        assert_eq!(summed_missing.position(), dec!(2));
+        assert_eq!(cumulative.position(), summed.position());
+        assert_eq!(cumulative.buy_amount(), summed.buy_amount());
+        assert_eq!(cumulative.avg_entry_price(), summed.avg_entry_price());
+        assert_eq!(*cumulative.avg_entry_price(), dec!(98));
+        // replayed, the last update adds nothing
+        assert_eq!(
+            fill_delta(&fills[1], dec!(3), dec!(98)),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_sell_from_flat_opens_short() {
+        let json = format!(
+            r#"{{
+                "e": "ORDER_TRADE_UPDATE", "E": 1568879465651, "T": 1568879465650,
+                "o": {{
+                    "s": "SOLUSDT", "c": "{CLIENT_ID}", "S": "SELL", "o": "LIMIT", "f": "GTC",
+                    "q": "2", "p": "100", "ap": "100", "x": "TRADE", "X": "PARTIALLY_FILLED",
+                    "i": 8886774, "l": "1", "z": "1", "L": "100", "n": "0",
+                    "T": 1568879465650, "t": 42, "m": true, "rp": "0"
+                }}
+            }}"#
+        );
+        let sell: OrderTradeUpdateEvent = serde_json::from_str(&json).unwrap();
+        let mut pnl = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+
+        pnl.on_update_received(&sell);
+        pnl.on_update_received(&sell);
+
+        assert_eq!(pnl.position(), dec!(-2));
+        assert_eq!(*pnl.avg_entry_price(), dec!(100));
+        assert_eq!(pnl.sell_amount(), dec!(200));
+    }
+
+    #[test]
+    fn test_taker_fee_decreases_execution_pnl() {
+        let mut pnl = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+        pnl.on_update_received(&make_fill("0.05", false));
+
+        assert_eq!(pnl.execution_pnl(), dec!(-0.05));
+        assert_eq!(pnl.total_fees(), dec!(0.05));
+        assert_eq!(pnl.total_rebates(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_maker_rebate_increases_execution_pnl() {
+        let mut pnl = ProfitAndLoss::new(Decimal::ZERO, Decimal::ZERO);
+        pnl.on_update_received(&make_fill("-0.01", true));
+
+        assert_eq!(pnl.execution_pnl(), dec!(0.01));
+        assert_eq!(pnl.total_fees(), Decimal::ZERO);
+        assert_eq!(pnl.total_rebates(), dec!(0.01));
+    }
+
+    #[test]
+    fn test_gtc_order_query() {
+        let query = serde_urlencoded::to_string(make_order(TimeInForce::GoodUntilCancel, None))
+            .expect("Failed to encode order");
+
+        assert_eq!(
+            query,
+            format!(
+                "symbol=SOLUSDT&side=BUY&newClientOrderId={CLIENT_ID}&type=LIMIT\
+                 &price=100.5&quantity=2&timeInForce=GTC&selfTradePreventionMode=EXPIRE_MAKER"
+            )
+        );
+        assert!(!query.contains("goodTillDate"));
+    }
+
+    #[test]
+    fn test_gtd_order_query() {
+        let order = make_order(TimeInForce::GoodUntilDate, Some(1_700_000_000_000));
+        let query = serde_urlencoded::to_string(order).expect("Failed to encode order");
+
+        assert_eq!(
+            query,
+            format!(
+                "symbol=SOLUSDT&side=BUY&newClientOrderId={CLIENT_ID}&type=LIMIT\
+                 &price=100.5&quantity=2&timeInForce=GTD&goodTillDate=1700000000000\
+                 &selfTradePreventionMode=EXPIRE_MAKER"
+            )
+        );
+    }
+
+    #[test]
+    fn test_stp_mode_query() {
+        let order = make_order(TimeInForce::GoodUntilCancel, None)
+            .with_stp_mode(Some(SelfTradePreventionMode::ExpireBoth));
+        let query = serde_urlencoded::to_string(order).expect("Failed to encode order");
+        assert!(query.ends_with("&selfTradePreventionMode=EXPIRE_BOTH"));
+
+        let order = make_order(TimeInForce::GoodUntilCancel, None).with_stp_mode(None);
+        let query = serde_urlencoded::to_string(order).expect("Failed to encode order");
+        assert!(!query.contains("selfTradePreventionMode"));
+    }
+
+    fn try_limit(price: Decimal, quantity: Decimal) -> TradingCoreResult<Order> {
+        Order::try_new(
+            Symbol::SOLUSDT,
+            Side::Buy,
+            OrderKind::Limit,
+            price,
+            quantity,
+            TimeInForce::GoodUntilCancel,
+            None,
+        )
+    }
+
+    fn assert_bad_definition(res: TradingCoreResult<Order>, expected: &str) {
+        match res {
+            Err(TradingCoreError::Data(DataError::BadDefinition { reason })) => {
+                assert_eq!(reason, expected)
+            }
+            other => panic!("expected a bad definition, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_order_snapshot_round_trips_every_field() {
+        let gtd = (LocalTime::now() + Duration::minutes(20)).timestamp_millis() as u64;
+        let mut order = make_order(TimeInForce::GoodUntilDate, Some(gtd))
+            .with_stp_mode(Some(SelfTradePreventionMode::ExpireBoth));
+        order.on_update_received(&make_fill("0.01", true));
+
+        let json = serde_json::to_string(&OrderSnapshot::from(&order)).unwrap();
+        let restored = Order::from(serde_json::from_str::<OrderSnapshot>(&json).unwrap());
+
+        // every field is in the snapshot, local times at millisecond precision
+        assert_eq!(
+            serde_json::to_value(OrderSnapshot::from(&restored)).unwrap(),
+            serde_json::to_value(OrderSnapshot::from(&order)).unwrap()
+        );
+        assert_eq!(restored.order_id(), &Some(8886774));
+        assert_eq!(restored.client_order_id(), order.client_order_id());
+        assert_eq!(restored.filled_qty(), &dec!(1));
+        assert_eq!(restored.avg_fill_price(), &dec!(100));
+        assert_eq!(restored.good_till_date(), &Some(gtd));
+        assert_eq!(restored.last_trade_id(), Some(42));
+        assert!(matches!(
+            restored.status(),
+            Some(OrderStatus::PartiallyFilled)
+        ));
+        assert!(matches!(
+            restored.stp_mode(),
+            Some(SelfTradePreventionMode::ExpireBoth)
+        ));
+        assert_eq!(
+            restored.start_ts().timestamp_millis(),
+            order.start_ts().timestamp_millis()
+        );
+    }
+
+    #[test]
+    fn test_gtd_minimum_window() {
+        let now = LocalTime::now();
+        let at = |ahead: Duration| Some((now + ahead).timestamp_millis() as u64);
+
+        let too_soon = make_order(TimeInForce::GoodUntilDate, at(Duration::minutes(5)));
+        match too_soon.check_time_in_force(now) {
+            Err(TradingCoreError::Data(DataError::BadDefinition { reason })) => {
+                assert_eq!(reason, "goodTillDate is less than 10 minutes ahead")
+            }
+            other => panic!("expected a bad definition, got {other:?}"),
+        }
+        let valid = make_order(TimeInForce::GoodUntilDate, at(Duration::minutes(20)));
+        assert!(valid.check_time_in_force(now).is_ok());
+        assert!(
+            make_order(TimeInForce::GoodUntilDate, None)
+                .check_time_in_force(now)
+                .is_err()
+        );
+        assert!(
+            make_order(TimeInForce::GoodUntilCancel, None)
+                .check_time_in_force(now)
+                .is_ok()
+        );
+
+        // generated dates are pushed out to the minimum, longer ones are kept
+        let clamped = good_till_date(now, Duration::seconds(30));
+        let clamped = make_order(TimeInForce::GoodUntilDate, Some(clamped));
+        assert!(clamped.check_time_in_force(now).is_ok());
+        assert_eq!(
+            good_till_date(now, Duration::hours(1)),
+            at(Duration::hours(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_try_new_validation() {
+        assert!(try_limit(dec!(100.5), dec!(2)).is_ok());
+
+        assert_bad_definition(try_limit(dec!(0), dec!(2)), "Order price must be positive");
+        assert_bad_definition(try_limit(dec!(-1), dec!(2)), "Order price must be positive");
+        assert_bad_definition(
+            try_limit(dec!(100.5), dec!(0)),
+            "Order quantity must be positive",
+        );
+        assert_bad_definition(
+            try_limit(dec!(100.5), dec!(-0.1)),
+            "Order quantity must be positive",
+        );
+
+        // market orders carry no price
+        let market = Order::try_new(
+            Symbol::SOLUSDT,
+            Side::Sell,
+            OrderKind::Market,
+            Decimal::ZERO,
+            dec!(1),
+            TimeInForce::GoodUntilCancel,
+            None,
+        );
+        assert!(market.is_ok());
+    }
+
+    fn make_depth(update_id: u64, bids: &str, asks: &str) -> Depth {
+        let json = format!(
+            r#"{{
+                "e": "depthUpdate", "E": 1571889248277, "T": 1571889248276, "s": "SOLUSDT",
+                "U": {update_id}, "u": {update_id}, "pu": {prev}, "b": {bids}, "a": {asks}
+            }}"#,
+            prev = update_id - 1
+        );
+        serde_json::from_str(&json).expect("Failed to parse depth")
+    }
+
+    fn make_chained_depth(first: u64, last: u64, prev_last: u64, bids: &str) -> Depth {
+        let json = format!(
+            r#"{{
+                "e": "depthUpdate", "E": 1571889248277, "T": 1571889248276, "s": "SOLUSDT",
+                "U": {first}, "u": {last}, "pu": {prev_last}, "b": {bids}, "a": []
+            }}"#
+        );
+        serde_json::from_str(&json).expect("Failed to parse depth")
+    }
+
+    fn make_book(last_update_id: u64) -> OrderBook {
+        let json = format!(
+            r#"{{
+                "lastUpdateId": {last_update_id}, "E": 1571889248277, "T": 1571889248268,
+                "bids": [["99", "1"]], "asks": [["101", "1"]]
+            }}"#
+        );
+        OrderBook::from_depth_snapshot(Symbol::SOLUSDT, serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn test_apply_depth_in_order() {
+        let mut ob = make_book(100);
+        // older than the snapshot, skipped
+        ob.apply_depth(&make_chained_depth(90, 95, 89, r#"[["98", "5"]]"#))
+            .unwrap();
+        assert_eq!(ob.last_update_id(), 100);
+        assert!(ob.bids().get(&dec!(98)).is_none());
+
+        // straddles the snapshot, then chains by pu
+        ob.apply_depth(&make_chained_depth(96, 105, 95, r#"[["98", "2"]]"#))
+            .unwrap();
+        ob.apply_depth(&make_chained_depth(106, 110, 105, r#"[["99", "0"]]"#))
+            .unwrap();
+        assert_eq!(ob.last_update_id(), 110);
+        assert_eq!(ob.bids().keys().collect::<Vec<_>>(), vec![&dec!(98)]);
+    }
+
+    #[test]
+    fn test_apply_depth_duplicate_is_a_gap() {
+        let mut ob = make_book(100);
+        let depth = make_chained_depth(96, 105, 95, r#"[["98", "2"]]"#);
+        ob.apply_depth(&depth).unwrap();
+
+        assert_eq!(
+            ob.apply_depth(&depth),
+            Err(DepthGapError {
+                expected: 105,
+                actual: 95
+            })
+        );
+        assert_eq!(ob.last_update_id(), 105);
+    }
+
+    #[test]
+    fn test_apply_depth_gap_leaves_book_untouched() {
+        // the first update after the snapshot starts past it
+        let mut ob = make_book(100);
+        assert_eq!(
+            ob.apply_depth(&make_chained_depth(102, 110, 101, r#"[["98", "2"]]"#)),
+            Err(DepthGapError {
+                expected: 100,
+                actual: 102
+            })
+        );
+        assert_eq!(ob.last_update_id(), 100);
+
+        // 106..=110 never arrived
+        let mut ob = make_book(100);
+        ob.apply_depth(&make_chained_depth(101, 105, 100, "[]"))
+            .unwrap();
+        let err = ob
+            .apply_depth(&make_chained_depth(111, 120, 110, r#"[["98", "2"]]"#))
+            .unwrap_err();
+        assert_eq!((err.expected, err.actual), (105, 110));
+        assert_eq!(ob.last_update_id(), 105);
+        assert!(ob.bids().get(&dec!(98)).is_none());
+    }
+
+    #[test]
+    fn test_top_cache_follows_updates() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(
+            1,
+            r#"[["99", "1"], ["98", "1"], ["97", "1"], ["96", "1"]]"#,
+            r#"[["101", "1"], ["102", "1"], ["103", "1"], ["104", "1"]]"#,
+        ));
+        ob.enable_top_cache(2);
+
+        let updates = [
+            // outside the window
+            (r#"[["95", "3"]]"#, r#"[["105", "3"]]"#),
+            // inside the window
+            (r#"[["99", "0"]]"#, r#"[["100.5", "2"]]"#),
+            // new best bid, window shifts
+            (r#"[["99.5", "4"]]"#, r#"[["100.5", "0"], ["101", "0"]]"#),
+        ];
+        for (i, (bids, asks)) in updates.into_iter().enumerate() {
+            ob.extend(&make_depth(i as u64 + 2, bids, asks));
+            let (top_bids, top_asks) = ob.top(2);
+            assert_eq!(ob.cached_top(), Some((&top_bids[..], &top_asks[..])));
+        }
+
+        let (bids, asks) = ob.cached_top().unwrap();
+        assert_eq!(bids[0].price, dec!(99.5));
+        assert_eq!(asks[0].price, dec!(102));
+    }
+
+    #[test]
+    fn test_side_notional() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(
+            1,
+            r#"[["99", "1"], ["98", "2"], ["97", "3"]]"#,
+            r#"[["101", "0.5"], ["102", "1"]]"#,
+        ));
+
+        assert_eq!(ob.side_notional(Side::Buy, 2), Some(dec!(295)));
+        assert_eq!(ob.side_notional(Side::Buy, 10), Some(dec!(586)));
+        assert_eq!(ob.side_notional(Side::Sell, 1), Some(dec!(50.5)));
+        assert_eq!(ob.side_notional(Side::Sell, 0), Some(Decimal::ZERO));
+        assert_eq!(
+            OrderBook::new(Symbol::SOLUSDT).side_notional(Side::Sell, 5),
+            None
+        );
+    }
+
+    #[test]
+    fn test_book_top_display() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(
+            1,
+            r#"[["99", "1"], ["98", "2"], ["97", "3"]]"#,
+            r#"[["101", "0.5"]]"#,
+        ));
+
+        let top = ob.book_top(2);
+        assert_eq!(top.bids.len(), 2);
+        assert_eq!(top.asks, vec![Level::from((dec!(101), dec!(0.5)))]);
+        assert_eq!(top.to_string(), "[B:1@99,2@98|A:0.5@101]");
+        assert_eq!(ob.show(2), top.to_string());
+    }
+
+    #[test]
+    fn test_analytics_on_empty_book() {
+        let ob = OrderBook::new(Symbol::SOLUSDT);
+
+        assert_eq!(ob.shape(), BookShape::Empty);
+        assert_eq!(ob.get_bbo(), None);
+        assert_eq!(ob.mid_price(), None);
+        assert_eq!(ob.side_notional(Side::Buy, 5), None);
+        assert_eq!(ob.vwap(Side::Buy, dec!(1)), None);
+        assert_eq!(ob.price_impact(Side::Sell, dec!(1)), None);
+        assert_eq!(ob.show(5), "[B:|A:]");
+    }
+
+    #[test]
+    fn test_analytics_on_one_sided_book() {
+        let mut bids_only = OrderBook::new(Symbol::SOLUSDT);
+        bids_only.extend(make_depth(1, r#"[["99", "1"]]"#, "[]"));
+
+        assert_eq!(bids_only.shape(), BookShape::BidsOnly);
+        assert_eq!(bids_only.get_bbo(), None);
+        assert_eq!(bids_only.mid_price(), None);
+        assert_eq!(bids_only.side_notional(Side::Buy, 5), Some(dec!(99)));
+        assert_eq!(bids_only.side_notional(Side::Sell, 5), None);
+        assert_eq!(bids_only.vwap(Side::Sell, dec!(1)), Some(dec!(99)));
+        assert_eq!(bids_only.price_impact(Side::Buy, dec!(1)), None);
+
+        let mut asks_only = OrderBook::new(Symbol::SOLUSDT);
+        asks_only.extend(make_depth(1, "[]", r#"[["101", "1"]]"#));
+
+        assert_eq!(asks_only.shape(), BookShape::AsksOnly);
+        assert_eq!(asks_only.get_bbo(), None);
+        assert_eq!(asks_only.side_notional(Side::Buy, 5), None);
+        assert_eq!(
+            asks_only.price_impact(Side::Buy, dec!(1)),
+            Some(Decimal::ZERO)
+        );
+        assert_eq!(asks_only.price_impact(Side::Sell, dec!(1)), None);
+    }
+
+    #[test]
+    fn test_price_impact() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(
+            1,
+            r#"[["80", "1"], ["76", "1"]]"#,
+            r#"[["100", "1"], ["101", "1"], ["104", "2"]]"#,
+        ));
+
+        assert_eq!(ob.price_impact(Side::Buy, dec!(0.5)), Some(Decimal::ZERO));
+        assert_eq!(ob.vwap(Side::Buy, dec!(2)), Some(dec!(100.5)));
+        assert_eq!(ob.price_impact(Side::Buy, dec!(2)), Some(dec!(50)));
+        assert_eq!(ob.price_impact(Side::Buy, dec!(4)), Some(dec!(225)));
+        assert_eq!(ob.price_impact(Side::Sell, dec!(2)), Some(dec!(250)));
+        assert_eq!(ob.price_impact(Side::Sell, dec!(2.5)), None);
+        assert_eq!(ob.price_impact(Side::Buy, Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn test_book_diff() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(
+            1,
+            r#"[["99", "1"], ["98", "2"]]"#,
+            r#"[["100", "1"], ["101", "3"]]"#,
+        ));
+        let previous = ob.clone();
+        // bids: 99.5 joins ahead of the touch, 98 shrinks; asks: 100 is taken, 101 grows
+        ob.extend(&make_depth(
+            2,
+            r#"[["99.5", "4"], ["98", "0.5"]]"#,
+            r#"[["100", "0"], ["101", "3.5"]]"#,
+        ));
+
+        let diff = ob.diff(&previous);
+        assert_eq!((diff.from_update_id, diff.to_update_id), (1, 2));
+        assert_eq!(
+            diff.bids,
+            SideDiff {
+                added_qty: dec!(4),
+                removed_qty: dec!(1.5),
+                new_levels: 1,
+                removed_levels: 0,
+                touch_move: Some(dec!(0.5)),
+            }
+        );
+        assert_eq!(diff.bids.net_qty(), dec!(2.5));
+        assert_eq!(
+            diff.asks,
+            SideDiff {
+                added_qty: dec!(0.5),
+                removed_qty: dec!(1),
+                new_levels: 0,
+                removed_levels: 1,
+                touch_move: Some(dec!(1)),
+            }
+        );
+        assert_eq!(
+            ob.diff(&ob.clone()).bids,
+            SideDiff {
+                touch_move: Some(Decimal::ZERO),
+                ..SideDiff::default()
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Order book invariant violated")]
+    fn test_crossed_update_trips_debug_assertion() {
+        let mut ob = OrderBook::new(Symbol::SOLUSDT);
+        ob.extend(&make_depth(1, r#"[["99", "1"]]"#, r#"[["100", "1"]]"#));
+        assert!(ob.is_healthy());
+
+        ob.extend(&make_depth(2, r#"[["100.5", "1"]]"#, "[]"));
+    }
+}
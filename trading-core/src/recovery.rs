@@ -0,0 +1,108 @@
+//! Warm restart after a crash. The trading states are written to disk on every report tick and
+//! on shutdown, and read back at startup instead of starting flat with no orders.
+
+use data::time::LocalTime;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::engine::{StateSnapshot, SymbolStates};
+use crate::error::Result as TradingCoreResult;
+use crate::report::write_atomic;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryFile {
+    /// Local time the states were exported, in milliseconds
+    pub written_at: i64,
+    pub states: Vec<StateSnapshot>,
+}
+
+impl RecoveryFile {
+    pub fn new(states: &SymbolStates) -> Self {
+        Self {
+            written_at: LocalTime::now().timestamp_millis(),
+            states: states.export(),
+        }
+    }
+
+    /// Replace the file at `path`, a crash mid-write leaves the previous one intact
+    pub fn write_atomic(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_vec(self)?;
+        write_atomic(path.as_ref(), &json)
+    }
+
+    /// `None` if there is no file at `path`, e.g. on the very first start
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Import the snapshots into `states`. They are as old as `written_at`, reconcile against
+    /// the exchange before quoting.
+    pub fn restore(self, states: &mut SymbolStates) -> TradingCoreResult<()> {
+        states.import(self.states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Order, ProfitAndLoss};
+    use data::order::{OrderKind, Side, Symbol, TimeInForce};
+    use rust_decimal::dec;
+    use uuid::Uuid;
+
+    fn make_order(side: Side) -> Order {
+        Order::new(
+            Symbol::SOLUSDT,
+            side,
+            OrderKind::Limit,
+            dec!(100),
+            dec!(2),
+            TimeInForce::GoodUntilCancel,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_states_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ceraunus-recovery-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        assert!(RecoveryFile::load(&path).unwrap().is_none());
+
+        let mut states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+        let state = states.get_mut(Symbol::SOLUSDT).unwrap();
+        let (resting, done) = (make_order(Side::Buy), make_order(Side::Sell));
+        state.register_order(resting);
+        state.register_order(done);
+        state.complete_order(done.client_order_id());
+        state.pnl = ProfitAndLoss::new(dec!(100), dec!(-1.5));
+        RecoveryFile::new(&states).write_atomic(&path).unwrap();
+
+        let mut restored = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+        let file = RecoveryFile::load(&path).unwrap().unwrap();
+        file.restore(&mut restored).unwrap();
+
+        let state = restored.get_mut(Symbol::SOLUSDT).unwrap();
+        let order = state.get_active_order(&resting.client_order_id()).unwrap();
+        assert_eq!(*order.orig_price(), dec!(100));
+        assert_eq!(
+            order.start_ts().timestamp_millis(),
+            resting.start_ts().timestamp_millis()
+        );
+        assert_eq!(state.active_order_count(), 1);
+        assert_eq!(state.get_position(), dec!(-1.5));
+        assert!(!state.register_order(done));
+        assert_eq!(
+            restored.get(Symbol::BTCUSDT).unwrap().active_order_count(),
+            0
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
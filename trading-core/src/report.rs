@@ -0,0 +1,334 @@
+//! Periodic session summary, also written to disk for external supervisors.
+
+use data::order::Symbol;
+use data::time::LocalTime;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use tracing::error;
+
+use crate::engine::State;
+use crate::models::{BookShape, BookTop, OrderBook};
+use crate::retry::RetryPolicy;
+
+/// Levels per side carried by [`SymbolReport::top`]
+const REPORT_BOOK_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FeedHealth {
+    /// Receiving events, false while the session is being restarted
+    pub up: bool,
+    /// Restarts since the last event received
+    pub restarts: u32,
+    /// Given up for good after running out of restarts
+    pub down: bool,
+    /// Frames that failed to parse since startup
+    pub parse_failures: u64,
+}
+
+impl FeedHealth {
+    pub fn from_restarts(restarts: u32) -> Self {
+        Self {
+            up: restarts == 0,
+            restarts,
+            down: false,
+            parse_failures: 0,
+        }
+    }
+
+    pub fn with_parse_failures(mut self, parse_failures: u64) -> Self {
+        self.parse_failures = parse_failures;
+        self
+    }
+}
+
+/// Restarts of one feed through an outage. With `max_attempts` set on the policy the feed is
+/// given up once they run out, rather than restarted forever against a dead endpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct FeedRestarts {
+    name: &'static str,
+    policy: RetryPolicy,
+    restarts: u32,
+    // local time the current outage started, `None` while the feed is up
+    outage_since: Option<LocalTime>,
+    down: bool,
+}
+
+impl FeedRestarts {
+    pub fn new(name: &'static str, policy: RetryPolicy) -> Self {
+        Self {
+            name,
+            policy,
+            restarts: 0,
+            outage_since: None,
+            down: false,
+        }
+    }
+
+    /// An event arrived, the outage if any is over
+    pub fn on_event(&mut self) {
+        self.restarts = 0;
+        self.outage_since = None;
+    }
+
+    /// The session closed at `now`, returns the delay before restarting it. `None` once the
+    /// restarts run out, the feed is down for good from then on.
+    pub fn on_closed(&mut self, now: LocalTime) -> Option<Duration> {
+        let since = *self.outage_since.get_or_insert(now);
+        if self.down || !self.policy.allows(self.restarts + 1) {
+            if !self.down {
+                self.down = true;
+                error!(
+                    feed = self.name,
+                    attempts = self.restarts,
+                    "{} feed DOWN after {} attempts over {} minutes",
+                    self.name,
+                    self.restarts,
+                    (now - since).num_minutes()
+                );
+            }
+            return None;
+        }
+        let delay = self.policy.delay(self.restarts);
+        self.restarts += 1;
+        Some(delay)
+    }
+
+    /// Restarts since the last event received, zero on the first close of an outage
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.down
+    }
+
+    pub fn health(&self) -> FeedHealth {
+        FeedHealth {
+            up: self.restarts == 0 && !self.down,
+            restarts: self.restarts,
+            down: self.down,
+            parse_failures: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolReport {
+    pub symbol: Symbol,
+    pub book_ready: bool,
+    /// `None` until the order book is built
+    pub book: Option<BookShape>,
+    /// Best levels of the order book, `None` until it is built
+    pub top: Option<BookTop>,
+    pub position: Decimal,
+    pub turnover: Decimal,
+    pub execution_pnl: Decimal,
+    pub fees: Decimal,
+    pub rebates: Decimal,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    /// Aggressor volume of the recent public trades
+    pub buy_flow: Decimal,
+    pub sell_flow: Decimal,
+    pub active_orders: usize,
+    /// Position and open orders placed outside the bot, not part of `position`
+    pub external_position: Decimal,
+    pub external_orders: usize,
+    /// IOC probes sent, and those that found anything to fill
+    pub probes_sent: u64,
+    pub probes_filled: u64,
+    /// Quoting stopped by a schedule window
+    pub scheduled_pause: bool,
+}
+
+impl From<&State> for SymbolReport {
+    fn from(state: &State) -> Self {
+        Self {
+            symbol: state.symbol,
+            book_ready: state.has_order_book(),
+            book: state.order_book.as_ref().map(OrderBook::shape),
+            top: state
+                .order_book
+                .as_ref()
+                .map(|ob| ob.book_top(REPORT_BOOK_DEPTH)),
+            position: state.get_position(),
+            turnover: state.turnover(),
+            execution_pnl: state.pnl.execution_pnl(),
+            fees: state.pnl.total_fees(),
+            rebates: state.pnl.total_rebates(),
+            realized_pnl: state.pnl.realized_pnl(),
+            unrealized_pnl: state.pnl.unrealized_pnl(),
+            buy_flow: state.trade_flow().buy_volume(),
+            sell_flow: state.trade_flow().sell_volume(),
+            active_orders: state.active_order_count(),
+            external_position: state.external_position(),
+            external_orders: state.external_order_count(),
+            probes_sent: state.probe_stats().sent(),
+            probes_filled: state.probe_stats().filled(),
+            scheduled_pause: state.is_schedule_paused(LocalTime::now()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    /// Local time the report was taken, in milliseconds
+    pub generated_at: i64,
+    pub trading: bool,
+    pub market_feed: FeedHealth,
+    /// `None` when not trading, there is no account feed then
+    pub account_feed: Option<FeedHealth>,
+    pub symbols: Vec<SymbolReport>,
+}
+
+impl SessionReport {
+    pub fn new<'a>(
+        states: impl IntoIterator<Item = &'a State>,
+        trading: bool,
+        market_feed: FeedHealth,
+        account_feed: Option<FeedHealth>,
+    ) -> Self {
+        Self {
+            generated_at: LocalTime::now().timestamp_millis(),
+            trading,
+            market_feed,
+            account_feed,
+            symbols: states.into_iter().map(SymbolReport::from).collect(),
+        }
+    }
+
+    /// Replace the file at `path` with this report, readers never see a partial file
+    pub fn write_atomic(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        write_atomic(path.as_ref(), &json)
+    }
+}
+
+/// Write to a sibling temporary file, then rename it over `path`. The rename is atomic as long
+/// as both are on the same filesystem, which the shared directory guarantees.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SymbolStates;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use uuid::Uuid;
+
+    fn make_report(n: usize) -> SessionReport {
+        let states = SymbolStates::new(&[Symbol::SOLUSDT, Symbol::BTCUSDT]);
+        let mut report =
+            SessionReport::new(states.iter(), true, FeedHealth::from_restarts(0), None);
+        // pad the report so that a torn write would be likely to show
+        let symbols = report.symbols.clone();
+        report.symbols = symbols.into_iter().cycle().take(n).collect();
+        report
+    }
+
+    #[test]
+    fn test_report_fields() {
+        let states = SymbolStates::new(&[Symbol::SOLUSDT]);
+        let report = SessionReport::new(
+            states.iter(),
+            false,
+            FeedHealth::from_restarts(2),
+            Some(FeedHealth::from_restarts(0)),
+        );
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["market_feed"]["up"], false);
+        assert_eq!(json["market_feed"]["restarts"], 2);
+        assert_eq!(json["account_feed"]["up"], true);
+        assert_eq!(json["symbols"][0]["symbol"], "SOLUSDT");
+        assert_eq!(json["symbols"][0]["book_ready"], false);
+        assert_eq!(json["symbols"][0]["book"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_feed_down_after_restarts_run_out() {
+        let policy =
+            RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(30)).with_max_attempts(3);
+        let mut feed = FeedRestarts::new("market", policy);
+        let now = LocalTime::now();
+
+        // a feed that comes back starts over
+        assert!(feed.on_closed(now).is_some());
+        feed.on_event();
+        assert_eq!(feed.restarts(), 0);
+
+        for attempt in 0..3 {
+            assert_eq!(feed.on_closed(now), Some(policy.backoff(attempt)));
+        }
+        assert!(!feed.is_down());
+        assert_eq!(feed.on_closed(now + chrono::Duration::minutes(2)), None);
+        assert!(feed.is_down());
+        assert_eq!(feed.on_closed(now + chrono::Duration::minutes(3)), None);
+
+        let health = feed.health();
+        assert!(!health.up);
+        assert!(health.down);
+        assert_eq!(health.restarts, 3);
+        let report = SessionReport::new(std::iter::empty::<&State>(), true, health, None);
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["market_feed"]["down"], true);
+    }
+
+    #[test]
+    fn test_report_shows_empty_book() {
+        let mut states = SymbolStates::new(&[Symbol::SOLUSDT]);
+        let state = states.get_mut(Symbol::SOLUSDT).unwrap();
+        state.apply_buffered(OrderBook::new(Symbol::SOLUSDT));
+        let report = SessionReport::new(states.iter(), true, FeedHealth::from_restarts(0), None);
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["symbols"][0]["book_ready"], true);
+        assert_eq!(json["symbols"][0]["book"], "empty");
+        assert_eq!(json["symbols"][0]["top"]["bids"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_atomic_write_is_never_partial() {
+        let dir = std::env::temp_dir().join(format!("ceraunus-report-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("health.json");
+        make_report(1).write_atomic(&path).unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (path, done) = (path.clone(), Arc::clone(&done));
+            std::thread::spawn(move || {
+                let mut reads = 0;
+                loop {
+                    let raw = fs::read(&path).expect("Report file went missing");
+                    serde_json::from_slice::<serde_json::Value>(&raw).expect("Partial report read");
+                    reads += 1;
+                    if done.load(Ordering::Relaxed) {
+                        return reads;
+                    }
+                }
+            })
+        };
+
+        for i in 0..200 {
+            make_report(1 + i % 50).write_atomic(&path).unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+
+        assert!(reader.join().unwrap() >= 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
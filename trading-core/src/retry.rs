@@ -0,0 +1,181 @@
+//! Exponential backoff with jitter, shared by feed restarts, snapshots and order requests.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Delay before the first retry, doubled for every following one
+    pub base: Duration,
+    pub max: Duration,
+    /// Fraction of each delay that is randomized away, in `[0, 1]`
+    pub jitter: f64,
+    /// Attempts including the first one, unlimited if `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl RetryPolicy {
+    pub const fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+
+    pub const fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub const fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Delay before retry number `retry` (zero-based), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.base
+            .saturating_mul(1u32 << retry.min(31))
+            .min(self.max)
+    }
+
+    /// Jittered delay before retry number `retry`, within `[backoff * (1 - jitter), backoff]`
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        // v4 uuids are random, no need to pull in a rng for this
+        let unit = Uuid::new_v4().as_u64_pair().0 as f64 / u64::MAX as f64;
+        backoff.mul_f64(1.0 - jitter * unit)
+    }
+
+    /// Whether attempt number `attempt` (one-based) may be made
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+}
+
+/// Run `op` until it succeeds, fails with an error `is_retriable` rejects, or the policy runs out
+/// of attempts. The last error is returned.
+pub async fn retry<T, E, F, Fut, P>(
+    policy: &RetryPolicy,
+    mut op: F,
+    is_retriable: P,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: Fn(&E) -> bool,
+    E: Display,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) if is_retriable(&err) && policy.allows(attempt + 1) => {
+                let delay = policy.delay(attempt - 1);
+                warn!(attempt, retry_in = ?delay, %err, "Retrying after failure");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    const POLICY: RetryPolicy =
+        RetryPolicy::new(Duration::from_millis(100), Duration::from_millis(250)).with_jitter(0.5);
+
+    #[test]
+    fn test_delay_bounds() {
+        for retry in 0..10 {
+            let backoff = POLICY.backoff(retry);
+            assert!(backoff <= POLICY.max);
+
+            let delay = POLICY.delay(retry);
+            assert!(delay <= backoff);
+            assert!(delay >= backoff.mul_f64(0.5));
+        }
+        assert_eq!(POLICY.backoff(0), Duration::from_millis(100));
+        assert_eq!(POLICY.backoff(1), Duration::from_millis(200));
+        assert_eq!(POLICY.backoff(2), Duration::from_millis(250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_until_success() {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let res = retry(
+            &POLICY,
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        Err("boom")
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            |_| true,
+        )
+        .await;
+
+        assert_eq!(res, Ok(3));
+        assert_eq!(attempts, 3);
+        // two retries: 100ms and 200ms, each jittered by up to half
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(150));
+        assert!(elapsed <= Duration::from_millis(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fatal_error_is_not_retried() {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let res: Result<(), &str> = retry(
+            &POLICY,
+            || {
+                attempts += 1;
+                async { Err("fatal") }
+            },
+            |err| *err != "fatal",
+        )
+        .await;
+
+        assert_eq!(res, Err("fatal"));
+        assert_eq!(attempts, 1);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_attempts() {
+        let policy = POLICY.with_max_attempts(4);
+        let mut attempts = 0;
+        let res: Result<(), &str> = retry(
+            &policy,
+            || {
+                attempts += 1;
+                async { Err("boom") }
+            },
+            |_| true,
+        )
+        .await;
+
+        assert_eq!(res, Err("boom"));
+        assert_eq!(attempts, 4);
+    }
+}
@@ -0,0 +1,134 @@
+//! Reading the trading state from outside the event loop.
+//!
+//! [`State`] is owned by the event loop and is never shared: it is `Send` but is not wrapped in a
+//! lock, so the loop never waits on a reader. Observers such as reports or a control API read
+//! immutable views that the loop publishes through a `watch` channel instead. Publishing swaps
+//! in a new `Arc`. A reader holds an old view for as long as it likes without blocking the next
+//! publish, and always sees a view taken at one point of the loop.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::engine::SymbolStates;
+use crate::report::SymbolReport;
+
+/// States of every symbol as of one publish
+#[derive(Debug, Clone, Default)]
+pub struct StateView {
+    /// Publishes so far, views with a higher number are newer
+    pub seq: u64,
+    pub symbols: Vec<SymbolReport>,
+}
+
+/// Write side, kept by the event loop
+#[derive(Debug)]
+pub struct StatePublisher {
+    tx: watch::Sender<Arc<StateView>>,
+}
+
+/// Read side, cheap to clone and `Send + Sync` for use from any task
+#[derive(Debug, Clone)]
+pub struct StateObserver {
+    rx: watch::Receiver<Arc<StateView>>,
+}
+
+pub fn state_channel() -> (StatePublisher, StateObserver) {
+    let (tx, rx) = watch::channel(Arc::new(StateView::default()));
+    (StatePublisher { tx }, StateObserver { rx })
+}
+
+impl StatePublisher {
+    /// Publish a view of `states`, replacing the previous one. Never waits on readers, and
+    /// works with no reader left.
+    pub fn publish(&self, states: &SymbolStates) {
+        let seq = self.tx.borrow().seq + 1;
+        let view = StateView {
+            seq,
+            symbols: states.iter().map(SymbolReport::from).collect(),
+        };
+        self.tx.send_replace(Arc::new(view));
+    }
+
+    pub fn subscribe(&self) -> StateObserver {
+        StateObserver {
+            rx: self.tx.subscribe(),
+        }
+    }
+}
+
+impl StateObserver {
+    /// Most recent view, empty until the first publish
+    pub fn latest(&self) -> Arc<StateView> {
+        Arc::clone(&self.rx.borrow())
+    }
+
+    /// Wait for a view newer than the last one returned, `None` once the publisher is gone
+    pub async fn next(&mut self) -> Option<Arc<StateView>> {
+        self.rx.changed().await.ok()?;
+        Some(Arc::clone(&self.rx.borrow_and_update()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::State;
+    use crate::models::Order;
+    use data::order::{OrderKind, Side, Symbol, TimeInForce};
+    use rust_decimal::dec;
+    use std::time::Duration;
+
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_thread_bounds() {
+        assert_send::<State>();
+        assert_send::<StatePublisher>();
+        assert_send_sync::<StateObserver>();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_reader_sees_consistent_views() {
+        let (publisher, mut observer) = state_channel();
+        let reader = tokio::spawn(async move {
+            let mut last_seq = 0;
+            while let Some(view) = observer.next().await {
+                // one order is registered before each publish
+                assert!(view.seq > last_seq);
+                assert_eq!(view.symbols[0].active_orders as u64, view.seq);
+                last_seq = view.seq;
+            }
+            last_seq
+        });
+
+        let writer = tokio::spawn(async move {
+            let mut states = SymbolStates::new(&[Symbol::SOLUSDT]);
+            for _ in 0..1000 {
+                let state = states.get_mut(Symbol::SOLUSDT).unwrap();
+                state.register_order(Order::new(
+                    Symbol::SOLUSDT,
+                    Side::Buy,
+                    OrderKind::Limit,
+                    dec!(100),
+                    dec!(1),
+                    TimeInForce::GoodUntilCancel,
+                    None,
+                ));
+                publisher.publish(&states);
+                tokio::task::yield_now().await;
+            }
+            // dropping the publisher ends the reader
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), writer)
+            .await
+            .expect("Writer blocked")
+            .unwrap();
+        let last_seq = tokio::time::timeout(Duration::from_secs(5), reader)
+            .await
+            .expect("Reader blocked")
+            .unwrap();
+        assert_eq!(last_seq, 1000);
+    }
+}
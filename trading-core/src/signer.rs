@@ -0,0 +1,123 @@
+use crate::error::{Result, TradingCoreError};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use data::config::KeyType;
+use ed25519_dalek::{Signer as _, SigningKey, pkcs8::DecodePrivateKey};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+/// Signs the payload of a request with the api secret, the scheme follows the key type
+#[derive(Clone)]
+pub enum Signer {
+    /// Hex encoded HMAC-SHA256 of the payload
+    HmacSha256(Hmac<Sha256>),
+    /// Base64 encoded Ed25519 signature of the payload
+    Ed25519(SigningKey),
+}
+
+impl Signer {
+    pub fn new(key_type: KeyType, secret: &str) -> Result<Self> {
+        match key_type {
+            KeyType::HmacSha256 => Ok(Self::hmac_sha256(secret)),
+            KeyType::Ed25519 => Self::ed25519(secret),
+        }
+    }
+
+    pub fn hmac_sha256(secret: &str) -> Self {
+        Self::HmacSha256(
+            Hmac::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length"),
+        )
+    }
+
+    /// Private key as a PKCS#8 PEM, or its base64 body alone so it fits on one csv line
+    pub fn ed25519(secret: &str) -> Result<Self> {
+        let secret = secret.trim();
+        let key = if secret.starts_with("-----BEGIN") {
+            SigningKey::from_pkcs8_pem(secret).map_err(invalid_key)?
+        } else {
+            let der = BASE64.decode(secret).map_err(invalid_key)?;
+            SigningKey::from_pkcs8_der(&der).map_err(invalid_key)?
+        };
+        Ok(Self::Ed25519(key))
+    }
+
+    /// Signature of `payload`, not yet url encoded
+    pub fn sign(&self, payload: &str) -> String {
+        match self {
+            Self::HmacSha256(mac) => {
+                let mut mac = mac.clone();
+                mac.update(payload.as_bytes());
+                hex::encode(mac.finalize().into_bytes())
+            }
+            Self::Ed25519(key) => BASE64.encode(key.sign(payload.as_bytes()).to_bytes()),
+        }
+    }
+}
+
+// keys never reach the logs
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HmacSha256(_) => f.write_str("Signer::HmacSha256(<redacted>)"),
+            Self::Ed25519(_) => f.write_str("Signer::Ed25519(<redacted>)"),
+        }
+    }
+}
+
+fn invalid_key(err: impl fmt::Display) -> TradingCoreError {
+    TradingCoreError::ClientInitialization(format!("invalid Ed25519 private key: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{
+        Signature, Verifier,
+        pkcs8::{EncodePrivateKey, LineEnding},
+    };
+
+    #[test]
+    fn test_hmac_signature_of_known_payload() {
+        // example from the Binance API documentation
+        let signer = Signer::new(
+            KeyType::HmacSha256,
+            "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j",
+        )
+        .unwrap();
+        let payload = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1\
+                       &recvWindow=5000&timestamp=1499827319559";
+
+        assert_eq!(
+            signer.sign(payload),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+    }
+
+    #[test]
+    fn test_ed25519_signature_of_known_payload() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let pem = key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let body: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let payload = "symbol=BTCUSDT&side=SELL&type=LIMIT&quantity=1&price=0.2\
+                       &timestamp=1668481559918&recvWindow=5000";
+
+        let from_pem = Signer::new(KeyType::Ed25519, &pem).unwrap();
+        let from_body = Signer::new(KeyType::Ed25519, &body).unwrap();
+        let signature = from_pem.sign(payload);
+
+        // Ed25519 is deterministic, 64 bytes in 88 base64 chars
+        assert_eq!(signature, from_body.sign(payload));
+        assert_eq!(signature.len(), 88);
+        let bytes: [u8; 64] = BASE64.decode(&signature).unwrap().try_into().unwrap();
+        assert!(
+            key.verifying_key()
+                .verify(payload.as_bytes(), &Signature::from_bytes(&bytes))
+                .is_ok()
+        );
+        assert!(Signer::new(KeyType::Ed25519, "not a key").is_err());
+        assert_eq!(format!("{from_pem:?}"), "Signer::Ed25519(<redacted>)");
+    }
+}
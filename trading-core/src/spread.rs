@@ -0,0 +1,158 @@
+//! Rolling history of the top of book, the input for spread and volatility regimes.
+
+use chrono::Duration;
+use data::time::ExchangeTime;
+use derive_getters::Getters;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpreadSample {
+    ts: ExchangeTime,
+    mid: Decimal,
+    spread: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Getters)]
+pub struct SpreadStats {
+    samples: usize,
+    #[getter(copy)]
+    avg_spread_bps: Decimal,
+    /// Square root of the summed squared log returns of the mid, not annualized
+    realized_volatility: f64,
+}
+
+/// Time-indexed ring buffer of `(ts, mid, spread)`, bounded both by age and by length
+#[derive(Debug, Clone)]
+pub struct SpreadHistory {
+    samples: VecDeque<SpreadSample>,
+    capacity: usize,
+    horizon: Duration,
+}
+
+impl SpreadHistory {
+    pub fn new(capacity: usize, horizon: Duration) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            horizon,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Record a top of book, only kept if the mid or the spread moved
+    pub fn push(&mut self, ts: ExchangeTime, bid: Decimal, ask: Decimal) {
+        let sample = SpreadSample {
+            ts,
+            mid: (bid + ask) / Decimal::TWO,
+            spread: ask - bid,
+        };
+        if self
+            .samples
+            .back()
+            .is_some_and(|last| last.mid == sample.mid && last.spread == sample.spread)
+        {
+            return;
+        }
+
+        while self
+            .samples
+            .front()
+            .is_some_and(|first| ts - first.ts > self.horizon)
+            || self.samples.len() >= self.capacity
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Statistics over the samples within `window` of the latest one
+    pub fn stats(&self, window: Duration) -> Option<SpreadStats> {
+        let latest = self.samples.back()?.ts;
+        let start = self
+            .samples
+            .partition_point(|sample| latest - sample.ts > window);
+        let recent = self.samples.range(start..);
+
+        let mut samples = 0;
+        let mut spread_bps = Decimal::ZERO;
+        let mut squared_returns = 0.0;
+        let mut prev_mid: Option<f64> = None;
+        for sample in recent {
+            samples += 1;
+            if !sample.mid.is_zero() {
+                spread_bps += sample.spread / sample.mid * Decimal::from(10_000);
+            }
+            let mid = sample.mid.to_f64()?;
+            if let Some(prev) = prev_mid {
+                squared_returns += (mid / prev).ln().powi(2);
+            }
+            prev_mid = Some(mid);
+        }
+
+        Some(SpreadStats {
+            samples,
+            avg_spread_bps: spread_bps / Decimal::from(samples),
+            realized_volatility: squared_returns.sqrt(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn ts(secs: i64) -> ExchangeTime {
+        ExchangeTime::from_millis((1_700_000_000 + secs) * 1_000).unwrap()
+    }
+
+    fn feed(history: &mut SpreadHistory, mids: &[Decimal]) {
+        for (i, mid) in mids.iter().enumerate() {
+            history.push(ts(i as i64), *mid - dec!(0.05), *mid + dec!(0.05));
+        }
+    }
+
+    #[test]
+    fn test_spread_stats_over_window() {
+        let mut history = SpreadHistory::new(1024, Duration::minutes(5));
+        feed(
+            &mut history,
+            &[dec!(50), dec!(100), dec!(101), dec!(100), dec!(101)],
+        );
+
+        // the first sample is 4s behind the latest, out of the window
+        let stats = history.stats(Duration::seconds(3)).unwrap();
+        assert_eq!(stats.samples(), 4);
+
+        let expected_vol = (3.0 * 1.01f64.ln().powi(2)).sqrt();
+        assert!((stats.realized_volatility() - expected_vol).abs() < 1e-12);
+
+        // 0.1 over mids of 100 and 101, in bps
+        let expected_bps = (dec!(10) + dec!(1000) / dec!(101)) / dec!(2);
+        assert!((stats.avg_spread_bps() - expected_bps).abs() < dec!(0.000001));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut history = SpreadHistory::new(3, Duration::seconds(10));
+        feed(&mut history, &[dec!(100), dec!(101), dec!(102), dec!(103)]);
+        assert_eq!(history.len(), 3);
+
+        // an unchanged top of book is not a new sample
+        history.push(ts(4), dec!(102.95), dec!(103.05));
+        assert_eq!(history.len(), 3);
+
+        // samples older than the horizon are dropped
+        history.push(ts(13), dec!(99.95), dec!(100.05));
+        assert_eq!(history.len(), 2);
+    }
+}
@@ -1,45 +1,894 @@
 use crate::engine::State;
-use crate::models::Order;
+use crate::models::{BboPair, Order, good_till_date};
+use chrono::Duration;
+use data::config::{BalanceSizingConfig, TickOffsetConfig, WideSpreadQuoting};
 use data::order::*;
-use rust_decimal::Decimal;
+use data::time::LocalTime;
+use rust_decimal::{Decimal, RoundingStrategy, dec};
 use smallvec::SmallVec;
+use std::iter;
+use tracing::{debug, warn};
+
+/// Inline room for two levels per side, deeper ladders spill to the heap
+pub type Quotes = SmallVec<[Order; 4]>;
+
+/// Quote quantity without balance sizing
+pub const FIXED_QUOTE_QTY: Decimal = Decimal::ONE;
+const BPS: Decimal = dec!(10000);
+
+/// Quantity whose margin at `sizing.leverage` is `sizing.fraction` of `balance`, rounded down to
+/// the quantity step. `None` if the balance cannot afford a single step.
+pub fn balance_quote_qty(
+    balance: Decimal,
+    sizing: &BalanceSizingConfig,
+    price: Decimal,
+) -> Option<Decimal> {
+    if price <= Decimal::ZERO {
+        return None;
+    }
+    let qty = (balance * sizing.fraction * sizing.leverage / price)
+        .round_dp_with_strategy(sizing.qty_scale, RoundingStrategy::ToZero);
+    (qty > Decimal::ZERO).then_some(qty)
+}
+
+/// Quantity worth `notional` in the quote asset at `price`, rounded down to `lot_size`. `None`
+/// if the notional cannot afford a single lot.
+pub fn notional_quote_qty(notional: Decimal, price: Decimal, lot_size: Decimal) -> Option<Decimal> {
+    if price <= Decimal::ZERO || lot_size <= Decimal::ZERO {
+        return None;
+    }
+    let qty = round_to_lot(notional / price, lot_size);
+    (qty > Decimal::ZERO).then_some(qty)
+}
+
+fn round_to_lot(qty: Decimal, lot_size: Decimal) -> Decimal {
+    (qty / lot_size).floor() * lot_size
+}
+
+/// Bid and ask quantities shifted towards the side that brings `excess` inventory back to zero:
+/// that side grows by `size_skew * excess` of `qty` and the other shrinks by as much, down to
+/// zero. Rounded down to `qty_scale` decimal places when known.
+pub fn skewed_quote_qty(
+    qty: Decimal,
+    excess: Decimal,
+    size_skew: Decimal,
+    qty_scale: Option<u32>,
+) -> (Decimal, Decimal) {
+    let skew = (size_skew * excess).clamp(-Decimal::ONE, Decimal::ONE);
+    let round = |qty: Decimal| match qty_scale {
+        Some(scale) => qty.round_dp_with_strategy(scale, RoundingStrategy::ToZero),
+        None => qty,
+    };
+    (
+        round(qty * (Decimal::ONE - skew)),
+        round(qty * (Decimal::ONE + skew)),
+    )
+}
+
+/// Snap a quote price onto the tick grid away from the touch: bids down, asks up
+pub fn round_passive(price: Decimal, tick_size: Decimal, side: Side) -> Decimal {
+    let ticks = price / tick_size;
+    let ticks = match side {
+        Side::Buy => ticks.floor(),
+        Side::Sell => ticks.ceil(),
+    };
+    ticks * tick_size
+}
+
+/// Everything besides the BBO the quote prices depend on, see [`quote_prices`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrategyParams<'a> {
+    /// Center to quote around instead of the mid
+    pub fair_value: Option<Decimal>,
+    /// Position away from the inventory target, positive above it
+    pub excess: Decimal,
+    /// Price shift in bps of the mid per unit of `excess`
+    pub mean_reversion_bps: Option<Decimal>,
+    /// Placement relative to the touch with the symbol's tick size, around the center if unset
+    pub tick_offset: Option<(&'a TickOffsetConfig, Decimal)>,
+    /// Wider spreads are quoted at this spread around the center
+    pub max_quote_spread_bps: Option<Decimal>,
+    /// Fixed half spread around the center instead of the market's
+    pub half_spread: Option<Decimal>,
+    /// Price shift per unit of `excess`
+    pub skew_per_unit: Decimal,
+}
+
+impl<'a> StrategyParams<'a> {
+    pub fn new(symbol: Symbol, state: &'a State) -> Self {
+        let strategy = &state.strategy;
+        Self {
+            fair_value: state.fair_value(),
+            excess: state.get_position() - strategy.inventory_target,
+            mean_reversion_bps: strategy.mean_reversion_bps,
+            tick_offset: strategy.tick_offset.as_ref().and_then(|offset| {
+                offset
+                    .tick_size(symbol)
+                    .map(|tick_size| (offset, tick_size))
+            }),
+            max_quote_spread_bps: strategy.max_quote_spread_bps,
+            half_spread: None,
+            skew_per_unit: Decimal::ZERO,
+        }
+    }
+
+    /// Widest spread quoted around `mid_price`, any if `None`
+    fn max_spread(&self, mid_price: Decimal) -> Option<Decimal> {
+        self.max_quote_spread_bps.map(|bps| mid_price * bps / BPS)
+    }
+
+    /// Whether the spread of `bbo` is past `max_quote_spread_bps`
+    pub fn is_too_wide(&self, (bid, ask): BboPair) -> bool {
+        let mid_price = (ask.price + bid.price) / Decimal::TWO;
+        self.max_spread(mid_price)
+            .is_some_and(|max_spread| ask.price - bid.price > max_spread)
+    }
+}
+
+/// Bid and ask prices quoted against the top of book `bbo`. A spread past the maximum is
+/// capped, abstaining instead is left to the caller.
+pub fn quote_prices((bid, ask): BboPair, params: &StrategyParams) -> (Decimal, Decimal) {
+    let spread = ask.price - bid.price;
+    let mid_price = (ask.price + bid.price) / Decimal::TWO;
+    let center = params.fair_value.unwrap_or(mid_price);
+    // inventory above the target is positive, the skew leans towards selling it
+    let price_skew = params
+        .mean_reversion_bps
+        .map_or(Decimal::ZERO, |bps| mid_price * bps * params.excess / BPS)
+        + params.skew_per_unit * params.excess;
+    let tick_offset = params
+        .tick_offset
+        .map(|(offset, tick_size)| (offset.ticks_for(spread, tick_size), tick_size));
+    // a market wider than the maximum is quoted tighter than it
+    let capped_spread = params
+        .max_spread(mid_price)
+        .filter(|max_spread| spread > *max_spread);
+    match (tick_offset, capped_spread) {
+        // the touch is too far to anchor to, around the center on the tick grid instead
+        (Some((_, tick_size)), Some(max_spread)) => (
+            round_passive(
+                center - price_skew - max_spread / Decimal::TWO,
+                tick_size,
+                Side::Buy,
+            ),
+            round_passive(
+                center - price_skew + max_spread / Decimal::TWO,
+                tick_size,
+                Side::Sell,
+            ),
+        ),
+        // anchored to the touch, one tick short of crossing at best
+        (Some((ticks, tick_size)), None) => {
+            let offset = tick_size * Decimal::from(ticks);
+            let bid_opx = (bid.price - offset - price_skew).min(ask.price - tick_size);
+            let ask_opx = (ask.price + offset - price_skew).max(bid.price + tick_size);
+            (
+                round_passive(bid_opx, tick_size, Side::Buy),
+                round_passive(ask_opx, tick_size, Side::Sell),
+            )
+        }
+        (None, capped_spread) => {
+            // a fixed spread is capped the same as the market's
+            let spread = match params.half_spread {
+                Some(half_spread) => params
+                    .max_spread(mid_price)
+                    .map_or(half_spread * Decimal::TWO, |max_spread| {
+                        (half_spread * Decimal::TWO).min(max_spread)
+                    }),
+                None => capped_spread.unwrap_or(spread),
+            };
+            (
+                center - price_skew - spread / Decimal::TWO,
+                center - price_skew + spread / Decimal::TWO,
+            )
+        }
+    }
+}
 
 pub trait Strategy {
-    fn generate_quotes(symbol: Symbol, state: &State) -> SmallVec<[Order; 2]>;
+    fn generate_quotes(&self, symbol: Symbol, state: &State) -> Quotes;
 }
 
-pub struct QuoteStrategy;
+/// Quotes around the center, by default at the market's spread with the configured sizing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuoteStrategy {
+    half_spread: Option<Decimal>,
+    skew_per_unit: Decimal,
+    size: Option<Decimal>,
+}
+
+impl QuoteStrategy {
+    /// Inventory-skewed quoting: `half_spread` either side of the center, shifted by
+    /// `skew_per_unit` per unit of inventory away from the target, for `size` unless the
+    /// balance or notional sizing applies
+    pub fn with_params(half_spread: Decimal, skew_per_unit: Decimal, size: Decimal) -> Self {
+        Self {
+            half_spread: Some(half_spread),
+            skew_per_unit,
+            size: Some(size),
+        }
+    }
+}
 
 impl Strategy for QuoteStrategy {
-    fn generate_quotes(symbol: Symbol, state: &State) -> SmallVec<[Order; 2]> {
-        if let Some((bid, ask)) = state.bbo_level {
-            let spread = ask.price - bid.price;
+    fn generate_quotes(&self, symbol: Symbol, state: &State) -> Quotes {
+        let now = LocalTime::now();
+        if state.is_bbo_stale(now) {
+            warn!(%symbol, "Book ticker is stale, skipping quotes");
+            return SmallVec::new();
+        }
+        if let Some((bid, ask)) = state.best_bid_ask() {
             let mid_price = (ask.price + bid.price) / Decimal::TWO;
-            let ask_opx = mid_price + spread / Decimal::TWO;
-            let bid_opx = mid_price - spread / Decimal::TWO;
-
-            SmallVec::from_slice(&[
-                Order::new(
-                    symbol,
-                    Side::Buy,
-                    OrderKind::Limit,
-                    bid_opx,
-                    Decimal::ONE,
-                    TimeInForce::GoodUntilCancel,
-                    None,
-                ),
-                Order::new(
-                    symbol,
-                    Side::Sell,
-                    OrderKind::Limit,
-                    ask_opx,
-                    Decimal::ONE,
-                    TimeInForce::GoodUntilCancel,
-                    None,
+            let params = StrategyParams {
+                half_spread: self.half_spread,
+                skew_per_unit: self.skew_per_unit,
+                ..StrategyParams::new(symbol, state)
+            };
+            if params.is_too_wide((bid, ask))
+                && state.strategy.wide_spread_quoting == WideSpreadQuoting::Abstain
+            {
+                debug!(%symbol, bid = %bid.price, ask = %ask.price, "Spread too wide, skipping quotes");
+                return SmallVec::new();
+            }
+            let (bid_opx, ask_opx) = quote_prices((bid, ask), &params);
+
+            let notional_sizing = state
+                .strategy
+                .notional_sizing
+                .as_ref()
+                .and_then(|sizing| sizing.sizing(symbol));
+            let qty = match (&state.strategy.balance_sizing, state.available_balance()) {
+                (Some(sizing), Some(balance)) => {
+                    match balance_quote_qty(balance, sizing, mid_price) {
+                        Some(qty) => qty,
+                        None => return SmallVec::new(),
+                    }
+                }
+                _ => match notional_sizing {
+                    Some((notional, lot_size)) => {
+                        match notional_quote_qty(notional, mid_price, lot_size) {
+                            Some(qty) => qty,
+                            None => return SmallVec::new(),
+                        }
+                    }
+                    None => self.size.unwrap_or(FIXED_QUOTE_QTY),
+                },
+            };
+
+            let qty = match state.scheduled_size_factor(now) {
+                Some(factor) if factor <= Decimal::ZERO => {
+                    debug!(%symbol, "Quoting paused by schedule");
+                    return SmallVec::new();
+                }
+                Some(factor) => {
+                    let qty = qty * factor;
+                    match (state.strategy.balance_sizing, notional_sizing) {
+                        (Some(sizing), _) => {
+                            qty.round_dp_with_strategy(sizing.qty_scale, RoundingStrategy::ToZero)
+                        }
+                        (None, Some((_, lot_size))) => round_to_lot(qty, lot_size),
+                        (None, None) => qty,
+                    }
+                }
+                None => qty,
+            };
+
+            let qty_scale = state
+                .strategy
+                .balance_sizing
+                .map(|sizing| sizing.qty_scale)
+                .or(notional_sizing.map(|(_, lot_size)| lot_size.normalize().scale()));
+            let (bid_qty, ask_qty) = match state.strategy.size_skew {
+                Some(size_skew) => skewed_quote_qty(qty, params.excess, size_skew, qty_scale),
+                None => (qty, qty),
+            };
+
+            let (time_in_force, gtd) = match state.strategy.quote_ttl_ms {
+                Some(ttl_ms) => (
+                    TimeInForce::GoodUntilDate,
+                    Some(good_till_date(now, Duration::milliseconds(ttl_ms as i64))),
                 ),
-            ])
+                None => (TimeInForce::GoodUntilCancel, None),
+            };
+
+            let (levels, level_step, size_decay) = state
+                .strategy
+                .quote_levels
+                .map_or((1, Decimal::ZERO, Decimal::ZERO), |layers| {
+                    (layers.levels, layers.level_step, layers.size_decay)
+                });
+            let size_factors = iter::successors(Some(Decimal::ONE), |factor| {
+                Some(factor * (Decimal::ONE - size_decay))
+            });
+            let quotes: Result<Quotes, _> = (0..levels)
+                .zip(size_factors)
+                .flat_map(|(level, factor)| {
+                    let step = level_step * Decimal::from(level);
+                    let level_qty = |qty: Decimal| match qty_scale {
+                        Some(scale) => {
+                            (qty * factor).round_dp_with_strategy(scale, RoundingStrategy::ToZero)
+                        }
+                        None => qty * factor,
+                    };
+                    [
+                        (Side::Buy, bid_opx - step, level_qty(bid_qty)),
+                        (Side::Sell, ask_opx + step, level_qty(ask_qty)),
+                    ]
+                })
+                // a fully skewed or decayed side is not quoted at all
+                .filter(|(_, _, qty)| !qty.is_zero())
+                .map(|(side, price, qty)| {
+                    Order::try_new(
+                        symbol,
+                        side,
+                        OrderKind::Limit,
+                        price,
+                        qty,
+                        time_in_force,
+                        gtd,
+                    )
+                    .map(|order| {
+                        order
+                            .with_client_order_id(state.client_ids.mint(symbol))
+                            .with_stp_mode(Some(state.strategy.stp_mode))
+                    })
+                })
+                .collect();
+            let mut quotes = match quotes {
+                Ok(quotes) => quotes,
+                Err(err) => {
+                    warn!(%symbol, %bid_opx, %ask_opx, %qty, %err, "Skipping invalid quotes");
+                    return SmallVec::new();
+                }
+            };
+            quotes.retain(|order| {
+                state.strategy.quote_side.allows(*order.side())
+                    && !state.is_cooling_down(*order.side(), now)
+            });
+            quotes
         } else {
             SmallVec::new()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_id::{ClientIdGenerator, ClientIdScheme};
+    use crate::models::ProfitAndLoss;
+    use data::{
+        binance::market::Level,
+        config::{
+            NotionalSizingConfig, PlacementPolicy, QuoteLevelsConfig, QuoteSide, QuoteWindowConfig,
+            SpreadPlacementConfig, TickOffsetConfig,
+        },
+    };
+    use rust_decimal::dec;
+
+    fn make_state(quote_side: QuoteSide) -> State {
+        let mut state = State::new(Symbol::SOLUSDT);
+        state.strategy.quote_side = quote_side;
+        state.bbo_level = Some((
+            Level::from((dec!(99.9), dec!(1))),
+            Level::from((dec!(100.1), dec!(1))),
+        ));
+        state
+    }
+
+    #[test]
+    fn test_quotes_centered_on_fair_value() {
+        let mut state = make_state(QuoteSide::Both);
+        state.set_fair_value(Some(dec!(101)));
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert_eq!(*quotes[0].orig_price(), dec!(100.9));
+        assert_eq!(*quotes[1].orig_price(), dec!(101.1));
+
+        state.set_fair_value(None);
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert_eq!(*quotes[0].orig_price(), dec!(99.9));
+        assert_eq!(*quotes[1].orig_price(), dec!(100.1));
+    }
+
+    #[test]
+    fn test_quote_both_sides() {
+        let quotes =
+            QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &make_state(QuoteSide::Both));
+
+        assert_eq!(quotes.len(), 2);
+        assert!(
+            quotes
+                .iter()
+                .all(|order| *order.stp_mode() == Some(SelfTradePreventionMode::ExpireMaker))
+        );
+    }
+
+    #[test]
+    fn test_quote_ask_only() {
+        let state = make_state(QuoteSide::AskOnly);
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert_eq!(quotes.len(), 1);
+        assert!(matches!(quotes[0].side(), Side::Sell));
+    }
+
+    fn make_tick_state(bid: Decimal, ask: Decimal, ticks: i64) -> State {
+        let mut state = make_state(QuoteSide::Both);
+        state.bbo_level = Some((Level::from((bid, dec!(1))), Level::from((ask, dec!(1)))));
+        state.strategy.tick_offset = Some(TickOffsetConfig {
+            ticks,
+            tick_sizes: [(Symbol::SOLUSDT, dec!(0.01))].into_iter().collect(),
+            placement: None,
+        });
+        state
+    }
+
+    fn make_placement_state(
+        bid: Decimal,
+        ask: Decimal,
+        wide: PlacementPolicy,
+        tight: PlacementPolicy,
+    ) -> State {
+        let mut state = make_tick_state(bid, ask, 0);
+        state.strategy.tick_offset.as_mut().unwrap().placement = Some(SpreadPlacementConfig {
+            wide_spread_ticks: 5,
+            wide,
+            tight,
+        });
+        state
+    }
+
+    fn quoted_prices(state: &State) -> (Decimal, Decimal) {
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, state);
+        (*quotes[0].orig_price(), *quotes[1].orig_price())
+    }
+
+    fn bbo(bid: Decimal, ask: Decimal) -> BboPair {
+        (Level::from((bid, dec!(1))), Level::from((ask, dec!(1))))
+    }
+
+    #[test]
+    fn test_quote_prices_from_bbo_and_params() {
+        let offset = TickOffsetConfig {
+            ticks: 1,
+            ..Default::default()
+        };
+        let cases = [
+            // plain mid ± half spread
+            (
+                bbo(dec!(99.9), dec!(100.1)),
+                StrategyParams::default(),
+                (dec!(99.9), dec!(100.1)),
+            ),
+            // around the fair value instead of the mid
+            (
+                bbo(dec!(99.9), dec!(100.1)),
+                StrategyParams {
+                    fair_value: Some(dec!(100.5)),
+                    ..Default::default()
+                },
+                (dec!(100.4), dec!(100.6)),
+            ),
+            // 2 long at 5 bps per unit shifts both quotes down by 0.1
+            (
+                bbo(dec!(99.9), dec!(100.1)),
+                StrategyParams {
+                    excess: dec!(2),
+                    mean_reversion_bps: Some(dec!(5)),
+                    ..Default::default()
+                },
+                (dec!(99.8), dec!(100.0)),
+            ),
+            // one tick behind the touch
+            (
+                bbo(dec!(99.95), dec!(100.05)),
+                StrategyParams {
+                    tick_offset: Some((&offset, dec!(0.01))),
+                    ..Default::default()
+                },
+                (dec!(99.94), dec!(100.06)),
+            ),
+            // 100 bps market capped at 10 bps around the mid
+            (
+                bbo(dec!(99.5), dec!(100.5)),
+                StrategyParams {
+                    max_quote_spread_bps: Some(dec!(10)),
+                    ..Default::default()
+                },
+                (dec!(99.95), dec!(100.05)),
+            ),
+        ];
+        for (bbo, params, expected) in cases {
+            assert_eq!(quote_prices(bbo, &params), expected, "{params:?}");
+        }
+        assert!(
+            StrategyParams {
+                max_quote_spread_bps: Some(dec!(10)),
+                ..Default::default()
+            }
+            .is_too_wide(bbo(dec!(99.5), dec!(100.5)))
+        );
+        assert!(!StrategyParams::default().is_too_wide(bbo(dec!(99.5), dec!(100.5))));
+    }
+
+    #[test]
+    fn test_wide_spread_capped_around_mid() {
+        // 100 bps wide, capped at 20
+        let mut state = make_state(QuoteSide::Both);
+        state.bbo_level = Some((
+            Level::from((dec!(99.5), dec!(1))),
+            Level::from((dec!(100.5), dec!(1))),
+        ));
+        state.strategy.max_quote_spread_bps = Some(dec!(20));
+        assert_eq!(quoted_prices(&state), (dec!(99.9), dec!(100.1)));
+
+        // on the tick grid, instead of anchored to the far touch
+        let mut ticked = make_tick_state(dec!(99.5), dec!(100.5), 0);
+        ticked.strategy.max_quote_spread_bps = Some(dec!(25));
+        assert_eq!(quoted_prices(&ticked), (dec!(99.87), dec!(100.13)));
+
+        // narrower markets are quoted as they are
+        let mut narrow = make_state(QuoteSide::Both);
+        narrow.strategy.max_quote_spread_bps = Some(dec!(20));
+        assert_eq!(quoted_prices(&narrow), (dec!(99.9), dec!(100.1)));
+    }
+
+    #[test]
+    fn test_wide_spread_abstains() {
+        let mut state = make_tick_state(dec!(99.5), dec!(100.5), 0);
+        state.strategy.max_quote_spread_bps = Some(dec!(50));
+        state.strategy.wide_spread_quoting = WideSpreadQuoting::Abstain;
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+
+        state.strategy.max_quote_spread_bps = Some(dec!(100));
+        assert_eq!(quoted_prices(&state), (dec!(99.5), dec!(100.5)));
+    }
+
+    #[test]
+    fn test_tick_offset_placement() {
+        let join = make_tick_state(dec!(99.95), dec!(100.05), 0);
+        assert_eq!(quoted_prices(&join), (dec!(99.95), dec!(100.05)));
+
+        let back_off = make_tick_state(dec!(99.95), dec!(100.05), 2);
+        assert_eq!(quoted_prices(&back_off), (dec!(99.93), dec!(100.07)));
+
+        let improve = make_tick_state(dec!(99.95), dec!(100.05), -3);
+        assert_eq!(quoted_prices(&improve), (dec!(99.98), dec!(100.02)));
+
+        // no room to improve on a one-tick market
+        let tight = make_tick_state(dec!(100.00), dec!(100.01), -1);
+        assert_eq!(quoted_prices(&tight), (dec!(100.00), dec!(100.01)));
+
+        // unknown tick size falls back to quoting around the mid
+        let mut other = make_tick_state(dec!(99.95), dec!(100.05), 2);
+        other
+            .strategy
+            .tick_offset
+            .as_mut()
+            .unwrap()
+            .tick_sizes
+            .clear();
+        assert_eq!(quoted_prices(&other), (dec!(99.95), dec!(100.05)));
+    }
+
+    #[test]
+    fn test_placement_policy_by_spread() {
+        use PlacementPolicy::*;
+        // (policy, wide book quotes, tight book quotes), books at 99.95/100.05 and 100.00/100.03
+        let cases = [
+            (
+                Join,
+                (dec!(99.95), dec!(100.05)),
+                (dec!(100.00), dec!(100.03)),
+            ),
+            (
+                ImproveByTicks(1),
+                (dec!(99.96), dec!(100.04)),
+                (dec!(100.01), dec!(100.02)),
+            ),
+            (
+                StepBackTicks(1),
+                (dec!(99.94), dec!(100.06)),
+                (dec!(99.99), dec!(100.04)),
+            ),
+        ];
+        for (policy, wide_quotes, tight_quotes) in cases {
+            // the other regime is set to something else, only the matching one applies
+            let wide = make_placement_state(dec!(99.95), dec!(100.05), policy, StepBackTicks(9));
+            assert_eq!(
+                quoted_prices(&wide),
+                wide_quotes,
+                "{policy:?} on a wide book"
+            );
+
+            let tight = make_placement_state(dec!(100.00), dec!(100.03), StepBackTicks(9), policy);
+            assert_eq!(
+                quoted_prices(&tight),
+                tight_quotes,
+                "{policy:?} on a tight book"
+            );
+        }
+
+        // improving never crosses the other side's touch
+        let one_tick = make_placement_state(dec!(100.00), dec!(100.01), Join, ImproveByTicks(1));
+        assert_eq!(quoted_prices(&one_tick), (dec!(100.00), dec!(100.01)));
+    }
+
+    #[test]
+    fn test_quote_ttl_is_clamped_to_gtd_minimum() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.quote_ttl_ms = Some(1_000);
+        let now = LocalTime::now();
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert_eq!(quotes.len(), 2);
+        for quote in &quotes {
+            assert!(matches!(quote.time_in_force(), TimeInForce::GoodUntilDate));
+            assert!(quote.check_time_in_force(now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_round_passive() {
+        assert_eq!(
+            round_passive(dec!(100.014), dec!(0.01), Side::Buy),
+            dec!(100.01)
+        );
+        assert_eq!(
+            round_passive(dec!(100.014), dec!(0.01), Side::Sell),
+            dec!(100.02)
+        );
+        assert_eq!(
+            round_passive(dec!(100.01), dec!(0.01), Side::Sell),
+            dec!(100.01)
+        );
+        assert_eq!(round_passive(dec!(1.3), dec!(0.5), Side::Buy), dec!(1.0));
+    }
+
+    #[test]
+    fn test_long_position_skews_size_to_ask() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.size_skew = Some(dec!(0.1));
+        state.pnl = ProfitAndLoss::new(dec!(100), dec!(2));
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        let (bid_qty, ask_qty) = (*quotes[0].orig_qty(), *quotes[1].orig_qty());
+        assert_eq!(bid_qty, dec!(0.8));
+        assert_eq!(ask_qty, dec!(1.2));
+        // 2 * size_skew * position of the base quantity
+        assert_eq!(ask_qty - bid_qty, dec!(0.4));
+    }
+
+    #[test]
+    fn test_below_target_skews_to_buy() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.size_skew = Some(dec!(0.1));
+        state.strategy.mean_reversion_bps = Some(dec!(1));
+        state.strategy.inventory_target = dec!(3);
+        state.pnl = ProfitAndLoss::new(dec!(100), dec!(1));
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        // 2 below target: sizes lean to the bid and both prices move up by 2 bps of the mid
+        assert_eq!(*quotes[0].orig_qty(), dec!(1.2));
+        assert_eq!(*quotes[1].orig_qty(), dec!(0.8));
+        assert_eq!(*quotes[0].orig_price(), dec!(99.92));
+        assert_eq!(*quotes[1].orig_price(), dec!(100.12));
+    }
+
+    #[test]
+    fn test_above_target_skews_to_sell() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.size_skew = Some(dec!(0.1));
+        state.strategy.mean_reversion_bps = Some(dec!(1));
+        state.strategy.inventory_target = dec!(-1);
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        // flat is 1 above a short target
+        assert_eq!(*quotes[0].orig_qty(), dec!(0.9));
+        assert_eq!(*quotes[1].orig_qty(), dec!(1.1));
+        assert_eq!(*quotes[0].orig_price(), dec!(99.89));
+        assert_eq!(*quotes[1].orig_price(), dec!(100.09));
+    }
+
+    #[test]
+    fn test_seeded_quotes_are_reproducible() {
+        let run = || {
+            let mut state = make_state(QuoteSide::Both);
+            state.client_ids = ClientIdGenerator::new(ClientIdScheme::Seeded { seed: 42 });
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .iter()
+                .map(Order::client_order_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run().len(), 2);
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_scheduled_window_suppresses_quotes() {
+        let mut state = make_state(QuoteSide::Both);
+        let now = LocalTime::now().as_datetime().time();
+        let mut window = QuoteWindowConfig {
+            start: now - chrono::Duration::minutes(5),
+            end: now + chrono::Duration::minutes(5),
+            size_factor: dec!(0.5),
+        };
+        state.strategy.schedule = vec![window];
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+        assert_eq!(*quotes[0].orig_qty(), dec!(0.5));
+
+        window.size_factor = dec!(0);
+        state.strategy.schedule = vec![window];
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_skewed_quote_qty() {
+        assert_eq!(
+            skewed_quote_qty(dec!(1), dec!(-3), dec!(0.1), None),
+            (dec!(1.3), dec!(0.7))
+        );
+        assert_eq!(
+            skewed_quote_qty(dec!(1), dec!(2), dec!(0.1), Some(0)),
+            (dec!(0), dec!(1))
+        );
+        // the shrinking side bottoms out at zero
+        assert_eq!(
+            skewed_quote_qty(dec!(1), dec!(20), dec!(0.1), None),
+            (dec!(0), dec!(2))
+        );
+    }
+
+    fn make_sizing(fraction: Decimal, leverage: Decimal) -> BalanceSizingConfig {
+        BalanceSizingConfig {
+            fraction,
+            leverage,
+            qty_scale: 2,
+        }
+    }
+
+    #[test]
+    fn test_quote_qty_from_balance_and_leverage() {
+        let sizing = make_sizing(dec!(0.1), dec!(5));
+
+        assert_eq!(
+            balance_quote_qty(dec!(1000), &sizing, dec!(100)),
+            Some(dec!(5))
+        );
+        // 1000 * 0.1 * 5 / 150 = 3.333.., rounded down to the step
+        assert_eq!(
+            balance_quote_qty(dec!(1000), &sizing, dec!(150)),
+            Some(dec!(3.33))
+        );
+    }
+
+    #[test]
+    fn test_quote_qty_below_step_is_none() {
+        let sizing = make_sizing(dec!(0.1), dec!(1));
+
+        assert_eq!(
+            balance_quote_qty(dec!(10), &sizing, dec!(100)),
+            Some(dec!(0.01))
+        );
+        assert_eq!(balance_quote_qty(dec!(1), &sizing, dec!(100)), None);
+    }
+
+    #[test]
+    fn test_notional_converted_to_lots() {
+        // 500 / 142.37 = 3.5119.., rounded down to the lot
+        assert_eq!(
+            notional_quote_qty(dec!(500), dec!(142.37), dec!(0.01)),
+            Some(dec!(3.51))
+        );
+        assert_eq!(
+            notional_quote_qty(dec!(500), dec!(60000), dec!(0.001)),
+            Some(dec!(0.008))
+        );
+        assert_eq!(notional_quote_qty(dec!(500), dec!(142.37), dec!(5)), None);
+
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.notional_sizing = Some(NotionalSizingConfig {
+            notionals: [(Symbol::SOLUSDT, dec!(250))].into(),
+            lot_sizes: [(Symbol::SOLUSDT, dec!(0.1))].into(),
+        });
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert_eq!(Symbol::SOLUSDT.quote_asset(), Asset::USDT);
+        assert_eq!(quotes.len(), 2);
+        // mid 100
+        assert!(quotes.iter().all(|order| *order.orig_qty() == dec!(2.5)));
+        // other symbols keep the fixed quantity
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::BTCUSDT, &state);
+        assert!(
+            quotes
+                .iter()
+                .all(|order| *order.orig_qty() == FIXED_QUOTE_QTY)
+        );
+    }
+
+    #[test]
+    fn test_fixed_qty_until_balance_known() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.balance_sizing = Some(make_sizing(dec!(0.1), dec!(5)));
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        assert!(
+            quotes
+                .iter()
+                .all(|order| *order.orig_qty() == FIXED_QUOTE_QTY)
+        );
+    }
+
+    #[test]
+    fn test_inventory_skew_shifts_quotes_against_position() {
+        let strategy = QuoteStrategy::with_params(dec!(0.5), dec!(0.25), dec!(3));
+        let prices = |position: Decimal| {
+            let mut state = make_state(QuoteSide::Both);
+            state.pnl = ProfitAndLoss::new(dec!(100), position);
+            let quotes = strategy.generate_quotes(Symbol::SOLUSDT, &state);
+            assert!(quotes.iter().all(|order| *order.orig_qty() == dec!(3)));
+            (*quotes[0].orig_price(), *quotes[1].orig_price())
+        };
+
+        // flat: the fixed half spread around the mid, not the market's
+        assert_eq!(prices(Decimal::ZERO), (dec!(99.5), dec!(100.5)));
+        // long 2: both quotes 0.5 lower to sell it down
+        assert_eq!(prices(dec!(2)), (dec!(99.0), dec!(100.0)));
+        // short 2: both quotes 0.5 higher to buy it back
+        assert_eq!(prices(dec!(-2)), (dec!(100.0), dec!(101.0)));
+    }
+
+    #[test]
+    fn test_levels_step_away_with_decaying_size() {
+        let mut state = make_state(QuoteSide::Both);
+        state.strategy.quote_levels = Some(QuoteLevelsConfig {
+            levels: 3,
+            level_step: dec!(0.1),
+            size_decay: dec!(0.5),
+        });
+        let quotes = QuoteStrategy::default().generate_quotes(Symbol::SOLUSDT, &state);
+
+        let ladder: Vec<_> = quotes
+            .iter()
+            .map(|order| (*order.orig_price(), *order.orig_qty()))
+            .collect();
+        assert_eq!(
+            ladder,
+            vec![
+                (dec!(99.9), dec!(1)),
+                (dec!(100.1), dec!(1)),
+                (dec!(99.8), dec!(0.5)),
+                (dec!(100.2), dec!(0.5)),
+                (dec!(99.7), dec!(0.25)),
+                (dec!(100.3), dec!(0.25)),
+            ]
+        );
+        let mut client_ids: Vec<_> = quotes.iter().map(Order::client_order_id).collect();
+        client_ids.sort();
+        client_ids.dedup();
+        assert_eq!(client_ids.len(), 6);
+
+        state.strategy.quote_levels = Some(QuoteLevelsConfig {
+            levels: 0,
+            level_step: dec!(0.1),
+            size_decay: Decimal::ZERO,
+        });
+        assert!(
+            QuoteStrategy::default()
+                .generate_quotes(Symbol::SOLUSDT, &state)
+                .is_empty()
+        );
+    }
+}
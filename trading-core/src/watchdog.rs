@@ -0,0 +1,118 @@
+//! Wall time of each event loop iteration. Handlers run inline on the loop, one blocking
+//! handler delays every event queued behind it, market data included.
+
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Source of the current instant, swapped out in tests
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug)]
+pub struct LoopWatchdog<C = MonotonicClock> {
+    clock: C,
+    threshold: Duration,
+    slow_iterations: u64,
+}
+
+impl LoopWatchdog {
+    pub fn new(threshold: Duration) -> Self {
+        Self::with_clock(threshold, MonotonicClock)
+    }
+}
+
+impl<C: Clock> LoopWatchdog<C> {
+    pub fn with_clock(threshold: Duration, clock: C) -> Self {
+        Self {
+            clock,
+            threshold,
+            slow_iterations: 0,
+        }
+    }
+
+    /// Time the iteration handling `event` until the returned guard drops, which covers early
+    /// exits like `continue` as well
+    pub fn start(&mut self, event: &'static str) -> IterationGuard<'_, C> {
+        let started = self.clock.now();
+        IterationGuard {
+            watchdog: self,
+            event,
+            started,
+        }
+    }
+
+    /// Iterations that took longer than the threshold so far
+    pub fn slow_iterations(&self) -> u64 {
+        self.slow_iterations
+    }
+}
+
+pub struct IterationGuard<'a, C: Clock> {
+    watchdog: &'a mut LoopWatchdog<C>,
+    event: &'static str,
+    started: Instant,
+}
+
+impl<C: Clock> Drop for IterationGuard<'_, C> {
+    fn drop(&mut self) {
+        let elapsed = self
+            .watchdog
+            .clock
+            .now()
+            .saturating_duration_since(self.started);
+        if elapsed > self.watchdog.threshold {
+            self.watchdog.slow_iterations += 1;
+            warn!(
+                event = self.event,
+                ?elapsed,
+                threshold = ?self.watchdog.threshold,
+                "Slow event loop iteration"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct ManualClock(Rc<Cell<Instant>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn test_slow_handler_is_flagged() {
+        let clock = ManualClock(Rc::new(Cell::new(Instant::now())));
+        let mut watchdog = LoopWatchdog::with_clock(Duration::from_millis(50), clock.clone());
+
+        for handler_time in [10, 50, 120, 5] {
+            let _guard = watchdog.start("ReportStateTick");
+            clock.advance(Duration::from_millis(handler_time));
+        }
+
+        assert_eq!(watchdog.slow_iterations(), 1);
+    }
+}